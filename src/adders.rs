@@ -106,6 +106,13 @@ pub(crate) mod adders {
     }
     impl AddSub16bit {
         pub fn value(&self, inp0 : [bool; 16], inp1 : [bool; 16], cin : bool, add : bool) -> ([bool; 16], bool) {
+            let (return16, carries) = self.value_split(inp0, inp1, cin, add, false);
+            (return16, carries[1])
+        }
+
+        pub fn value_split(&self, inp0 : [bool; 16], inp1 : [bool; 16], cin : bool, add : bool, split : bool) -> ([bool; 16], [bool; 2]) {
+            // With split set the carry out of the low byte isn't passed on, so the two bytes are independent 8-bit lanes
+            // Returns the carry (or borrow) out of each byte
 
             let mut nibbles_0 = [[false; 4]; 4];
             let mut nibbles_1 = [[false; 4]; 4];
@@ -125,7 +132,8 @@ pub(crate) mod adders {
 
             (nibbles_return[0], carries[0]) = self.add_sub_4bit_0.value(nibbles_0[0], nibbles_1[0], cin, add);
             (nibbles_return[1], carries[1]) = self.add_sub_4bit_1.value(nibbles_0[1], nibbles_1[1], carries[0], add);
-            (nibbles_return[2], carries[2]) = self.add_sub_4bit_2.value(nibbles_0[2], nibbles_1[2], carries[1], add);
+            let byte_cin = if split { false } else { carries[1] };
+            (nibbles_return[2], carries[2]) = self.add_sub_4bit_2.value(nibbles_0[2], nibbles_1[2], byte_cin, add);
             (nibbles_return[3], carries[3]) = self.add_sub_4bit_3.value(nibbles_0[3], nibbles_1[3], carries[2], add);
 
             let mut return16 = [false; 16];
@@ -134,7 +142,7 @@ pub(crate) mod adders {
             return16[4..8].copy_from_slice(&nibbles_return[1][0..4]);
             return16[8..12].copy_from_slice(&nibbles_return[2][0..4]);
             return16[12..16].copy_from_slice(&nibbles_return[3][0..4]);
-            (return16, carries[3])
+            (return16, [carries[1], carries[3]])
         }
     }
     impl Default for AddSub16bit {
@@ -158,6 +166,11 @@ pub(crate) mod adders {
     }
     impl AddSub64bit {
         pub fn value(&self, inp0 : [bool; 64], inp1 : [bool; 64], add : bool) -> ([bool; 64], bool) {
+            self.value_with_carry(inp0, inp1, false, add)
+        }
+
+        pub fn value_with_carry(&self, inp0 : [bool; 64], inp1 : [bool; 64], cin : bool, add : bool) -> ([bool; 64], bool) {
+            // Carry-in to the lowest bit - a borrow-in when subtracting, as the carry-out is a borrow-out
 
             let mut bit16s_0 = [[false; 16]; 4];
             let mut  bit16s_1 = [[false; 16]; 4];
@@ -175,7 +188,7 @@ pub(crate) mod adders {
             let mut bit16s_return = [[false; 16]; 4];
             let mut carries = [false; 4];
 
-            (bit16s_return[0], carries[0]) = self.add_sub_16bit_0.value(bit16s_0[0], bit16s_1[0], false, add);
+            (bit16s_return[0], carries[0]) = self.add_sub_16bit_0.value(bit16s_0[0], bit16s_1[0], cin, add);
             (bit16s_return[1], carries[1]) = self.add_sub_16bit_1.value(bit16s_0[1], bit16s_1[1], carries[0], add);
             (bit16s_return[2], carries[2]) = self.add_sub_16bit_2.value(bit16s_0[2], bit16s_1[2], carries[1], add);
             (bit16s_return[3], carries[3]) = self.add_sub_16bit_3.value(bit16s_0[3], bit16s_1[3], carries[2], add);
@@ -188,6 +201,31 @@ pub(crate) mod adders {
             return64[48..64].copy_from_slice(&bit16s_return[3][0..16]);
             (return64, carries[3])
         }
+
+        pub fn value_lanes(&self, inp0 : [bool; 64], inp1 : [bool; 64], lane_bits : usize, add : bool) -> ([bool; 64], [bool; 8]) {
+            // Packed lanes of 8, 16, 32 or 64 bits - the carry chain is cut at every lane boundary, so each lane adds (or subtracts) on its own
+            // Returns the carry (or borrow) out of every byte - the one out of a lane's top byte is that lane's carry-out
+
+            let adders = [&self.add_sub_16bit_0, &self.add_sub_16bit_1, &self.add_sub_16bit_2, &self.add_sub_16bit_3];
+            let mut return64 = [false; 64];
+            let mut carries = [false; 8];
+            let mut carry = false;
+
+            for (index, adder) in adders.iter().enumerate() {
+                let mut bits16_0 = [false; 16];
+                let mut bits16_1 = [false; 16];
+                bits16_0.copy_from_slice(&inp0[index * 16..index * 16 + 16]);
+                bits16_1.copy_from_slice(&inp1[index * 16..index * 16 + 16]);
+
+                let cin = if (index * 16) % lane_bits == 0 { false } else { carry };
+                let (bits16_return, byte_carries) = adder.value_split(bits16_0, bits16_1, cin, add, lane_bits == 8);
+
+                return64[index * 16..index * 16 + 16].copy_from_slice(&bits16_return);
+                carries[index * 2..index * 2 + 2].copy_from_slice(&byte_carries);
+                carry = byte_carries[1];
+            }
+            (return64, carries)
+        }
     }
     impl Default for AddSub64bit {
         fn default() -> Self {
@@ -200,4 +238,61 @@ pub(crate) mod adders {
         }
     }
 
-}
\ No newline at end of file
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::converter::converter::Converter;
+
+        fn bits(value : u64) -> [bool; 64] { Converter::dec_to_bin_pos_only(value, 64).try_into().unwrap() }
+
+        fn reference(a : u64, b : u64, lane_bits : usize, add : bool) -> (u64, Vec<bool>) {
+            // Each lane on its own in wider integers - the result, and each lane's carry (or borrow) out
+            let mask = u64::MAX >> (64 - lane_bits);
+            let mut result = 0;
+            let mut carries = vec![];
+            for low in (0..64).step_by(lane_bits) {
+                let (x, y) = ((a >> low & mask) as u128, (b >> low & mask) as u128);
+                let (lane, carry) = if add { (x + y, x + y > mask as u128) } else { (x.wrapping_sub(y), x < y) };
+                result |= (lane as u64 & mask) << low;
+                carries.push(carry);
+            }
+            (result, carries)
+        }
+
+        fn check(adder : &AddSub64bit, a : u64, b : u64) {
+            for lane_bits in [8, 16, 32, 64] {
+                for add in [true, false] {
+                    let (result, carries) = adder.value_lanes(bits(a), bits(b), lane_bits, add);
+                    let (expected, expected_carries) = reference(a, b, lane_bits, add);
+                    assert_eq!(Converter::bin_to_dec_pos_only(result.to_vec()), expected, "{0:016X} {1} {2:016X}, {3}-bit lanes", a, if add { '+' } else { '-' }, b, lane_bits);
+                    let lane_carries : Vec<bool> = (0..64 / lane_bits).map(|lane| carries[(lane + 1) * lane_bits / 8 - 1]).collect();
+                    assert_eq!(lane_carries, expected_carries, "{0:016X} {1} {2:016X}, {3}-bit lanes", a, if add { '+' } else { '-' }, b, lane_bits);
+                }
+            }
+        }
+
+        #[test]
+        fn lane_boundaries() {
+            // Carries out of every lane, and borrows into none
+            let adder = AddSub64bit::default();
+            check(&adder, u64::MAX, 1);
+            check(&adder, 0, 1);
+            check(&adder, 0x00FF_00FF_00FF_00FF, 0x0001_0001_0001_0001);
+            check(&adder, 0x7F7F_7F7F_7F7F_7F7F, 0x0101_0101_0101_0101);
+            check(&adder, 0x8000_0000_8000_0000, 0x8000_0000_8000_0000);
+            check(&adder, 0x0100_0000_0100_0000, 0x0000_0001_0000_0001);
+        }
+
+        #[test]
+        fn seeded_lanes() {
+            let adder = AddSub64bit::default();
+            let mut value : u64 = 12345;
+            for _ in 0..200 {
+                value = value.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                let a = value;
+                value = value.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                check(&adder, a, value);
+            }
+        }
+    }
+}
@@ -12,11 +12,12 @@ pub(crate) mod alu{
         pub(crate) bitwise_operator: BitwiseOperator,
         pub(crate) z: bool, // Zero Flag
         pub(crate) n: bool, // Negative Flag
-        pub(crate) o: bool  // Overflow
+        pub(crate) v: bool, // Overflow Flag - the signed result did not fit in 64 bits
+        pub(crate) c: bool  // Carry Flag - carry-out of an addition, or NOT borrow-out of a subtraction, as on ARM
     }
     impl Alu {
 
-        fn set_flags(&mut self, bits : [bool; 64], carry_out : bool) -> ([bool; 64], bool){
+        fn set_flags(&mut self, bits : [bool; 64], overflow : bool) -> [bool; 64]{
             // Set accumulator flags, for use by branches
             if Converter::bin_to_dec_2s_comp(bits.to_vec()) == 0{
                 self.z = true;
@@ -31,9 +32,16 @@ pub(crate) mod alu{
                 self.n = false;
             }
 
-            self.o = carry_out; // Overflow on a carry-out
-            (bits, carry_out)
+            self.v = overflow;
+            bits
         }
+
+        fn overflow(val0 : [bool; 64], val1 : [bool; 64], result : [bool; 64], add : bool) -> bool{
+            // Signed overflow - adding two numbers of the same sign, or subtracting numbers of different signs, gives a result of the other sign
+            let (sign0, sign1, sign_result) = (val0[63], val1[63], result[63]);
+            (sign0 == sign1) == add && sign_result != sign0
+        }
+
         pub fn add(&mut self, val0 : [bool; 64], val1 : [bool; 64], incr : bool) -> ([bool; 64], bool){
             // Use Adder to add two numbers
             if incr{
@@ -41,23 +49,39 @@ pub(crate) mod alu{
             }
             else{
                 let (val, c_out) = self.adder_subtractor_64bit.value(val0, val1, true);
-                self.set_flags(val, c_out)
+                self.c = c_out;
+                (self.set_flags(val, Self::overflow(val0, val1, val, true)), c_out)
             }
         }
 
+        pub fn adc(&mut self, val0 : [bool; 64], val1 : [bool; 64]) -> ([bool; 64], bool){
+            // Add with the carry flag fed into the lowest bit, to chain additions wider than 64 bits
+            let (val, c_out) = self.adder_subtractor_64bit.value_with_carry(val0, val1, self.c, true);
+            self.c = c_out;
+            (self.set_flags(val, Self::overflow(val0, val1, val, true)), c_out)
+        }
+
         pub fn sub(&mut self, val0 : [bool; 64], val1 : [bool; 64]) -> ([bool; 64], bool){
             let (val, c_out) = self.adder_subtractor_64bit.value(val0, val1, false);
-            self.set_flags(val, c_out)
+            self.c = !c_out;
+            (self.set_flags(val, Self::overflow(val0, val1, val, false)), c_out)
+        }
+
+        pub fn sbc(&mut self, val0 : [bool; 64], val1 : [bool; 64]) -> ([bool; 64], bool){
+            // Subtract with a borrow-in when the carry flag is clear, to chain subtractions wider than 64 bits
+            let (val, c_out) = self.adder_subtractor_64bit.value_with_carry(val0, val1, !self.c, false);
+            self.c = !c_out;
+            (self.set_flags(val, Self::overflow(val0, val1, val, false)), c_out)
         }
 
         pub fn mult(&mut self, val0 : [bool; 64], val1 : [bool; 64]) -> [bool; 64]{
             let mult_val = self.multiplier.value(val0, val1);
-            self.set_flags(mult_val, false).0
+            self.set_flags(mult_val, false)
         }
 
         pub fn bitwise(&mut self, val0 : [bool; 64], val1 : [bool; 64], instr_type: [bool; 4]) -> [bool; 64]{
             let bitwise_val = self.bitwise_operator.value(val0, val1, instr_type);
-            self.set_flags(bitwise_val, false).0
+            self.set_flags(bitwise_val, false)
         }
     }
 
@@ -67,7 +91,7 @@ pub(crate) mod alu{
                 adder_subtractor_64bit: AddSub64bit::default(),
                 multiplier: Multiplier::default(),
                 bitwise_operator: Default::default(),
-                z: false, n: false, o: false
+                z: false, n: false, v: false, c: false
             }
         }
     }
@@ -1,7 +1,15 @@
 pub(crate) mod assembler{
 
+    use std::collections::HashMap;
     use num_derive::FromPrimitive;
     use crate::converter::converter::Converter;
+    use crate::image::image::SectionKind;
+    use crate::system_regs::system_regs::SystemRegs;
+
+    // Opcode nibble 15 marks an extended instruction - the full instruction type is then held in bits 4..12
+    pub(crate) const EXTENDED_OPCODE : u8 = 15;
+    // Relocation field for a MOVZ/MOVK immediate holding a label's address - plus 0 to 3 for which 16 bits of it
+    pub(crate) const HALFWORD_RELOCATION : u32 = 64;
 
     #[repr(u8)]
     #[derive(Clone, FromPrimitive)]
@@ -21,7 +29,59 @@ pub(crate) mod assembler{
         OR = 11,
         XOR = 12,
         NOT = 13,
-        FLIP = 14
+        FLIP = 14,
+        // Extended instructions (encoded behind EXTENDED_OPCODE)
+        SYSCALL = 16,
+        SRET = 17,
+        MFS = 18,
+        MTS = 19,
+        IN = 20,
+        MOVZ = 21,
+        MOVK = 22,
+        MOVN = 23,
+        ADC = 24,
+        SBC = 25,
+        // Floating point (see fpu.rs)
+        FADD = 26,
+        FSUB = 27,
+        FMUL = 28,
+        FDIV = 29,
+        FSQRT = 30,
+        FCMP = 31,
+        FMOV = 32, // Between float registers, or to/from an integer register - bit for bit
+        FMOVI = 33, // 'FMOV F1, #1.5' - a double whose lowest 48 bits are 0
+        ITOF = 34,
+        FTOI = 35,
+        FRSR = 36, // Read the FPSR
+        FWSR = 37, // Write the FPSR
+        // SIMD (see simd.rs) - the lane size is a '.B', '.H', '.W' or '.D' suffix
+        VADD = 38,
+        VSUB = 39,
+        VMUL = 40,
+        VQADD = 41, // Signed saturating
+        VQSUB = 42,
+        VUQADD = 43, // Unsigned saturating
+        VUQSUB = 44,
+        VAND = 45,
+        VOR = 46,
+        VXOR = 47,
+        VDUP = 48, // Broadcast an integer register to every lane
+        VADDV = 49, // Sum the lanes into an integer register
+        VMOV = 50, // Between vector registers, or to/from an integer register
+        VLDR = 51,
+        VSTR = 52
+    }
+
+    impl InstrType {
+        pub fn signed_immediates(&self) -> bool {
+            // Arithmetic immediates are sign extended (-32768 to 32767), bitwise ones zero extended (0 to 65535)
+            !matches!(self, InstrType::AND | InstrType::OR | InstrType::XOR | InstrType::NOT)
+        }
+
+        pub fn privileged(&self) -> bool {
+            // Instructions which trap if executed in User mode
+            matches!(self, InstrType::HLT | InstrType::SRET | InstrType::MFS | InstrType::MTS)
+        }
     }
 
     #[repr(u8)]
@@ -35,7 +95,16 @@ pub(crate) mod assembler{
         BGT = 4,
         BLE = 5,
         BGE = 6,
-        OTH = 7
+        OTH = 7,
+        // Unsigned, overflow and carry conditions
+        BLO = 8,
+        BHS = 9,
+        BHI = 10,
+        BLS = 11,
+        BVS = 12,
+        BVC = 13,
+        BCS = 14,
+        BCC = 15
     }
 
     #[derive(Clone)]
@@ -49,7 +118,9 @@ pub(crate) mod assembler{
         pub(crate) reg_1 : bool,
         pub(crate) ascii : bool,
         pub(crate) input_val_0 : [bool; 16],
-        pub(crate) input_val_1 : [bool; 16]
+        pub(crate) input_val_1 : [bool; 16],
+        pub(crate) access_size : u8, // Bytes loaded or stored by LDR/STR, or in each SIMD lane - 1, 2, 4 or 8
+        pub(crate) signed : bool // Sign extend narrow loads
     }
     impl ParsedInstruction {
         pub fn clear(&mut self){
@@ -62,6 +133,8 @@ pub(crate) mod assembler{
             self.ascii = false;
             self.input_val_0 = [false; 16];
             self.input_val_1 = [false; 16];
+            self.access_size = 8;
+            self.signed = false;
         }
     }
 
@@ -72,22 +145,231 @@ pub(crate) mod assembler{
                 branch_condition : BranchConditions::OTH,
                 addr : [false; 48], return_register : [false; 4],
                 reg_0 : false, reg_1 : false, ascii : false,
-                input_val_0 : [false; 16], input_val_1 : [false; 16]
+                input_val_0 : [false; 16], input_val_1 : [false; 16],
+                access_size : 8, signed : false
             }
         }
     }
 
-    pub(crate) struct Assembler {} // Convert a line from the instruction input file first into an intermediate representation (ParsedInstruction), then into binary
+    pub(crate) enum DataDirective {
+        Bytes(u64, Vec<u8>), // Alignment, then the bytes - .word, .dword, .ascii, .asciz, .space and .zero
+        Align(u64), // Bytes
+        Org(u64) // Address
+    }
+
+    fn parse_value(text : &str, size : u32) -> Result<u64, String> {
+        // A decimal (may be negative), 0x hex or 0b binary number, or a 'c' character - must fit in size bytes, signed or unsigned
+        let text = text.trim();
+        let value : i128 = if let Some(hex) = text.strip_prefix("0x") {
+            i128::from_str_radix(hex, 16).map_err(|_| format!("Invalid hex value '{0}'", text))?
+        } else if let Some(binary) = text.strip_prefix("0b") {
+            i128::from_str_radix(binary, 2).map_err(|_| format!("Invalid binary value '{0}'", text))?
+        } else if text.len() == 3 && text.starts_with('\'') && text.ends_with('\'') {
+            text.chars().nth(1).unwrap() as i128
+        } else {
+            text.parse().map_err(|_| format!("Invalid value '{0}'", text))?
+        };
+        let bits = size * 8;
+        if value < -(1 << (bits - 1)) || value >= 1 << bits { return Err(format!("{0} doesn't fit in {1} bytes", text, size)); }
+        Ok(value as u64 & (u64::MAX >> (64 - bits)))
+    }
+
+    fn parse_float(text : &str) -> Result<u64, String> {
+        // A double precision literal - '1.5', '-2e-3', 'inf', 'nan'... - as its bits
+        text.trim().parse::<f64>().map(f64::to_bits).map_err(|_| format!("Invalid float '{0}'", text.trim()))
+    }
+
+    fn is_float(text : &str) -> bool {
+        // Decimal literals with a point or exponent, and infinities and NaNs, are doubles rather than integers
+        !text.starts_with("0x") && !text.starts_with("0b") && !text.starts_with('\'') && text.to_ascii_lowercase().contains(['.', 'e', 'i', 'n'])
+    }
+
+    fn parse_strings(text : &str) -> Result<Vec<Vec<u8>>, String> {
+        // Comma separated "quoted" strings, with \n, \t, \0, \\ and \" escapes
+        let mut strings = vec![];
+        let mut chars = text.trim().chars();
+        loop {
+            match chars.next() {
+                None => break,
+                Some('"') => {}
+                Some(_) => return Err(format!("Expected a quoted string in '{0}'", text))
+            }
+            let mut string = vec![];
+            loop {
+                match chars.next() {
+                    None => return Err(format!("Unterminated string in '{0}'", text)),
+                    Some('"') => break,
+                    Some('\\') => string.push(match chars.next() {
+                        Some('n') => b'\n',
+                        Some('t') => b'\t',
+                        Some('0') => 0,
+                        Some('\\') => b'\\',
+                        Some('"') => b'"',
+                        other => return Err(format!("Unknown escape '\\{0}'", other.map(String::from).unwrap_or_default()))
+                    }),
+                    Some(c) => string.extend(c.to_string().as_bytes())
+                }
+            }
+            strings.push(string);
+            let rest = chars.as_str().trim_start();
+            chars = rest.strip_prefix(',').unwrap_or(rest).trim_start().chars();
+            if !rest.is_empty() && !rest.starts_with(',') { return Err(format!("Expected ',' between strings in '{0}'", text)); }
+        }
+        Ok(strings)
+    }
+
+    #[derive(Default)]
+    pub(crate) struct Assembler { // Convert a line from the instruction input file first into an intermediate representation (ParsedInstruction), then into binary
+        pub(crate) symbols : HashMap<String, [bool; 48]>, // Label addresses - '&label' may be used anywhere an '&<hex address>' is accepted
+        pub(crate) exports : Vec<String>, // '.global <label>' - visible to other objects when linked
+        pub(crate) externs : Vec<String>, // '.extern <label>' - defined in another object, and resolved by the linker
+        pub(crate) location : u64, // Address of the line being assembled
+        pub(crate) relocations : Vec<(u64, u32, String)>, // (location, first bit of the address field, label) for every label used as an address
+        pub(crate) sections : Vec<(u64, SectionKind)> // '.text', '.data' and '.bss' - the address each section starts at, in file order
+    }
 
     impl Assembler {
 
+        pub fn split_label(line : &str) -> (Option<String>, String) {
+            // Lines may start with a label definition - 'loop: ADD R1, R1, #1', or 'loop:' on its own
+            match line.split_once(':') {
+                Some((label, rest)) if !label.is_empty() && !label.starts_with("//") && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => {
+                    (Some(label.to_string()), rest.trim_start().to_string())
+                }
+                _ => (None, line.to_string())
+            }
+        }
+
+        pub fn directive(&mut self, line : &str) -> bool {
+            // '.global' and '.extern', followed by comma separated labels, or a section starting at location - false if the line isn't one of these
+            let (name, labels) = line.split_once(' ').unwrap_or((line, ""));
+            let list = match name {
+                ".global" => &mut self.exports,
+                ".extern" => &mut self.externs,
+                ".text" | ".data" | ".bss" => {
+                    let kind = match name { ".text" => SectionKind::Text, ".data" => SectionKind::Data, _ => SectionKind::Bss };
+                    self.sections.push((self.location, kind));
+                    return true;
+                }
+                _ => return false
+            };
+            list.extend(labels.split(',').map(|label| label.trim().to_string()).filter(|label| !label.is_empty()));
+            true
+        }
+
+        pub fn section_at(&self, addr : u64) -> SectionKind {
+            // Everything before the first section directive is .text
+            self.sections.iter().rev().find(|(start, _)| *start <= addr).map(|(_, kind)| *kind).unwrap_or(SectionKind::Text)
+        }
+
+        pub fn parse_data(line : &str) -> Result<Option<DataDirective>, String> {
+            // Data directives, which take up as many bytes as they hold rather than a word - None if the line isn't one
+            let (name, operands) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let values = |size : u32| -> Result<DataDirective, String> {
+                let mut bytes = vec![];
+                for value in operands.split(',') { bytes.extend(&parse_value(value, size)?.to_le_bytes()[..size as usize]); }
+                Ok(DataDirective::Bytes(size as u64, bytes)) // Aligned to their size
+            };
+            let count = || -> Result<u64, String> {
+                parse_value(operands.split(',').next().unwrap(), 8)
+            };
+            Ok(Some(match name {
+                ".word" => values(4)?,
+                ".dword" => values(8)?,
+                ".double" => {
+                    let mut bytes = vec![];
+                    for value in operands.split(',') { bytes.extend(&parse_float(value)?.to_le_bytes()); }
+                    DataDirective::Bytes(8, bytes)
+                }
+                ".ascii" => DataDirective::Bytes(1, parse_strings(operands)?.concat()),
+                ".asciz" => DataDirective::Bytes(1, parse_strings(operands)?.into_iter().flat_map(|string| string.into_iter().chain([0])).collect()),
+                ".space" => {
+                    let fill = match operands.split_once(',') {
+                        Some((_, fill)) => parse_value(fill, 1)? as u8,
+                        None => 0
+                    };
+                    DataDirective::Bytes(1, vec![fill; count()? as usize])
+                }
+                ".zero" => DataDirective::Bytes(1, vec![0; count()? as usize]),
+                ".align" => {
+                    let alignment = count()?;
+                    if !alignment.is_power_of_two() { return Err(format!(".align {0} is not a power of two", alignment)); }
+                    DataDirective::Align(alignment)
+                }
+                ".org" => DataDirective::Org(u64::from_str_radix(operands.trim().trim_start_matches("0x"), 16).map_err(|_| format!("Invalid .org address '{0}'", operands.trim()))?),
+                _ => return Ok(None)
+            }))
+        }
+
+        pub fn expand_li(operands : &str) -> Result<Vec<String>, String> {
+            // 'LI R1, <value>' - the fewest MOVZ/MOVN and MOVK instructions which build the value, from any 64-bit number or double,
+            // or '&label' for a label's address
+            let (reg, value) = operands.split_once(',').ok_or(format!("Expected 'LI <register>, <value>', found 'LI {0}'", operands))?;
+            let value = value.trim().trim_start_matches('#');
+            let reg = reg.trim();
+            if value.starts_with('&') {
+                // A label's address, which is only known once linked - always all 48 bits, so the linker can patch each half
+                return Ok((0..3).map(|index| format!("{0} {1}, {2}, LSL #{3}", if index == 0 { "MOVZ" } else { "MOVK" }, reg, value, index * 16)).collect());
+            }
+            let value = if is_float(value) { parse_float(value)? } else if value.starts_with('-') || value.starts_with("0x") || value.starts_with("0b") { parse_value(value, 8)? } else {
+                value.parse().map_err(|_| format!("Invalid value '{0}'", value))? // Up to 2^64 - 1, which parse_value would reject as too large
+            };
+            let halfwords : Vec<u64> = (0..4).map(|index| value >> (index * 16) & 0xFFFF).collect();
+            // MOVZ starts from zeros and MOVN from ones - whichever leaves fewer halfwords for MOVK to fill in
+            let zeros = halfwords.iter().filter(|halfword| **halfword == 0).count();
+            let ones = halfwords.iter().filter(|halfword| **halfword == 0xFFFF).count();
+            let (background, first) = if ones > zeros { (0xFFFF, "MOVN") } else { (0, "MOVZ") };
+            let mut lines = vec![];
+            for (index, halfword) in halfwords.iter().enumerate() {
+                if *halfword == background { continue; }
+                if lines.is_empty() {
+                    let immediate = if first == "MOVN" { !halfword & 0xFFFF } else { *halfword };
+                    lines.push(format!("{0} {1}, #{2}, LSL #{3}", first, reg, immediate, index * 16));
+                }
+                else {
+                    lines.push(format!("MOVK {0}, #{1}, LSL #{2}", reg, halfword, index * 16));
+                }
+            }
+            if lines.is_empty() { lines.push(format!("{0} {1}, #0, LSL #0", first, reg)); } // 0 or -1
+            Ok(lines)
+        }
+
+        pub fn parse_address(&mut self, operand : &str, field : u32) -> [bool; 48] {
+            // Operand without the leading '&' - either a label, or a 12 digit hex address
+            // A label is recorded against the bit its address field starts at, so the linker can move it - imported labels read as 0 until then
+            if let Some(addr) = self.symbols.get(operand) {
+                self.relocations.push((self.location, field, operand.to_string()));
+                return *addr;
+            }
+            if self.externs.iter().any(|label| label == operand) {
+                self.relocations.push((self.location, field, operand.to_string()));
+                return [false; 48];
+            }
+            Converter::hex_val_to_bin(operand.to_string()).try_into().expect("Invalid address or unknown label")
+        }
+
+        pub fn parse_operand(&self, operand : &str, signed : bool) -> (bool, [bool; 16]) {
+            // 'R<n>' for a register, or '#<value>' for an immediate - returns whether it is a register, and the 16-bit field
+            // Immediates are decimal, 0x hex, 0b binary or a 'c' character, and must fit in 16 bits, signed or unsigned as given
+            let operand = operand.trim().trim_end_matches(',');
+            if let Some(immediate) = operand.strip_prefix('#') {
+                let (min, max) = if signed { (-32768, 32767) } else { (0, 65535) };
+                let value = parse_value(immediate, 2).ok().map(|value| if immediate.starts_with('-') { value as u16 as i16 as i64 } else { value as i64 })
+                    .filter(|value| (min..=max).contains(value))
+                    .unwrap_or_else(|| panic!("Immediate #{0} is out of range - must be #{1} to #{2}", immediate, min, max));
+                return (false, Converter::dec_to_bin_pos_only(value as u16 as u64, 16).try_into().unwrap());
+            }
+            let reg_number : u8 = operand.trim_start_matches('R').parse().expect("Failed to parse register number");
+            (true, Converter::dec_to_bin_pos_only(reg_number as u64, 16).try_into().unwrap())
+        }
+
         pub fn get_type(&self, line : String) -> InstrType { // Get instruction type
-            match line.split(" ").nth(0).unwrap() {
+            match line.split(" ").nth(0).unwrap().split('.').nth(0).unwrap() {
                 "ADD" => InstrType::ADD,
                 "SUB" => InstrType::SUB,
                 "MULT" => InstrType::MULT,
-                "LDR" => InstrType::LDR,
-                "STR" => InstrType::STR,
+                "LDR" | "LDRB" | "LDRSB" | "LDRH" | "LDRSH" | "LDRW" | "LDRSW" => InstrType::LDR,
+                "STR" | "STRB" | "STRH" | "STRW" => InstrType::STR,
                 "HLT" => InstrType::HLT,
                 "OUT" => InstrType::OUT,
                 "CMP" => InstrType::CMP,
@@ -96,6 +378,42 @@ pub(crate) mod assembler{
                 "XOR" => InstrType::XOR,
                 "NOT" => InstrType::NOT,
                 "FLIP" => InstrType::FLIP,
+                "SYSCALL" => InstrType::SYSCALL,
+                "SRET" => InstrType::SRET,
+                "MFS" => InstrType::MFS,
+                "MTS" => InstrType::MTS,
+                "IN" => InstrType::IN,
+                "MOVZ" => InstrType::MOVZ,
+                "MOVK" => InstrType::MOVK,
+                "MOVN" => InstrType::MOVN,
+                "ADC" => InstrType::ADC,
+                "SBC" => InstrType::SBC,
+                "FADD" => InstrType::FADD,
+                "FSUB" => InstrType::FSUB,
+                "FMUL" => InstrType::FMUL,
+                "FDIV" => InstrType::FDIV,
+                "FSQRT" => InstrType::FSQRT,
+                "FCMP" => InstrType::FCMP,
+                "FMOV" => if line.contains('#') { InstrType::FMOVI } else { InstrType::FMOV },
+                "ITOF" => InstrType::ITOF,
+                "FTOI" => InstrType::FTOI,
+                "FRSR" => InstrType::FRSR,
+                "FWSR" => InstrType::FWSR,
+                "VADD" => InstrType::VADD,
+                "VSUB" => InstrType::VSUB,
+                "VMUL" => InstrType::VMUL,
+                "VQADD" => InstrType::VQADD,
+                "VQSUB" => InstrType::VQSUB,
+                "VUQADD" => InstrType::VUQADD,
+                "VUQSUB" => InstrType::VUQSUB,
+                "VAND" => InstrType::VAND,
+                "VOR" => InstrType::VOR,
+                "VXOR" => InstrType::VXOR,
+                "VDUP" => InstrType::VDUP,
+                "VADDV" => InstrType::VADDV,
+                "VMOV" => InstrType::VMOV,
+                "VLDR" => InstrType::VLDR,
+                "VSTR" => InstrType::VSTR,
                 _ => {
                     if line.split(" ").nth(0).unwrap().chars().nth(0).unwrap() == 'B' {
                         return InstrType::B;
@@ -105,6 +423,28 @@ pub(crate) mod assembler{
             }
         }
 
+        pub fn get_access(&self, line : String) -> (u8, bool) { // Access size in bytes, and whether it is sign extended, from the LDR/STR suffix
+            let mnemonic = line.split(" ").nth(0).unwrap();
+            let suffix = &mnemonic[3..];
+            let size = match suffix.trim_start_matches('S') {
+                "B" => 1,
+                "H" => 2,
+                "W" => 4,
+                _ => 8
+            };
+            (size, suffix.starts_with('S'))
+        }
+
+        pub fn get_lanes(&self, line : String) -> u8 { // Lane size in bytes, from the SIMD suffix - a single 64-bit lane without one
+            match line.split(" ").nth(0).unwrap().split_once('.').map(|(_, suffix)| suffix) {
+                Some("B") => 1,
+                Some("H") => 2,
+                Some("W") => 4,
+                Some("D") | None => 8,
+                Some(suffix) => panic!("Unknown lane size '.{0}' - must be .B, .H, .W or .D", suffix)
+            }
+        }
+
         pub fn get_condition(&self, line : String) -> BranchConditions { // Derive branch condition
             match line.split(" ").nth(0).unwrap() {
                 "B" => BranchConditions::B,
@@ -114,6 +454,14 @@ pub(crate) mod assembler{
                 "BGT" => BranchConditions::BGT,
                 "BLE" => BranchConditions::BLE,
                 "BGE" => BranchConditions::BGE,
+                "BLO" => BranchConditions::BLO,
+                "BHS" => BranchConditions::BHS,
+                "BHI" => BranchConditions::BHI,
+                "BLS" => BranchConditions::BLS,
+                "BVS" => BranchConditions::BVS,
+                "BVC" => BranchConditions::BVC,
+                "BCS" => BranchConditions::BCS,
+                "BCC" => BranchConditions::BCC,
                 _ => BranchConditions::OTH
             }
         }
@@ -129,7 +477,7 @@ pub(crate) mod assembler{
 
             match type_val {
 
-                InstrType::ADD|InstrType::SUB|InstrType::MULT|InstrType::AND|InstrType::OR|InstrType::XOR => {
+                InstrType::ADD|InstrType::SUB|InstrType::MULT|InstrType::AND|InstrType::OR|InstrType::XOR|InstrType::ADC|InstrType::SBC => {
 
                     let mut split = line_cpy.split(" ");
 
@@ -137,17 +485,8 @@ pub(crate) mod assembler{
                     let target_reg_number : u8 = split_0_parse.parse().expect("Failed to parse target register number");
                     parsed_instr.return_register = Converter::dec_to_bin_pos_only(target_reg_number as u64, 4).try_into().unwrap();
 
-                    let split_1_parse = split.clone().nth(2).unwrap().to_string().replace(&['R','#',','][..], "");
-                    let literal : i16 = split_1_parse.parse().expect("Failed to parse target register number");
-                    parsed_instr.input_val_0 = Converter::dec_to_bin_pos_only(literal as u64, 16).try_into().unwrap();
-
-                    if split.clone().nth(2).unwrap().chars().nth(0).unwrap() != '#'{ parsed_instr.reg_0 = true; }
-
-                    let split_2_parse = split.clone().nth(3).unwrap().to_string().replace(&['R','#',','][..], "");
-                    let literal : i16 = split_2_parse.parse().expect("Failed to parse target register number");
-                    parsed_instr.input_val_1 = Converter::dec_to_bin_pos_only(literal as u64, 16).try_into().unwrap();
-
-                    if split.nth(3).unwrap().chars().nth(0).unwrap() != '#'{ parsed_instr.reg_1 = true;} // '#' indicates literal, 'R' indicates register reference
+                    (parsed_instr.reg_0, parsed_instr.input_val_0) = self.parse_operand(split.clone().nth(2).unwrap(), type_val.signed_immediates());
+                    (parsed_instr.reg_1, parsed_instr.input_val_1) = self.parse_operand(split.nth(3).unwrap(), type_val.signed_immediates()); // '#' indicates literal, 'R' indicates register reference
                 }
 
                 InstrType::NOT|InstrType::FLIP => {
@@ -157,36 +496,23 @@ pub(crate) mod assembler{
                     let target_reg_number : u8 = split_0_parse.parse().expect("Failed to parse target register number");
                     parsed_instr.return_register = Converter::dec_to_bin_pos_only(target_reg_number as u64, 4).try_into().unwrap();
 
-                    let split_1_parse = split.clone().nth(2).unwrap().to_string().replace(&['R','#',','][..], "");
-                    let literal : i16 = split_1_parse.parse().expect("Failed to parse target register number");
-                    parsed_instr.input_val_0 = Converter::dec_to_bin_pos_only(literal as u64, 16).try_into().unwrap();
-
-                    if split.clone().nth(2).unwrap().chars().nth(0).unwrap() != '#'{ parsed_instr.reg_0 = true; }
+                    (parsed_instr.reg_0, parsed_instr.input_val_0) = self.parse_operand(split.nth(2).unwrap(), type_val.signed_immediates());
                 }
 
                 InstrType::CMP => {
                     let mut split = line_cpy.split(" ");
 
-                    let split_0_parse = &split.clone().nth(1).unwrap().to_string()[1..split.clone().nth(1).unwrap().to_string().clone().len()-1];
-                    let literal : i16 = split_0_parse.parse().expect("Failed to parse target register number");
-                    parsed_instr.input_val_0 = Converter::dec_to_bin_pos_only(literal as u64, 16).try_into().unwrap();
-
-                    if split.clone().nth(1).unwrap().chars().nth(0).unwrap() != '#'{ parsed_instr.reg_0 = true; }
-
-                    let split_1_parse = split.clone().nth(2).unwrap().to_string().replace(&['R','#',','][..], "");
-                    let literal : i16 = split_1_parse.parse().expect("Failed to parse target register number");
-                    parsed_instr.input_val_1 = Converter::dec_to_bin_pos_only(literal as u64, 16).try_into().unwrap();
-
-                    if split.nth(2).unwrap().chars().nth(0).unwrap() != '#'{ parsed_instr.reg_1 = true;}
+                    (parsed_instr.reg_0, parsed_instr.input_val_0) = self.parse_operand(split.clone().nth(1).unwrap(), true);
+                    (parsed_instr.reg_1, parsed_instr.input_val_1) = self.parse_operand(split.nth(2).unwrap(), true);
                 }
 
                 InstrType::STR| InstrType::LDR => {
                     let split1 = line_cpy.split(" ").nth(1).unwrap();
                     let reg_number : i32 = split1[1..(split1.len()-1)].parse().unwrap();
                     let split2 = line_cpy.split(" ").nth(2).unwrap();
-                    let hex_str: String = split2[1..split2.len()].parse().unwrap();
-                    parsed_instr.addr = Converter::hex_val_to_bin(hex_str).try_into().unwrap();
+                    parsed_instr.addr = self.parse_address(&split2[1..split2.len()], 8);
                     parsed_instr.return_register = Converter::dec_to_bin_pos_only(reg_number as u64, 4).try_into().unwrap();
+                    (parsed_instr.access_size, parsed_instr.signed) = self.get_access(line_cpy.clone());
                 }
 
                 InstrType::B => {
@@ -198,12 +524,11 @@ pub(crate) mod assembler{
                         parsed_instr.reg_0 = true;
                     }
                     else {
-                        let hex_str: String = address_string[1..address_string.len()].parse().unwrap();
-                        parsed_instr.addr = Converter::hex_val_to_bin(hex_str).try_into().unwrap();
+                        parsed_instr.addr = self.parse_address(&address_string[1..address_string.len()], 9);
                     }
                 }
 
-                InstrType::OUT => {
+                InstrType::OUT|InstrType::IN => {
                     let mut out_split = line_cpy.split(" ");
                     if out_split.clone().nth(1).unwrap().chars().nth(0).unwrap() == 'A'{
                         parsed_instr.ascii = true;
@@ -216,6 +541,109 @@ pub(crate) mod assembler{
                     parsed_instr.return_register = Converter::dec_to_bin_pos_only(reg_str[1..reg_str.len()].parse().unwrap(), 4).try_into().unwrap();
                 }
 
+                InstrType::SYSCALL => {
+                    let num_str = line_cpy.split(" ").nth(1).unwrap().replace('#', "");
+                    let syscall_number : u16 = num_str.parse().expect("Failed to parse syscall number");
+                    parsed_instr.input_val_0 = Converter::dec_to_bin_pos_only(syscall_number as u64, 16).try_into().unwrap();
+                }
+
+                InstrType::MFS => { // MFS R1, CAUSE
+                    let reg_str = line_cpy.split(" ").nth(1).unwrap().replace(&['R', ','][..], "");
+                    let reg_number : u8 = reg_str.parse().expect("Failed to parse target register number");
+                    parsed_instr.return_register = Converter::dec_to_bin_pos_only(reg_number as u64, 4).try_into().unwrap();
+                    let name = line_cpy.split(" ").nth(2).unwrap();
+                    let sys_reg = SystemRegs::get_register(name).unwrap_or_else(|| panic!("Unknown system register '{0}'", name));
+                    parsed_instr.input_val_0 = Converter::dec_to_bin_pos_only(sys_reg as u64, 16).try_into().unwrap();
+                }
+
+                InstrType::MOVZ|InstrType::MOVK|InstrType::MOVN => { // MOVZ R1, #0x1234, LSL #16 - the shift is optional
+                    // MOVZ and MOVK also take '&label', for the 16 bits of its address picked out by the shift (as LI uses them)
+                    let operands : Vec<&str> = line_cpy.split_once(' ').unwrap().1.split(',').map(|operand| operand.trim()).collect();
+                    let reg_number : u8 = operands[0].replace('R', "").parse().expect("Failed to parse target register number");
+                    parsed_instr.return_register = Converter::dec_to_bin_pos_only(reg_number as u64, 4).try_into().unwrap();
+                    let shift : u64 = match operands.get(2) {
+                        Some(shift) => shift.strip_prefix("LSL #").and_then(|shift| shift.parse().ok()).filter(|shift| [0, 16, 32, 48].contains(shift))
+                            .expect("Move shifts must be LSL #0, #16, #32 or #48"),
+                        None => 0
+                    };
+                    let immediate = match operands.get(1).and_then(|operand| operand.strip_prefix('&')) {
+                        Some(_) if matches!(type_val, InstrType::MOVN) => panic!("MOVN can't take an address - use MOVZ"),
+                        Some(label) => {
+                            let addr = self.parse_address(label, HALFWORD_RELOCATION + (shift / 16) as u32);
+                            Converter::bin_to_dec_pos_only(addr.to_vec()) >> shift & 0xFFFF
+                        }
+                        None => operands.get(1).and_then(|operand| operand.strip_prefix('#')).filter(|operand| !operand.starts_with('-'))
+                            .and_then(|operand| parse_value(operand, 2).ok()).expect("Move immediates must be #0 to #65535")
+                    };
+                    parsed_instr.input_val_0 = Converter::dec_to_bin_pos_only(immediate, 16).try_into().unwrap();
+                    parsed_instr.input_val_1[0..2].copy_from_slice(&Converter::dec_to_bin_pos_only(shift / 16, 2)); // Which 16 bits of the register
+                }
+
+                InstrType::FADD|InstrType::FSUB|InstrType::FMUL|InstrType::FDIV|InstrType::FSQRT|InstrType::FCMP|InstrType::FMOV|InstrType::ITOF|InstrType::FTOI|InstrType::FRSR|InstrType::FWSR => {
+                    // FADD F1, F2, F3 - the destination (if any) first. ITOF, FTOI and FMOV may have an integer register on either side
+                    let operands : Vec<&str> = line_cpy.split_once(' ').map(|(_, operands)| operands).unwrap_or("").split(',').map(|operand| operand.trim()).collect();
+                    let register = |operand : &str| -> [bool; 4] {
+                        let reg_number : u8 = operand.trim_start_matches(['R', 'F']).parse().ok().filter(|reg_number| *reg_number < 16)
+                            .unwrap_or_else(|| panic!("Failed to parse register '{0}'", operand));
+                        Converter::dec_to_bin_pos_only(reg_number as u64, 4).try_into().unwrap()
+                    };
+                    let sources = if let InstrType::FCMP = type_val { &operands[..] } else {
+                        parsed_instr.return_register = register(operands[0]);
+                        &operands[1..]
+                    };
+                    if let Some(source) = sources.first() { parsed_instr.input_val_0[0..4].copy_from_slice(&register(source)); }
+                    if let Some(source) = sources.get(1) { parsed_instr.input_val_1[0..4].copy_from_slice(&register(source)); }
+                    if let InstrType::FMOV = type_val {
+                        parsed_instr.reg_0 = sources[0].starts_with('R'); // Source is an integer register
+                        parsed_instr.reg_1 = operands[0].starts_with('R'); // Destination is an integer register
+                        if parsed_instr.reg_0 && parsed_instr.reg_1 { panic!("FMOV needs a float register - use ADD to copy between integer registers"); }
+                    }
+                }
+
+                InstrType::FMOVI => { // FMOV F1, #1.5
+                    let (reg, immediate) = line_cpy.split_once(' ').unwrap().1.split_once(',').expect("Expected 'FMOV <register>, #<value>'");
+                    let reg_number : u8 = reg.trim().trim_start_matches('F').parse().expect("Failed to parse target register number");
+                    parsed_instr.return_register = Converter::dec_to_bin_pos_only(reg_number as u64, 4).try_into().unwrap();
+                    let immediate = immediate.trim().trim_start_matches('#');
+                    let bits = parse_float(immediate).unwrap_or_else(|error| panic!("{0}", error));
+                    if bits & 0xFFFF_FFFF_FFFF != 0 { panic!("Float immediate #{0} needs more than 16 bits - use .double, or LI and FMOV", immediate); }
+                    parsed_instr.input_val_0 = Converter::dec_to_bin_pos_only(bits >> 48, 16).try_into().unwrap(); // Sign, exponent and the top 4 bits of the fraction
+                }
+
+                InstrType::VADD|InstrType::VSUB|InstrType::VMUL|InstrType::VQADD|InstrType::VQSUB|InstrType::VUQADD|InstrType::VUQSUB|InstrType::VAND|InstrType::VOR|InstrType::VXOR|InstrType::VDUP|InstrType::VADDV|InstrType::VMOV => {
+                    // VADD.H V1, V2, V3 - the destination first. VDUP takes an integer source, VADDV an integer destination, and VMOV either
+                    let operands : Vec<&str> = line_cpy.split_once(' ').map(|(_, operands)| operands).unwrap_or("").split(',').map(|operand| operand.trim()).collect();
+                    let register = |operand : &str| -> [bool; 4] {
+                        let reg_number : u8 = operand.trim_start_matches(['R', 'V']).parse().ok().filter(|reg_number| *reg_number < 16)
+                            .unwrap_or_else(|| panic!("Failed to parse register '{0}'", operand));
+                        Converter::dec_to_bin_pos_only(reg_number as u64, 4).try_into().unwrap()
+                    };
+                    parsed_instr.return_register = register(operands[0]);
+                    if let Some(source) = operands.get(1) { parsed_instr.input_val_0[0..4].copy_from_slice(&register(source)); }
+                    if let Some(source) = operands.get(2) { parsed_instr.input_val_1[0..4].copy_from_slice(&register(source)); }
+                    parsed_instr.access_size = self.get_lanes(line_cpy.clone());
+                    parsed_instr.reg_0 = operands.get(1).is_some_and(|source| source.starts_with('R')); // Source is an integer register
+                    parsed_instr.reg_1 = operands[0].starts_with('R'); // Destination is an integer register
+                    if parsed_instr.reg_0 && parsed_instr.reg_1 { panic!("VMOV needs a vector register - use ADD to copy between integer registers"); }
+                }
+
+                InstrType::VLDR|InstrType::VSTR => { // VLDR V1, &data - a whole 64-bit word
+                    let split1 = line_cpy.split(" ").nth(1).unwrap();
+                    let reg_number : u8 = split1[1..(split1.len()-1)].parse().expect("Failed to parse vector register number");
+                    let split2 = line_cpy.split(" ").nth(2).unwrap();
+                    parsed_instr.addr = self.parse_address(&split2[1..split2.len()], 16);
+                    parsed_instr.return_register = Converter::dec_to_bin_pos_only(reg_number as u64, 4).try_into().unwrap();
+                }
+
+                InstrType::MTS => { // MTS VEC, R1
+                    let name = line_cpy.split(" ").nth(1).unwrap().replace(',', "");
+                    let sys_reg = SystemRegs::get_register(&name).unwrap_or_else(|| panic!("Unknown system register '{0}'", name));
+                    parsed_instr.input_val_0 = Converter::dec_to_bin_pos_only(sys_reg as u64, 16).try_into().unwrap();
+                    let reg_str = line_cpy.split(" ").nth(2).unwrap().replace('R', "");
+                    let reg_number : u8 = reg_str.parse().expect("Failed to parse source register number");
+                    parsed_instr.return_register = Converter::dec_to_bin_pos_only(reg_number as u64, 4).try_into().unwrap();
+                }
+
                 _ => {}
 
             }
@@ -227,6 +655,12 @@ pub(crate) mod assembler{
             let mut instr_nibble = [false; 4];
             instr_nibble = Converter::dec_to_bin_pos_only(parsed_instruction.instr_type.clone() as u64, 4).try_into().unwrap();
 
+            if parsed_instruction.instr_type.clone() as u8 > EXTENDED_OPCODE {
+                // Extended instructions - escape nibble, followed by the full 8-bit instruction type
+                instr_nibble = Converter::dec_to_bin_pos_only(EXTENDED_OPCODE as u64, 4).try_into().unwrap();
+                return_bits[4..12].copy_from_slice(&Converter::dec_to_bin_pos_only(parsed_instruction.instr_type.clone() as u64, 8));
+            }
+
             return_bits[0..4].copy_from_slice(&instr_nibble);
             match parsed_instruction.instr_type {
 
@@ -253,7 +687,11 @@ pub(crate) mod assembler{
 
                 InstrType::LDR|InstrType::STR => {
                     return_bits[4..8].copy_from_slice(&parsed_instruction.return_register);
-                    return_bits[8..40].copy_from_slice(&parsed_instruction.addr);
+                    return_bits[8..56].copy_from_slice(&parsed_instruction.addr);
+                    // Size - 0: 64-bit (as before narrower accesses existed), 1: 8-bit, 2: 16-bit, 3: 32-bit
+                    let size_code = (parsed_instruction.access_size.trailing_zeros() + 1) % 4;
+                    return_bits[56..58].copy_from_slice(&Converter::dec_to_bin_pos_only(size_code as u64, 2));
+                    return_bits[58] = parsed_instruction.signed;
                 }
 
                 InstrType::B => {
@@ -269,6 +707,62 @@ pub(crate) mod assembler{
                     return_bits[8] = parsed_instruction.ascii;
                 }
 
+                InstrType::SYSCALL => {
+                    return_bits[12..28].copy_from_slice(&parsed_instruction.input_val_0);
+                }
+
+                InstrType::MFS|InstrType::MTS => {
+                    return_bits[12..16].copy_from_slice(&parsed_instruction.return_register);
+                    return_bits[16..20].copy_from_slice(&parsed_instruction.input_val_0[0..4]);
+                }
+
+                InstrType::IN => {
+                    return_bits[12..16].copy_from_slice(&parsed_instruction.return_register);
+                    return_bits[16] = parsed_instruction.ascii;
+                }
+
+                InstrType::MOVZ|InstrType::MOVK|InstrType::MOVN => {
+                    return_bits[12..16].copy_from_slice(&parsed_instruction.return_register);
+                    return_bits[16..18].copy_from_slice(&parsed_instruction.input_val_1[0..2]);
+                    return_bits[18..34].copy_from_slice(&parsed_instruction.input_val_0);
+                }
+
+                InstrType::FADD|InstrType::FSUB|InstrType::FMUL|InstrType::FDIV|InstrType::FSQRT|InstrType::FCMP|InstrType::FMOV|InstrType::ITOF|InstrType::FTOI|InstrType::FRSR|InstrType::FWSR => {
+                    return_bits[12..16].copy_from_slice(&parsed_instruction.return_register);
+                    return_bits[16..20].copy_from_slice(&parsed_instruction.input_val_0[0..4]);
+                    return_bits[20..24].copy_from_slice(&parsed_instruction.input_val_1[0..4]);
+                    return_bits[24] = parsed_instruction.reg_0;
+                    return_bits[25] = parsed_instruction.reg_1;
+                }
+
+                InstrType::FMOVI => {
+                    return_bits[12..16].copy_from_slice(&parsed_instruction.return_register);
+                    return_bits[16..32].copy_from_slice(&parsed_instruction.input_val_0);
+                }
+
+                InstrType::VADD|InstrType::VSUB|InstrType::VMUL|InstrType::VQADD|InstrType::VQSUB|InstrType::VUQADD|InstrType::VUQSUB|InstrType::VAND|InstrType::VOR|InstrType::VXOR|InstrType::VDUP|InstrType::VADDV|InstrType::VMOV => {
+                    return_bits[12..16].copy_from_slice(&parsed_instruction.return_register);
+                    return_bits[16..20].copy_from_slice(&parsed_instruction.input_val_0[0..4]);
+                    return_bits[20..24].copy_from_slice(&parsed_instruction.input_val_1[0..4]);
+                    return_bits[24] = parsed_instruction.reg_0;
+                    return_bits[25] = parsed_instruction.reg_1;
+                    // Lane size - 0: 8-bit, 1: 16-bit, 2: 32-bit, 3: 64-bit
+                    return_bits[26..28].copy_from_slice(&Converter::dec_to_bin_pos_only(parsed_instruction.access_size.trailing_zeros() as u64, 2));
+                }
+
+                InstrType::VLDR|InstrType::VSTR => {
+                    return_bits[12..16].copy_from_slice(&parsed_instruction.return_register);
+                    return_bits[16..64].copy_from_slice(&parsed_instruction.addr);
+                }
+
+                InstrType::ADC|InstrType::SBC => { // As ADD, moved up past the extended instruction type
+                    return_bits[12..16].copy_from_slice(&parsed_instruction.return_register);
+                    return_bits[16] = parsed_instruction.reg_0;
+                    return_bits[17..33].copy_from_slice(&parsed_instruction.input_val_0);
+                    return_bits[33] = parsed_instruction.reg_1;
+                    return_bits[34..50].copy_from_slice(&parsed_instruction.input_val_1);
+                }
+
                 _ => {}
 
             }
@@ -280,4 +774,55 @@ pub(crate) mod assembler{
             self.code_generation(parsed_instruction) // Convert intermediate representation into binary and return
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::RunOptions;
+        use crate::tests::{program_file, run_program};
+
+        fn immediate(operand : &str, signed : bool) -> Option<u16> {
+            // The 16-bit field, or None if the assembler rejects the immediate
+            std::panic::catch_unwind(|| Assembler::default().parse_operand(operand, signed))
+                .ok().map(|(register, bits)| { assert!(!register); Converter::bin_to_dec_pos_only(bits.to_vec()) as u16 })
+        }
+
+        #[test]
+        fn immediate_ranges() {
+            // Arithmetic immediates are sign extended, bitwise ones zero extended
+            assert_eq!(immediate("#-32768", true), Some(0x8000));
+            assert_eq!(immediate("#-1", true), Some(0xFFFF));
+            assert_eq!(immediate("#32767", true), Some(0x7FFF));
+            assert_eq!(immediate("#32768", true), None);
+            assert_eq!(immediate("#-32769", true), None);
+            assert_eq!(immediate("#0xFFFF", true), None);
+            assert_eq!(immediate("#0", false), Some(0));
+            assert_eq!(immediate("#65535", false), Some(0xFFFF));
+            assert_eq!(immediate("#0xFF00,", false), Some(0xFF00));
+            assert_eq!(immediate("#'A'", false), Some(65));
+            assert_eq!(immediate("#65536", false), None);
+            assert_eq!(immediate("#-1", false), None);
+            assert_eq!(immediate("#x", false), None);
+        }
+
+        #[test]
+        fn instructions_check_ranges() {
+            let assembles = |line : &str| std::panic::catch_unwind(|| Assembler::default().assemble(line.to_string())).is_ok();
+            for line in ["ADD R1, R1, #-32768", "SUB R1, #32767, R2", "CMP R1, #-1", "AND R1, R1, #65535", "XOR R1, R1, #0x8000"] {
+                assert!(assembles(line), "{}", line);
+            }
+            for line in ["ADD R1, R1, #32768", "ADC R1, R1, #-32769", "MULT R1, R1, #40000", "AND R1, R1, #-1", "OR R1, R1, #65536"] {
+                assert!(!assembles(line), "{}", line);
+            }
+        }
+
+        #[test]
+        fn negative_immediates() {
+            let path = program_file("negative_immediates", &["ADD R1, #0, #5", "ADD R1, R1, #-1", "OUT R R1", "SUB R2, R1, #-3", "OUT R R2",
+                "MULT R3, R1, #-2", "OUT R R3", "ADD R4, #-32768, #-32768", "OUT R R4", "AND R5, R3, #0xFFFF", "OUT R R5", "HLT"]);
+            let (_, output) = run_program(&path, RunOptions::default());
+            std::fs::remove_file(&path).ok();
+            assert_eq!(output, "R1 OUTPUT: 4\nR2 OUTPUT: 7\nR3 OUTPUT: -8\nR4 OUTPUT: -65536\nR5 OUTPUT: 65528\n");
+        }
+    }
 }
\ No newline at end of file
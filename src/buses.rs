@@ -11,14 +11,51 @@ pub(crate) mod buses{
     impl DataBus {}
     impl Default for DataBus { fn default() -> Self { DataBus { bits : [false; 64] } } }
 
+    #[derive(Default)]
     pub(crate) struct ControlBus { // Sends control signals between CPU and memory
         pub(crate) ready_memory : bool,
         pub(crate) ready_cpu : bool,
         pub(crate) str : bool,
-        pub(crate) lock : bool
+        pub(crate) lock : bool,
+        pub(crate) error : bool, // Raised with ready_cpu when the data could not be read correctly (an uncorrectable ECC error)
+        // Clock-domain crossing - when the CPU and memory run from different clocks, each ready signal has to pass
+        // through a synchronizer, only being seen by the other side after this many of its own clock edges
+        pub(crate) sync_stages : u8,
+        pub(crate) memory_sync : u8, // Edges left before memory sees ready_memory
+        pub(crate) cpu_sync : u8 // Edges left before the CPU sees ready_cpu
     }
-    impl ControlBus {}
-    impl Default for ControlBus {
-        fn default() -> Self { ControlBus { ready_memory: false, ready_cpu: false, str: false, lock: false } }
+    impl ControlBus {
+        pub fn request(&mut self, store : bool){
+            // CPU side - start a memory transaction
+            self.str = store;
+            self.ready_memory = true;
+            self.memory_sync = self.sync_stages;
+        }
+
+        pub fn memory_sees_request(&mut self) -> bool {
+            // Memory side - called on each memory clock edge
+            if !self.ready_memory { return false; }
+            if self.memory_sync > 0 {
+                self.memory_sync -= 1;
+                return false;
+            }
+            true
+        }
+
+        pub fn respond(&mut self){
+            // Memory side - data is on the bus for the CPU
+            self.ready_cpu = true;
+            self.cpu_sync = self.sync_stages;
+        }
+
+        pub fn cpu_sees_response(&mut self) -> bool {
+            // CPU side - called on each CPU clock edge
+            if !self.ready_cpu { return false; }
+            if self.cpu_sync > 0 {
+                self.cpu_sync -= 1;
+                return false;
+            }
+            true
+        }
     }
 }
\ No newline at end of file
@@ -5,17 +5,26 @@ pub(crate) mod caches{
     use crate::converter::converter::Converter;
     use crate::main_memory::main_memory::MainMemory;
     use crate::memory_chips::memory_chips::DRAM;
+    use crate::ecc::ecc::{self, EccResult, EccStats};
 
     struct CachedObj{
         key: [bool; 48],
-        value: [bool; 64]
+        value: [bool; 64],
+        check: u8 // ECC check bits, only used with ECC on
+    }
+
+    impl CachedObj{
+        fn new(key : [bool; 48], value : [bool; 64]) -> CachedObj {
+            CachedObj{ key, value, check: ecc::encode(Converter::bin_to_dec_pos_only(value.to_vec())) }
+        }
     }
 
     pub(crate) struct DataAccessManager {
         // Manages data flow between memory, caches and CPU
         pub(crate) l1_cache: L1Cache,
         pub(crate) l2_cache: L2Cache,
-        pub(crate) main_memory: MainMemory
+        pub(crate) main_memory: MainMemory,
+        pub(crate) last_access_level: i32 // Cache level (1/2) which served the most recent read or write, -1 if neither
     }
     impl DataAccessManager {
         pub fn get_index(&self, key: [bool; 48]) -> (i32, i32){
@@ -34,28 +43,28 @@ pub(crate) mod caches{
         pub fn read(&mut self, key : [bool; 48]) -> ([bool; 64], bool){
             // Read from either cache or memory, depending on where the value is present
             // If not present in cache, one must instead move to the stall state, taking an extra clock cycle
-            let (index, level) = self.get_index(key);
-            match level{
-                1 => {
-                    self.l1_cache.get_value(index)
-                }
-                2 => {
-                    self.l2_cache.get_value(index)
-                }
-                _ => {
+            // Uncacheable (device) addresses always go out onto the bus
+            // A cache line with an uncorrectable ECC error is dropped, and read again from memory
+            let (index, level) = if self.main_memory.address_decoder.is_cacheable(key) { self.get_index(key) } else { (-1, -1) };
+            let cached = match level{
+                1 => self.l1_cache.get_value(index),
+                2 => self.l2_cache.get_value(index),
+                _ => ([false; 64], false)
+            };
+            self.last_access_level = if cached.1 { level } else { -1 };
+            if cached.1{
+                return cached;
+            }
 
-                    while self.main_memory.control_bus.lock {}
-                    self.main_memory.control_bus.lock = true;
+            while self.main_memory.control_bus.lock {}
+            self.main_memory.control_bus.lock = true;
 
-                    self.main_memory.address_bus.bits = key;
-                    self.main_memory.control_bus.str = false;
-                    self.main_memory.control_bus.ready_memory = true;
+            self.main_memory.address_bus.bits = key;
+            self.main_memory.control_bus.request(false);
 
-                    self.main_memory.control_bus.lock = false;
+            self.main_memory.control_bus.lock = false;
 
-                    ([false; 64], false)
-                }
-            }
+            ([false; 64], false)
         }
 
         pub fn insert_to_cache(&mut self, key : [bool; 48], val : [bool; 64]){
@@ -83,32 +92,54 @@ pub(crate) mod caches{
         pub fn write(&mut self, key : [bool; 48], val : [bool; 64]){
             // Write data to both cache and memory
             // Sets control bus signals for RAM management
-            self.insert_to_cache(key, val);
+            self.last_access_level = -1;
+            if self.main_memory.address_decoder.is_cacheable(key){
+                self.last_access_level = self.get_index(key).1;
+                self.insert_to_cache(key, val);
+            }
 
             while self.main_memory.control_bus.lock {};
             self.main_memory.control_bus.lock = true;
 
             self.main_memory.address_bus.bits = key;
             self.main_memory.data_bus.bits = val;
-            self.main_memory.control_bus.str = true;
-            self.main_memory.control_bus.ready_memory = true;
+            self.main_memory.control_bus.request(true);
 
             self.main_memory.control_bus.lock = false;
         }
 
+        pub fn poke(&mut self, key : [bool; 48], val : [bool; 64]){
+            // Debugger/GUI write - straight into main memory, updating any cached copy, without using the buses
+            self.main_memory.write(key, val);
+            let (index, level) = self.get_index(key);
+            match level{
+                1 => { self.l1_cache.update(index, val); }
+                2 => { self.l2_cache.update(index, val); }
+                _ => {}
+            }
+        }
+
+        pub fn bus_busy(&self) -> bool {
+            // True while memory has yet to pick up the last request - only possible if it runs slower than the CPU
+            self.main_memory.control_bus.ready_memory
+        }
+
         pub fn stall_read(&mut self) -> (bool, [bool; 64]){
 
             while self.main_memory.control_bus.lock {}
             self.main_memory.control_bus.lock = true;
 
-            let ready = self.main_memory.control_bus.ready_cpu;
+            let ready = self.main_memory.control_bus.cpu_sees_response();
+            let error = self.main_memory.control_bus.error;
             let mut return_addr = [false; 48];
             let mut return_data = [false; 64];
             if ready{
                 self.main_memory.control_bus.ready_cpu = false;
                 return_addr = self.main_memory.address_bus.bits;
                 return_data = self.main_memory.data_bus.bits;
-                self.insert_to_cache(return_addr, return_data);
+                if !error && self.main_memory.address_decoder.is_cacheable(return_addr){
+                    self.insert_to_cache(return_addr, return_data);
+                }
                 self.main_memory.address_bus.bits = [false; 48];
                 self.main_memory.data_bus.bits = [false; 64];
             }
@@ -116,6 +147,11 @@ pub(crate) mod caches{
 
             (ready, return_data)
         }
+
+        pub fn take_bus_error(&mut self) -> bool {
+            // True if the data from the last stall_read was bad - a machine check
+            std::mem::take(&mut self.main_memory.control_bus.error)
+        }
     }
     
     
@@ -142,7 +178,9 @@ pub(crate) mod caches{
         // This object implements the LRU cache inside the L1 and L2 caches
         add_sub64bit: AddSub64bit,
         lru_queue: Vec<CachedObj>,
-        max_size: usize
+        max_size: usize,
+        ecc: bool,
+        ecc_stats: EccStats // Uncorrectable errors are counted as lines invalidated
     }
     impl CacheQueue {
         pub fn get_index(&self, key: [bool; 48]) -> i32{
@@ -160,12 +198,54 @@ pub(crate) mod caches{
             self.lru_queue.remove(index as usize);
         }
 
+        pub fn update(&mut self, index : i32, val : [bool; 64]){
+            // Overwrite a cached value in place, without changing its LRU position
+            self.lru_queue[index as usize] = CachedObj::new(self.lru_queue[index as usize].key, val);
+        }
+
+        pub fn contents(&self) -> Vec<([bool; 48], [bool; 64])>{
+            // (address, value) pairs, least recently used first
+            self.lru_queue.iter().map(|obj| (obj.key, obj.value)).collect()
+        }
+
+        pub fn restore(&mut self, contents : Vec<([bool; 48], [bool; 64])>){
+            // Replaces the whole queue - contents as returned by contents()
+            self.lru_queue = contents.into_iter().take(self.max_size).map(|(key, value)| CachedObj::new(key, value)).collect();
+        }
+
+        pub fn flip_bits(&mut self, index : usize, mask : u64){
+            // Fault injection - the check bits are left as they were
+            let value = Converter::bin_to_dec_pos_only(self.lru_queue[index].value.to_vec()) ^ mask;
+            self.lru_queue[index].value = Converter::dec_to_bin_pos_only(value, 64).try_into().unwrap();
+        }
+
+        fn check_line(&mut self, index : usize) -> bool {
+            // Returns false if the line had an uncorrectable error, and has been dropped
+            if !self.ecc { return true; }
+            let line = &self.lru_queue[index];
+            match ecc::check(Converter::bin_to_dec_pos_only(line.value.to_vec()), line.check) {
+                EccResult::Clean => true,
+                EccResult::Corrected(value, _) => {
+                    self.ecc_stats.corrected += 1;
+                    eprintln!("ECC: corrected a single-bit error in the cache line for {:012X}", Converter::bin_to_dec_pos_only(line.key.to_vec()));
+                    self.lru_queue[index] = CachedObj::new(line.key, Converter::dec_to_bin_pos_only(value, 64).try_into().unwrap());
+                    true
+                }
+                EccResult::Uncorrectable => {
+                    self.ecc_stats.uncorrectable += 1;
+                    eprintln!("ECC: uncorrectable error in the cache line for {:012X} - line invalidated", Converter::bin_to_dec_pos_only(line.key.to_vec()));
+                    self.lru_queue.remove(index);
+                    false
+                }
+            }
+        }
+
         pub fn get_value(&mut self, index : i32) -> ([bool; 64], bool){
-            if index != -1{
+            if index != -1 && self.check_line(index as usize){
                 let key: [bool; 48] = self.lru_queue.get(index as usize).clone().unwrap().key;
                 let val: [bool; 64] = self.lru_queue.get(index as usize).clone().unwrap().value;
                 self.lru_queue.remove(index as usize);
-                self.lru_queue.push(CachedObj{key: key.clone(), value: val.clone()});
+                self.lru_queue.push(CachedObj::new(key, val));
                 return (val, true);
             }
             ([false; 64], false)
@@ -176,19 +256,19 @@ pub(crate) mod caches{
             let index = self.get_index(key.clone());
             if index != -1{
                 self.lru_queue.remove(index as usize);
-                self.lru_queue.push(CachedObj{key: key.clone(), value: val.clone()});
+                self.lru_queue.push(CachedObj::new(key, val));
             }
             else{
                 if self.lru_queue.len() == self.max_size{
                     let flow_key = self.lru_queue.get(0).unwrap().key.clone();
                     let flow_val = self.lru_queue.get(0).unwrap().value.clone();
                     self.lru_queue.remove(0);
-                    return (CachedObj{key: flow_key, value: flow_val}, true);
+                    return (CachedObj::new(flow_key, flow_val), true);
 
                 }
-                self.lru_queue.push(CachedObj{key: key.clone(), value: val.clone()});
+                self.lru_queue.push(CachedObj::new(key, val));
             }
-            (CachedObj{ key : [false; 48], value: [false; 64] }, false)
+            (CachedObj::new([false; 48], [false; 64]), false)
         }
     }
 
@@ -206,6 +286,22 @@ pub(crate) mod caches{
         pub fn get_value(&mut self, index : i32) -> ([bool; 64], bool){ self.cache_queue.get_value(index) }
 
         pub fn insert(&mut self, key : [bool; 48], val : [bool; 64]) -> (CachedObj, bool){ self.cache_queue.insert(key, val) }
+
+        pub fn utilisation(&self) -> (usize, usize){ (self.cache_queue.lru_queue.len(), self.cache_queue.max_size) }
+
+        pub fn update(&mut self, index : i32, val : [bool; 64]){ self.cache_queue.update(index, val); }
+
+        pub fn contents(&self) -> Vec<([bool; 48], [bool; 64])>{ self.cache_queue.contents() }
+
+        pub fn restore(&mut self, contents : Vec<([bool; 48], [bool; 64])>){ self.cache_queue.restore(contents); }
+
+        pub fn flip_bits(&mut self, index : usize, mask : u64){ self.cache_queue.flip_bits(index, mask); }
+
+        pub fn set_ecc(&mut self, ecc : bool){ self.cache_queue.ecc = ecc; }
+
+        pub fn ecc_stats(&self) -> EccStats{ self.cache_queue.ecc_stats }
+
+        pub fn ecc(&self) -> bool{ self.cache_queue.ecc }
     }
     impl Default for L1Cache{
         fn default() -> Self {
@@ -213,7 +309,9 @@ pub(crate) mod caches{
                 cache_queue: CacheQueue {
                     add_sub64bit: Default::default(),
                     lru_queue: vec![],
-                    max_size: 20
+                    max_size: 20,
+                    ecc: false,
+                    ecc_stats: EccStats::default()
                 },
             }
         }
@@ -231,6 +329,22 @@ pub(crate) mod caches{
         pub fn get_value(&mut self, index : i32) -> ([bool; 64], bool){ self.cache_queue.get_value(index) }
 
         pub fn insert(&mut self, key : [bool; 48], val : [bool; 64]) -> (CachedObj, bool){ self.cache_queue.insert(key, val) }
+
+        pub fn utilisation(&self) -> (usize, usize){ (self.cache_queue.lru_queue.len(), self.cache_queue.max_size) }
+
+        pub fn update(&mut self, index : i32, val : [bool; 64]){ self.cache_queue.update(index, val); }
+
+        pub fn contents(&self) -> Vec<([bool; 48], [bool; 64])>{ self.cache_queue.contents() }
+
+        pub fn restore(&mut self, contents : Vec<([bool; 48], [bool; 64])>){ self.cache_queue.restore(contents); }
+
+        pub fn flip_bits(&mut self, index : usize, mask : u64){ self.cache_queue.flip_bits(index, mask); }
+
+        pub fn set_ecc(&mut self, ecc : bool){ self.cache_queue.ecc = ecc; }
+
+        pub fn ecc_stats(&self) -> EccStats{ self.cache_queue.ecc_stats }
+
+        pub fn ecc(&self) -> bool{ self.cache_queue.ecc }
     }
 
     impl Default for L2Cache{
@@ -239,7 +353,9 @@ pub(crate) mod caches{
                 cache_queue: CacheQueue {
                     add_sub64bit: Default::default(),
                     lru_queue: vec![],
-                    max_size: 50
+                    max_size: 50,
+                    ecc: false,
+                    ecc_stats: EccStats::default()
                 },
             }
         }
@@ -0,0 +1,354 @@
+pub(crate) mod checkpoint{
+    /*
+     * Machine checkpoints - the full state of the machine as text, one item per line
+     * Values are hex - 16 digits for 64-bit words, 12 for addresses
+     *
+     *   CHECKPOINT 1                 Format version
+     *   CYCLE <n>                    Clock cycle count (decimal)
+     *   ADDRESSING <0|1>             0 Word (bit addresses, as in checkpoints without this line), 1 Byte
+     *   HALT <0|1>
+     *   STATE <n>                    CpuState
+     *   PC / MIR / MDR <word>        PC, memory instruction register, memory data register
+     *   STALL <instr 0|1> <data 0|1>
+     *   DECODED <word>               Decoded instruction, re-encoded
+     *   FLAGS <z> <n> <v> <c>        Carry is optional, for checkpoints saved before it existed
+     *   PRIV <0|1>                   0 Supervisor, 1 User
+     *   SYS <n> <word>               System register n (see SystemRegister)
+     *   REG <n> <word>               General purpose register n
+     *   FREG <n> <word>              Float register n
+     *   FPSR <word>                  Float status - exception flags and rounding mode (see fpu.rs)
+     *   VREG <n> <word>              Vector register n
+     *   BUS <addr> <data> <ready_memory> <ready_cpu> <str> <lock> [<memory_sync> <cpu_sync> [<error>]]
+     *                                Sync values are synchronizer edges left, between clock domains - 0 if missing
+     *   L1 / L2 <addr> <word>        Cache lines, least recently used first
+     *   RAM <addr> <word>            Every word of main memory that has been written
+     *   ECC <addr> <check>           ECC check bits (2 hex digits) which don't match the word - a latent error. Others are recalculated
+     *   DEVICE <values...>           Internal registers of each mapped device, in mapping order
+     *   DRAM <values...>             DRAM controller state - see DramState::save_state
+     *
+     * Not included - anything already written by OUT, input buffered but not yet read by the program, and latent errors in cache lines
+     */
+    use num_traits::FromPrimitive;
+    use crate::assembler::assembler::Assembler;
+    use crate::clock::clock::Clock;
+    use crate::control_unit::control_unit::CpuState;
+    use crate::converter::converter::Converter;
+    use crate::disassembler::disassembler::Disassembler;
+    use crate::dram_controller::dram_controller::DramState;
+    use crate::ecc::ecc;
+    use crate::main_memory::main_memory::Addressing;
+    use crate::system_regs::system_regs::{PrivilegeLevel, SystemRegister};
+
+    const VERSION : &str = "1";
+
+    type RestoreAction = Box<dyn FnOnce(&mut Clock)>; // Applied once the whole file has parsed, so a bad line leaves the machine untouched
+
+    fn word(bits : [bool; 64]) -> String {
+        format!("{:016X}", Converter::bin_to_dec_pos_only(bits.to_vec()))
+    }
+
+    fn addr(bits : [bool; 48]) -> String {
+        format!("{:012X}", Converter::bin_to_dec_pos_only(bits.to_vec()))
+    }
+
+    fn parse_hex(hex : Option<&&str>) -> Result<u64, String> {
+        let hex = hex.ok_or("Missing value")?;
+        u64::from_str_radix(hex, 16).map_err(|_| format!("Invalid hex value '{0}'", hex))
+    }
+
+    fn parse_word(hex : Option<&&str>) -> Result<[bool; 64], String> {
+        Ok(Converter::dec_to_bin_pos_only(parse_hex(hex)?, 64).try_into().unwrap())
+    }
+
+    fn parse_addr(hex : Option<&&str>) -> Result<[bool; 48], String> {
+        let value = parse_hex(hex)?;
+        if value >= 1 << 48 { return Err("Address out of range".to_string()); }
+        Ok(Converter::dec_to_bin_pos_only(value, 48).try_into().unwrap())
+    }
+
+    fn parse_flag(text : Option<&&str>) -> Result<bool, String> {
+        match text {
+            Some(&"0") => Ok(false),
+            Some(&"1") => Ok(true),
+            _ => Err("Flag must be 0 or 1".to_string())
+        }
+    }
+
+    pub fn capture(clock : &mut Clock) -> String {
+        let ctrl = &mut clock.ctrl;
+        let mut lines = vec![
+            format!("CHECKPOINT {0}", VERSION),
+            format!("CYCLE {0}", clock.cycle_count),
+            format!("ADDRESSING {0}", ctrl.data_access_manager.main_memory.addressing as u8),
+            format!("HALT {0}", ctrl.halt as u8),
+            format!("STATE {0}", ctrl.state.clone() as u8),
+            format!("PC {0}", word(ctrl.pc.get_data())),
+            format!("MIR {0}", word(ctrl.memory_instr_reg.get_data())),
+            format!("MDR {0}", word(ctrl.memory_data_reg.get_data())),
+            format!("STALL {0} {1}", ctrl.memory_instr_stall as u8, ctrl.memory_data_stall as u8),
+            format!("DECODED {0}", word(Assembler::default().code_generation(ctrl.decoded_instruction.clone()))),
+            format!("FLAGS {0} {1} {2} {3}", ctrl.alu.z as u8, ctrl.alu.n as u8, ctrl.alu.v as u8, ctrl.alu.c as u8),
+            format!("PRIV {0}", ctrl.privilege as u8)
+        ];
+        for sys_reg in 0..15u64 {
+            if let Some(reg) = SystemRegister::from_u64(sys_reg) && reg != SystemRegister::OTH {
+                lines.push(format!("SYS {0} {1}", sys_reg, word(ctrl.system_regs.get_data(reg))));
+            }
+        }
+        for reg in 1..15u64 {
+            lines.push(format!("REG {0} {1}", reg, word(ctrl.register_bank.get_data(Converter::dec_to_bin_pos_only(reg, 4).try_into().unwrap()))));
+        }
+        for reg in 0..16u64 {
+            lines.push(format!("FREG {0} {1}", reg, word(ctrl.fpu.get_data(Converter::dec_to_bin_pos_only(reg, 4).try_into().unwrap()))));
+        }
+        lines.push(format!("FPSR {0}", word(ctrl.fpu.get_status())));
+        for reg in 0..16u64 {
+            lines.push(format!("VREG {0} {1}", reg, word(ctrl.simd.get_data(Converter::dec_to_bin_pos_only(reg, 4).try_into().unwrap()))));
+        }
+        let memory = &ctrl.data_access_manager.main_memory;
+        let bus = &memory.control_bus;
+        lines.push(format!("BUS {0} {1} {2} {3} {4} {5} {6} {7} {8}", addr(memory.address_bus.bits), word(memory.data_bus.bits), bus.ready_memory as u8, bus.ready_cpu as u8,
+            bus.str as u8, bus.lock as u8, bus.memory_sync, bus.cpu_sync, bus.error as u8));
+        for (key, value) in ctrl.data_access_manager.l1_cache.contents() {
+            lines.push(format!("L1 {0} {1}", addr(key), word(value)));
+        }
+        for (key, value) in ctrl.data_access_manager.l2_cache.contents() {
+            lines.push(format!("L2 {0} {1}", addr(key), word(value)));
+        }
+        let mut ram_addrs : Vec<[bool; 48]> = ctrl.data_access_manager.main_memory.ram_map.keys().cloned().collect();
+        ram_addrs.sort_by_key(|key| Converter::bin_to_dec_pos_only(key.to_vec()));
+        for key in ram_addrs {
+            let value = ctrl.data_access_manager.main_memory.read(key);
+            lines.push(format!("RAM {0} {1}", addr(key), word(value)));
+            let memory = &ctrl.data_access_manager.main_memory;
+            if let Some(check) = memory.check_bits.get(&key) && *check != ecc::encode(Converter::bin_to_dec_pos_only(value.to_vec())) {
+                lines.push(format!("ECC {0} {1:02X}", addr(key), check));
+            }
+        }
+        for state in ctrl.data_access_manager.main_memory.address_decoder.save_state() {
+            let values : Vec<String> = state.iter().map(|value| format!("{:X}", value)).collect();
+            lines.push(format!("DEVICE {0}", values.join(" ")).trim_end().to_string());
+        }
+        let dram : Vec<String> = ctrl.data_access_manager.main_memory.dram.state.save_state().iter().map(|value| format!("{:X}", value)).collect();
+        lines.push(format!("DRAM {0}", dram.join(" ")));
+        lines.join("\n") + "\n"
+    }
+
+    pub fn apply(clock : &mut Clock, text : &str) -> Result<(), String> {
+        // The checkpoint is checked in full before anything is changed
+        let mut parsed : Vec<Vec<&str>> = vec![];
+        for line in text.lines() {
+            let fields : Vec<&str> = line.split_whitespace().collect();
+            if !fields.is_empty() { parsed.push(fields); }
+        }
+        match parsed.first() {
+            Some(header) if header.len() == 2 && header[0] == "CHECKPOINT" && header[1] == VERSION => {}
+            _ => return Err("Not a checkpoint, or an unsupported version".to_string())
+        }
+
+        let mut l1 = vec![];
+        let mut l2 = vec![];
+        let mut ram = vec![];
+        let mut devices = vec![];
+        let mut check_bits = vec![];
+        let mut dram = DramState::default(); // Checkpoints from before the DRAM controller have every bank precharged
+        let mut addressing = Addressing::Word;
+        let mut actions : Vec<RestoreAction> = vec![];
+        for (index, fields) in parsed.iter().enumerate().skip(1) {
+            let error = |message : String| format!("Line {0}: {1}", index + 1, message);
+            match fields[0] {
+                "CYCLE" => {
+                    let cycle : u64 = fields.get(1).and_then(|cycle| cycle.parse().ok()).ok_or(error("Invalid cycle count".to_string()))?;
+                    actions.push(Box::new(move |clock| clock.cycle_count = cycle));
+                }
+                "ADDRESSING" => {
+                    addressing = if parse_flag(fields.get(1)).map_err(error)? { Addressing::Byte } else { Addressing::Word };
+                }
+                "HALT" => {
+                    let halt = parse_flag(fields.get(1)).map_err(error)?;
+                    actions.push(Box::new(move |clock| clock.ctrl.halt = halt));
+                }
+                "STATE" => {
+                    let state : CpuState = fields.get(1).and_then(|state| state.parse::<u64>().ok()).and_then(CpuState::from_u64).ok_or(error("Invalid CPU state".to_string()))?;
+                    actions.push(Box::new(move |clock| clock.ctrl.state = state));
+                }
+                "PC" | "MIR" | "MDR" | "DECODED" => {
+                    let value = parse_word(fields.get(1)).map_err(error)?;
+                    actions.push(match fields[0] {
+                        "PC" => Box::new(move |clock : &mut Clock| clock.ctrl.pc.set_data(value)),
+                        "MIR" => Box::new(move |clock : &mut Clock| clock.ctrl.memory_instr_reg.set_data(value)),
+                        "MDR" => Box::new(move |clock : &mut Clock| clock.ctrl.memory_data_reg.set_data(value)),
+                        _ => Box::new(move |clock : &mut Clock| clock.ctrl.decoded_instruction = Disassembler::decode(value))
+                    });
+                }
+                "STALL" => {
+                    let (instr_stall, data_stall) = (parse_flag(fields.get(1)).map_err(error)?, parse_flag(fields.get(2)).map_err(error)?);
+                    actions.push(Box::new(move |clock| { clock.ctrl.memory_instr_stall = instr_stall; clock.ctrl.memory_data_stall = data_stall; }));
+                }
+                "FLAGS" => {
+                    let (z, n, v) = (parse_flag(fields.get(1)).map_err(error)?, parse_flag(fields.get(2)).map_err(error)?, parse_flag(fields.get(3)).map_err(error)?);
+                    let c = fields.len() > 4 && parse_flag(fields.get(4)).map_err(error)?;
+                    actions.push(Box::new(move |clock| { clock.ctrl.alu.z = z; clock.ctrl.alu.n = n; clock.ctrl.alu.v = v; clock.ctrl.alu.c = c; }));
+                }
+                "PRIV" => {
+                    let privilege = if parse_flag(fields.get(1)).map_err(error)? { PrivilegeLevel::User } else { PrivilegeLevel::Supervisor };
+                    actions.push(Box::new(move |clock| clock.ctrl.privilege = privilege));
+                }
+                "SYS" => {
+                    let reg = fields.get(1).and_then(|reg| reg.parse::<u64>().ok()).and_then(SystemRegister::from_u64).ok_or(error("Invalid system register".to_string()))?;
+                    let value = parse_word(fields.get(2)).map_err(error)?;
+                    actions.push(Box::new(move |clock| clock.ctrl.system_regs.set_data(reg, value)));
+                }
+                "REG" => {
+                    let reg : [bool; 4] = fields.get(1).and_then(|reg| reg.parse::<u64>().ok()).filter(|reg| *reg < 15)
+                        .map(|reg| Converter::dec_to_bin_pos_only(reg, 4).try_into().unwrap()).ok_or(error("Invalid register".to_string()))?;
+                    let value = parse_word(fields.get(2)).map_err(error)?;
+                    actions.push(Box::new(move |clock| clock.ctrl.register_bank.set_data(reg, value)));
+                }
+                "FREG" => {
+                    let reg : [bool; 4] = fields.get(1).and_then(|reg| reg.parse::<u64>().ok()).filter(|reg| *reg < 16)
+                        .map(|reg| Converter::dec_to_bin_pos_only(reg, 4).try_into().unwrap()).ok_or(error("Invalid float register".to_string()))?;
+                    let value = parse_word(fields.get(2)).map_err(error)?;
+                    actions.push(Box::new(move |clock| clock.ctrl.fpu.set_data(reg, value)));
+                }
+                "FPSR" => {
+                    let value = parse_word(fields.get(1)).map_err(error)?;
+                    actions.push(Box::new(move |clock| clock.ctrl.fpu.set_status(value)));
+                }
+                "VREG" => {
+                    let reg : [bool; 4] = fields.get(1).and_then(|reg| reg.parse::<u64>().ok()).filter(|reg| *reg < 16)
+                        .map(|reg| Converter::dec_to_bin_pos_only(reg, 4).try_into().unwrap()).ok_or(error("Invalid vector register".to_string()))?;
+                    let value = parse_word(fields.get(2)).map_err(error)?;
+                    actions.push(Box::new(move |clock| clock.ctrl.simd.set_data(reg, value)));
+                }
+                "BUS" => {
+                    let address = parse_addr(fields.get(1)).map_err(error)?;
+                    let data = parse_word(fields.get(2)).map_err(error)?;
+                    let signals = [parse_flag(fields.get(3)).map_err(error)?, parse_flag(fields.get(4)).map_err(error)?, parse_flag(fields.get(5)).map_err(error)?, parse_flag(fields.get(6)).map_err(error)?];
+                    let sync : Vec<u8> = fields[7..fields.len().min(9)].iter().map(|value| value.parse().map_err(|_| error("Invalid synchronizer count".to_string()))).collect::<Result<_, _>>()?;
+                    let bus_error = fields.len() > 9 && parse_flag(fields.get(9)).map_err(error)?;
+                    actions.push(Box::new(move |clock| {
+                        let memory = &mut clock.ctrl.data_access_manager.main_memory;
+                        memory.address_bus.bits = address;
+                        memory.data_bus.bits = data;
+                        memory.control_bus.ready_memory = signals[0];
+                        memory.control_bus.ready_cpu = signals[1];
+                        memory.control_bus.str = signals[2];
+                        memory.control_bus.lock = signals[3];
+                        memory.control_bus.memory_sync = sync.first().cloned().unwrap_or(0);
+                        memory.control_bus.cpu_sync = sync.get(1).cloned().unwrap_or(0);
+                        memory.control_bus.error = bus_error;
+                    }));
+                }
+                "L1" | "L2" | "RAM" => {
+                    let entry = (parse_addr(fields.get(1)).map_err(error)?, parse_word(fields.get(2)).map_err(error)?);
+                    match fields[0] {
+                        "L1" => l1.push(entry),
+                        "L2" => l2.push(entry),
+                        _ => ram.push(entry)
+                    }
+                }
+                "DEVICE" => {
+                    let mut state = vec![];
+                    for value in &fields[1..] { state.push(parse_hex(Some(value)).map_err(error)?); }
+                    devices.push(state);
+                }
+                "ECC" => {
+                    let key = parse_addr(fields.get(1)).map_err(error)?;
+                    let check = fields.get(2).and_then(|check| u8::from_str_radix(check, 16).ok()).ok_or(error("Invalid check bits".to_string()))?;
+                    check_bits.push((key, check));
+                }
+                "DRAM" => {
+                    let mut state = vec![];
+                    for value in &fields[1..] { state.push(parse_hex(Some(value)).map_err(error)?); }
+                    dram = DramState::restore_state(&state).ok_or(error("Invalid DRAM controller state".to_string()))?;
+                }
+                other => return Err(error(format!("Unknown item '{0}'", other)))
+            }
+        }
+
+        for action in actions { action(clock); }
+        let data_access_manager = &mut clock.ctrl.data_access_manager;
+        data_access_manager.main_memory.set_addressing(addressing);
+        data_access_manager.l1_cache.restore(l1);
+        data_access_manager.l2_cache.restore(l2);
+        data_access_manager.main_memory.clear();
+        for (key, value) in ram { data_access_manager.main_memory.write(key, value); }
+        for (key, check) in check_bits { data_access_manager.main_memory.check_bits.insert(key, check); }
+        data_access_manager.main_memory.address_decoder.restore_state(devices);
+        data_access_manager.main_memory.dram.state = dram;
+        clock.running = !clock.ctrl.halt;
+        Ok(())
+    }
+
+    pub fn save(clock : &mut Clock, path : &str){
+        std::fs::write(path, capture(clock)).expect("Checkpoint File Error");
+        println!(" ----- CHECKPOINT SAVED TO {0} AT CYCLE {1} -----", path, clock.cycle_count);
+    }
+
+    pub fn restore(clock : &mut Clock, path : &str) -> Result<(), String> {
+        let text = std::fs::read_to_string(path).map_err(|error| format!("{0}: {1}", path, error))?;
+        apply(clock, &text)?;
+        println!(" ----- CHECKPOINT RESTORED FROM {0} AT CYCLE {1} -----", path, clock.cycle_count);
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::RunOptions;
+        use crate::output_sink::output_sink::BufferSink;
+        use crate::tests::{load_program, program_file};
+
+        // Registers of every kind, a store and a load, and output on each loop
+        const PROGRAM : [&str; 12] = ["ADD R2, #0, #40", "MOVZ R5, #4660, LSL #48", "FMOV F1, #1.5", "VDUP.H V1, R2", "loop: ADD R1, R1, R2",
+            "STR R1, &000000000C00", "LDR R3, &000000000C00", "FADD F2, F2, F1", "OUT R R1", "SUB R2, R2, #1", "BNE &loop", "HLT"];
+
+        fn machine(name : &str, lines : &[&str]) -> (Clock, BufferSink) {
+            let path = program_file(name, lines);
+            let machine = load_program(&path, RunOptions::default());
+            std::fs::remove_file(&path).ok();
+            machine
+        }
+
+        #[test]
+        fn round_trip() {
+            let (mut clk, _) = machine("checkpoint_round_trip", &PROGRAM);
+            while clk.cycle_count < 700 { clk.refresh(); }
+            let text = capture(&mut clk);
+            assert!(text.contains("\nFREG 2 ") && text.contains("\nRAM ") && text.contains("\nL1 "), "{}", text);
+
+            let (mut fresh, _) = machine("checkpoint_round_trip_fresh", &["HLT"]);
+            apply(&mut fresh, &text).unwrap();
+            assert_eq!(capture(&mut fresh), text);
+        }
+
+        #[test]
+        fn resume_matches_full_run() {
+            let (mut full, full_output) = machine("checkpoint_full", &PROGRAM);
+            full.start();
+
+            let (mut first, first_output) = machine("checkpoint_first", &PROGRAM);
+            while first.cycle_count < full.cycle_count / 2 { first.refresh(); }
+            let text = capture(&mut first);
+
+            let (mut resumed, resumed_output) = machine("checkpoint_resumed", &["HLT"]);
+            apply(&mut resumed, &text).unwrap();
+            resumed.start();
+            assert!(!first_output.contents().is_empty() && !resumed_output.contents().is_empty());
+            assert_eq!(first_output.contents() + &resumed_output.contents(), full_output.contents());
+            assert_eq!(resumed.cycle_count, full.cycle_count);
+            assert_eq!(capture(&mut resumed), capture(&mut full));
+        }
+
+        #[test]
+        fn bad_line_leaves_machine_untouched() {
+            let (mut clk, _) = machine("checkpoint_bad_line", &PROGRAM);
+            while clk.cycle_count < 300 { clk.refresh(); }
+            let before = capture(&mut clk);
+            let text = before.replace("CYCLE 300", "CYCLE 5").replace("\nREG 3 ", "\nREG 15 ");
+            assert!(apply(&mut clk, &text).unwrap_err().contains("Invalid register"));
+            assert_eq!(capture(&mut clk), before);
+        }
+    }
+}
@@ -1,12 +1,29 @@
 pub(crate) mod clock{
-    use crate::control_unit::control_unit::ControlUnit;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use crate::control_unit::control_unit::{ControlUnit, CpuState};
     use crate::converter::converter::Converter;
-    use crate::{read_pipe, send_memory};
+    use crate::gui_pipe::gui_pipe;
+    use crate::trace::trace::Tracer;
+    use crate::checkpoint::checkpoint;
+    use crate::history::history::History;
+    use crate::clock_domains::clock_domains::DomainScheduler;
+    use crate::fault_injector::fault_injector::FaultInjector;
+    use crate::{read_pipe, send_cycle, send_halt, send_instr, send_l1_util, send_l2_util, send_memory, send_paused, send_reg, send_speed, send_state};
+
+    pub(crate) const MIN_CLOCK_SPEED : f64 = 1e-6; // Slowest real-time speed, in Hz - about 12 days a cycle
 
     pub(crate) struct Clock {
-        pub(crate) clock_speed : i64, // Frequency in Hz
+        pub(crate) clock_speed : f64, // Frequency in Hz - may be below 1Hz, for stepping through a demo
+        pub(crate) throttled : bool, // Pace the clock to clock_speed in real time, rather than running as fast as possible
         pub(crate) running : bool,
+        pub(crate) paused : bool, // Set by the GUI - no cycles are run until resumed
         pub(crate) ctrl : ControlUnit,
+        pub(crate) tracer : Option<Tracer>, // Records each retired instruction, if tracing is enabled
+        pub(crate) checkpoint : Option<(Option<u64>, String)>, // Save a checkpoint to the file at the given cycle, or when the program halts
+        pub(crate) history : Option<History>, // Undo log, for reverse execution
+        pub(crate) domains : DomainScheduler, // Frequencies of the CPU, memory and devices, relative to each other
+        pub(crate) faults : Option<FaultInjector>, // Bit flips to inject, if any
 
         pub(crate) cycle_count : u64
     }
@@ -14,9 +31,54 @@ pub(crate) mod clock{
 
         pub fn start(&mut self){
             self.running = true;
+            let mut next_cycle : Option<Instant> = None; // When the next cycle is due, if throttled
+            let mut active_time = Duration::ZERO; // Time spent running, excluding pauses
+            let start_cycle = self.cycle_count;
+            let mut sample = (Instant::now(), self.cycle_count); // Start of the current measurement window
             while self.running {
                 self.parse_pipe_data(read_pipe());
+                if self.paused && !gui_pipe::connected(){ self.paused = false; } // Nothing left to resume the clock
+                if self.paused{
+                    next_cycle = None;
+                    active_time += sample.0.elapsed();
+                    thread::sleep(Duration::from_millis(1)); // Wait for commands from the GUI
+                    sample = (Instant::now(), self.cycle_count); // Time spent paused isn't counted
+                    continue;
+                }
+                let now = Instant::now();
+                if self.throttled{
+                    let period = Duration::from_secs_f64(1.0 / self.clock_speed);
+                    let due = *next_cycle.get_or_insert(now);
+                    if now < due{
+                        thread::sleep((due - now).min(Duration::from_millis(5))); // Short sleeps, so GUI commands are still handled at low speeds
+                        continue;
+                    }
+                    // Fall back into step rather than running a burst of cycles to catch up, e.g. after the process was suspended
+                    next_cycle = Some(if now - due > period.max(Duration::from_millis(50)) { now + period } else { due + period });
+                }
+                else{
+                    next_cycle = None;
+                }
                 self.refresh();
+
+                let elapsed = sample.0.elapsed();
+                if elapsed >= Duration::from_secs(1) && self.cycle_count > sample.1 {
+                    active_time += elapsed;
+                    if gui_pipe::connected(){ send_speed((self.cycle_count - sample.1) as f64 / elapsed.as_secs_f64()); }
+                    sample = (Instant::now(), self.cycle_count);
+                }
+            }
+            active_time += sample.0.elapsed();
+            if active_time > Duration::ZERO{
+                println!("EFFECTIVE CLOCK SPEED: {0:.1}Hz", (self.cycle_count - start_cycle) as f64 / active_time.as_secs_f64());
+            }
+        }
+
+        pub fn set_speed(&mut self, speed : f64){
+            // Switches to real-time pacing - speeds below MIN_CLOCK_SPEED are raised to it, so the period fits in a Duration
+            if speed.is_finite() && speed > 0.0{
+                self.clock_speed = speed.max(MIN_CLOCK_SPEED);
+                self.throttled = true;
             }
         }
 
@@ -24,39 +86,172 @@ pub(crate) mod clock{
             self.running = false;
         }
 
+        pub fn step_instruction(&mut self){
+            // Run cycles until the CPU is ready to fetch the next instruction, or halts
+            loop {
+                self.refresh();
+                if !self.running || matches!(self.ctrl.state, CpuState::Fetch){ break; }
+            }
+        }
+
+        pub fn step_cycles(&mut self, limit : u64) -> bool {
+            // As step_instruction, but gives up after limit cycles - returns whether the instruction finished
+            for _ in 0..limit {
+                self.refresh();
+                if !self.running || matches!(self.ctrl.state, CpuState::Fetch){ return true; }
+            }
+            false
+        }
+
         pub fn refresh(&mut self){
             self.running = !self.ctrl.halt;
             if self.running{
+                if let Some(history) = self.history.as_mut() && history.wants_checkpoint(self.cycle_count) {
+                    let text = checkpoint::capture(self);
+                    if let Some(history) = self.history.as_mut() { history.add_checkpoint(self.cycle_count, text); }
+                }
+                if let Some(history) = self.history.as_mut() { history.begin(&mut self.ctrl, self.cycle_count); }
+                if let Some(faults) = self.faults.as_mut() { faults.inject(&mut self.ctrl, self.cycle_count, self.history.as_mut()); }
+                // Each refresh is one CPU cycle - memory and devices tick on however many of their own edges fall within it
+                let memory_edges = self.domains.edges(&self.domains.memory, self.cycle_count);
+                let memory_cycle = self.domains.memory_cycle(self.cycle_count);
+                let io_edges = self.domains.edges(&self.domains.io, self.cycle_count);
                 self.cycle_count += 1;
-                // AT PRESENT - both the CPU and RAM are controlled by the same clock - these could be separated in future for greater realism
+                let fetching = matches!(self.ctrl.state, CpuState::Fetch);
+                if fetching && let Some(tracer) = self.tracer.as_mut() { tracer.begin(&mut self.ctrl); }
                 self.ctrl.tick();
-                self.ctrl.data_access_manager.main_memory.tick();
+                if let Some(tracer) = self.tracer.as_mut() {
+                    // An instruction retires when the CPU returns to Fetch, or halts
+                    if fetching { tracer.fetched(&self.ctrl); }
+                    else if matches!(self.ctrl.state, CpuState::Fetch) || self.ctrl.halt { tracer.retire(&mut self.ctrl, self.cycle_count); }
+                }
+                if let Some(history) = self.history.as_mut() { history.before_memory(&mut self.ctrl); }
+                for edge in memory_cycle..memory_cycle + memory_edges { self.ctrl.data_access_manager.main_memory.tick(edge); }
+                for _ in 0..io_edges { self.ctrl.data_access_manager.main_memory.address_decoder.tick(); }
+                if let Some(history) = self.history.as_mut() { history.end(&mut self.ctrl); }
+                self.ctrl.data_access_manager.main_memory.changed_words.clear();
+                if let Some((Some(cycle), path)) = self.checkpoint.clone() && cycle == self.cycle_count {
+                    checkpoint::save(self, &path);
+                }
+                if gui_pipe::connected(){
+                    self.push_state();
+                }
             }
             else{
+                self.ctrl.output.flush();
+                if let Some(tracer) = self.tracer.as_mut() { tracer.flush(); }
+                if let Some((None, path)) = self.checkpoint.take() { checkpoint::save(self, &path); }
+                send_halt();
                 println!("\nCYCLE COUNT: {0}", self.cycle_count); // Print total cycle count after completion
+                self.report_errors();
+            }
+        }
+
+        fn report_errors(&self){
+            // Soft error summary, if ECC or fault injection is on
+            let data_access_manager = &self.ctrl.data_access_manager;
+            if let Some(faults) = &self.faults { println!("FAULTS INJECTED: {0}", faults.injected); }
+            if data_access_manager.main_memory.ecc {
+                let memory = data_access_manager.main_memory.ecc_stats;
+                println!("ECC: {0} corrected, {1} uncorrectable in memory", memory.corrected, memory.uncorrectable);
+            }
+            let (l1, l2) = (data_access_manager.l1_cache.ecc_stats(), data_access_manager.l2_cache.ecc_stats());
+            if data_access_manager.l1_cache.ecc() || data_access_manager.l2_cache.ecc() {
+                println!("ECC: {0} corrected, {1} lines invalidated in the caches", l1.corrected + l2.corrected, l1.uncorrectable + l2.uncorrectable);
             }
         }
 
+        fn push_state(&mut self){
+            // Send the GUI anything which has changed this cycle
+            for reg in 0..15u8 {
+                let reg_index : [bool; 4] = Converter::dec_to_bin_pos_only(reg as u64, 4).try_into().unwrap();
+                send_reg(reg, Converter::bin_to_dec_2s_comp(self.ctrl.register_bank.get_data(reg_index).to_vec()).to_string());
+            }
+            send_state(self.ctrl.state.clone());
+            send_instr(Self::hex_addr(self.ctrl.pc.get_data()[0..48].to_vec()), format!("{:016X}", Converter::bin_to_dec_pos_only(self.ctrl.memory_instr_reg.get_data().to_vec())));
+            let (l1_used, l1_size) = self.ctrl.data_access_manager.l1_cache.utilisation();
+            send_l1_util(format!("{0}/{1}", l1_used, l1_size));
+            let (l2_used, l2_size) = self.ctrl.data_access_manager.l2_cache.utilisation();
+            send_l2_util(format!("{0}/{1}", l2_used, l2_size));
+            send_cycle(self.cycle_count);
+        }
+
+        fn hex_addr(bits : Vec<bool>) -> String {
+            format!("{:012X}", Converter::bin_to_dec_pos_only(bits))
+        }
+
+        fn parse_hex(hex : &str, size : u8) -> Option<Vec<bool>> {
+            let value = u64::from_str_radix(hex, 16).ok()?;
+            Some(Converter::dec_to_bin_pos_only(value, size))
+        }
+
         fn parse_pipe_data(&mut self, data : String){
-            // WORK-IN-PROGRESS GUI IMPLEMENTATION
+            // Commands from the GUI - see gui_pipe.rs for the message grammar
             if data == "INC_CLK"{
-                self.clock_speed += 1;
+                // Steps of 1Hz, or doubling below 1Hz
+                self.set_speed(if self.clock_speed < 1.0 { (self.clock_speed * 2.0).min(1.0) } else { self.clock_speed + 1.0 });
             }
             else if data == "DEC_CLK"{
-                self.clock_speed -= 1;
+                self.set_speed(if self.clock_speed <= 1.0 { self.clock_speed / 2.0 } else { self.clock_speed - 1.0 });
+            }
+            else if data == "PAUSE"{
+                self.paused = true;
+                send_paused(self.paused);
+            }
+            else if data == "RESUME"{
+                self.paused = false;
+                send_paused(self.paused);
+            }
+            else if data == "STEP"{
+                if self.paused{ self.refresh(); }
             }
             else{
                 let mut split_str = data.split("//");
+                if split_str.clone().nth(0).unwrap() == "SET" && split_str.clone().count() == 3{
+                    let write_addr = Self::parse_hex(split_str.clone().nth(1).unwrap(), 48);
+                    let write_data = Self::parse_hex(split_str.clone().nth(2).unwrap(), 64);
+                    if let (Some(addr), Some(val)) = (write_addr, write_data){
+                        self.ctrl.data_access_manager.poke(addr.clone().try_into().unwrap(), val.clone().try_into().unwrap());
+                        send_memory(Self::hex_addr(addr), format!("{:016X}", Converter::bin_to_dec_pos_only(val)));
+                    }
+                }
                 if split_str.clone().count() != 2{ return; }
                 if split_str.clone().nth(0).unwrap() == "GET"{
-                    let read_addr = Converter::hex_val_to_bin(split_str.clone().nth(1).unwrap().to_string());
-                    let return_data_bin = self.ctrl.data_access_manager.main_memory.read(read_addr.try_into().unwrap());
-                    send_memory(split_str.clone().nth(1).unwrap().to_string(), Converter::bin_to_hex(return_data_bin.to_vec()));
+                    if let Some(read_addr) = Self::parse_hex(split_str.clone().nth(1).unwrap(), 48){
+                        let return_data_bin = self.ctrl.data_access_manager.main_memory.read(read_addr.clone().try_into().unwrap());
+                        send_memory(Self::hex_addr(read_addr), format!("{:016X}", Converter::bin_to_dec_pos_only(return_data_bin.to_vec())));
+                    }
                 }
                 else if split_str.clone().nth(0).unwrap() == "SET_CLK"{
-                    self.clock_speed = split_str.clone().nth(1).unwrap().to_string().parse().unwrap();
+                    match split_str.nth(1).unwrap() {
+                        "MAX" => { self.throttled = false; }
+                        speed => if let Ok(speed) = speed.parse(){ self.set_speed(speed); }
+                    }
                 }
             }
         }
     }
-}
\ No newline at end of file
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::RunOptions;
+        use crate::tests::{load_program, program_file};
+
+        #[test]
+        fn speed_lower_bound() {
+            let path = program_file("clock_speed", &["HLT"]);
+            let (mut clk, _) = load_program(&path, RunOptions::default());
+            std::fs::remove_file(&path).ok();
+            clk.parse_pipe_data("SET_CLK//1e-30".to_string());
+            assert_eq!(clk.clock_speed, MIN_CLOCK_SPEED);
+            clk.set_speed(1.0);
+            for _ in 0..100 { clk.parse_pipe_data("DEC_CLK".to_string()); }
+            assert_eq!(clk.clock_speed, MIN_CLOCK_SPEED);
+            assert!(clk.throttled);
+            assert!(Duration::try_from_secs_f64(1.0 / clk.clock_speed).is_ok());
+            clk.set_speed(0.0);
+            assert_eq!(clk.clock_speed, MIN_CLOCK_SPEED); // Ignored
+        }
+    }
+}
@@ -0,0 +1,100 @@
+pub(crate) mod clock_domains{
+    /*
+     * Clock domains - the CPU, main memory and the I/O devices each run from their own clock
+     *
+     * Every domain has a frequency (Hz) and a phase (degrees of its own period), and edge n of a domain
+     * falls at time (n + phase / 360) / frequency. The scheduler works in CPU cycles - for each CPU cycle it
+     * reports how many edges the other domains have before the next CPU edge, so a slower memory ticks less
+     * often than the CPU, and a faster one more than once. Since the edges only depend on the cycle count, there
+     * is no scheduler state to save in checkpoints or undo in the history
+     *
+     * Domains running at a different frequency or phase to the CPU are asynchronous to it, and every
+     * ControlBus handshake between them goes through a synchronizer - see buses.rs
+     */
+
+    const PICOSECONDS : u128 = 1_000_000_000_000;
+    pub(crate) const SYNC_STAGES : u8 = 2; // Flip-flops in each synchronizer
+
+    #[derive(Clone, Copy, PartialEq)]
+    pub(crate) struct ClockDomain {
+        pub(crate) frequency : u64, // Hz
+        pub(crate) phase : u64 // Degrees, 0-359
+    }
+
+    impl ClockDomain {
+        fn period(&self) -> u128 {
+            PICOSECONDS / self.frequency as u128
+        }
+
+        fn offset(&self) -> u128 {
+            self.period() * self.phase as u128 / 360
+        }
+
+        fn edge(&self, n : u64) -> u128 {
+            // Time of edge n, in picoseconds
+            self.offset() + n as u128 * self.period()
+        }
+
+        fn edges_before(&self, time : u128) -> u64 {
+            // Number of edges strictly before the given time
+            if time <= self.offset() { return 0; }
+            (time - self.offset()).div_ceil(self.period()) as u64
+        }
+
+        pub fn parse(spec : &str) -> Option<ClockDomain> {
+            // <hz>[:<phase>] - the frequency may have a k/M/G suffix, e.g. 50M
+            let (frequency, phase) = match spec.split_once(':') {
+                Some((frequency, phase)) => (frequency, phase.parse().ok().filter(|phase| *phase < 360)?),
+                None => (spec, 0)
+            };
+            let (digits, multiplier) = match frequency.chars().last()? {
+                'k' | 'K' => (&frequency[..frequency.len() - 1], 1_000),
+                'M' => (&frequency[..frequency.len() - 1], 1_000_000),
+                'G' => (&frequency[..frequency.len() - 1], 1_000_000_000),
+                _ => (frequency, 1)
+            };
+            let frequency = digits.parse::<u64>().ok()?.checked_mul(multiplier).filter(|frequency| *frequency > 0 && *frequency as u128 <= PICOSECONDS)?;
+            Some(ClockDomain { frequency, phase })
+        }
+    }
+
+    impl Default for ClockDomain {
+        fn default() -> Self { ClockDomain { frequency: 100_000_000, phase: 0 } }
+    }
+
+    #[derive(Default)]
+    pub(crate) struct DomainScheduler {
+        pub(crate) cpu : ClockDomain,
+        pub(crate) memory : ClockDomain,
+        pub(crate) io : ClockDomain // Memory-mapped devices
+    }
+
+    impl DomainScheduler {
+
+        pub fn edges(&self, domain : &ClockDomain, cycle : u64) -> u64 {
+            // Edges of the domain from the start of CPU cycle 'cycle' up to, but not including, the next one
+            // A domain with an edge at the same time as the CPU runs after it
+            domain.edges_before(self.cpu.edge(cycle + 1)) - domain.edges_before(self.cpu.edge(cycle))
+        }
+
+        pub fn memory_cycle(&self, cycle : u64) -> u64 {
+            // Number of the first memory clock edge at or after the start of the CPU cycle
+            self.memory.edges_before(self.cpu.edge(cycle))
+        }
+
+        pub fn sync_stages(&self) -> u8 {
+            // Synchronizer length for the CPU <-> memory handshake - none if both share a clock
+            if self.memory == self.cpu { 0 } else { SYNC_STAGES }
+        }
+
+        pub fn set(&mut self, name : &str, domain : ClockDomain) -> bool {
+            match name {
+                "cpu" => self.cpu = domain,
+                "memory" => self.memory = domain,
+                "io" => self.io = domain,
+                _ => return false
+            }
+            true
+        }
+    }
+}
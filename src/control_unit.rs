@@ -1,13 +1,19 @@
 pub(crate) mod control_unit{
-    use std::fs::File;
-    use std::io::Write;
+    use std::cmp::Ordering;
     use num_derive::FromPrimitive;
     use crate::reg64::reg64::Reg64;
     use crate::reg_bank::reg_bank::RegBank;
     use crate::caches::caches::DataAccessManager;
     use crate::assembler::assembler::{BranchConditions, InstrType, ParsedInstruction};
+    use crate::disassembler::disassembler::Disassembler;
     use crate::converter::converter::Converter;
     use crate::alu::alu::Alu;
+    use crate::fpu::fpu::Fpu;
+    use crate::simd::simd::{Saturation, Simd};
+    use crate::system_regs::system_regs::{PrivilegeLevel, SystemRegister, SystemRegs, TrapCause};
+    use crate::devices::devices::ConsoleInput;
+    use crate::output_sink::output_sink::OutputSink;
+    use crate::main_memory::main_memory::Addressing;
     use num_traits::FromPrimitive;
     use crate::vec_to_str;
 
@@ -23,6 +29,8 @@ pub(crate) mod control_unit{
 
     pub(crate) struct ControlUnit {
         pub(crate) alu : Alu,
+        pub(crate) fpu : Fpu,
+        pub(crate) simd : Simd,
         pub(crate) memory_instr_reg : Reg64,
         pub(crate) memory_instr_stall : bool,
         pub(crate) memory_data_reg : Reg64,
@@ -30,21 +38,115 @@ pub(crate) mod control_unit{
         pub(crate) pc: Reg64,
         pub(crate) register_bank : RegBank,
         pub(crate) halt : bool,
+        pub(crate) faulted : bool, // The last instruction trapped before it could complete, so it never retired
         pub(crate) data_access_manager: DataAccessManager,
         pub(crate) state : CpuState,
-        pub(crate) decoded_instruction : ParsedInstruction
+        pub(crate) decoded_instruction : ParsedInstruction,
+        pub(crate) privilege : PrivilegeLevel,
+        pub(crate) system_regs : SystemRegs,
+        pub(crate) console_input : ConsoleInput,
+        pub(crate) output : Box<dyn OutputSink>
     }
 
     impl ControlUnit {
 
+        fn user_fault(&self, addr : [bool; 48]) -> bool {
+            // User mode accesses outside of [UBASE, ULIMIT) fault
+            self.privilege == PrivilegeLevel::User && !self.system_regs.user_access_allowed(addr)
+        }
+
+        fn addressing(&self) -> Addressing {
+            self.data_access_manager.main_memory.addressing
+        }
+
+        fn access_allowed(&mut self) -> bool {
+            // Loads and stores must be aligned to their size, and within bounds in User mode - otherwise they trap
+            let addr = self.decoded_instruction.addr;
+            if !self.addressing().aligned(addr, self.decoded_instruction.access_size){
+                self.fault(TrapCause::MisalignedAccess, Converter::bit48_to64(addr));
+                return false;
+            }
+            if self.user_fault(addr){
+                self.fault(TrapCause::AccessFault, Converter::bit48_to64(addr));
+                return false;
+            }
+            true
+        }
+
+        fn immediate(&self, field : [bool; 16]) -> [bool; 64] {
+            // Widens a 16-bit immediate - sign extended for arithmetic, zero extended for bitwise instructions
+            if self.decoded_instruction.instr_type.signed_immediates() { Converter::sign_extend(field.to_vec(), 64).try_into().unwrap() }
+            else { Converter::set_size(field.to_vec(), 64).try_into().unwrap() }
+        }
+
+        fn load_value(&self) -> [bool; 64] {
+            // The bytes LDR asked for, out of the word in the MDR
+            let instr = &self.decoded_instruction;
+            self.addressing().extract(self.memory_data_reg.get_data(), instr.addr, instr.access_size, instr.signed)
+        }
+
+        fn store_word(&mut self) -> [bool; 64] {
+            // The word STR writes back - the register, or for narrower stores, the word in the MDR with the register's low bytes merged in
+            let value = self.register_bank.get_data(self.decoded_instruction.return_register);
+            let instr = &self.decoded_instruction;
+            if instr.access_size == 8 { return value; }
+            self.addressing().insert(self.memory_data_reg.get_data(), instr.addr, instr.access_size, value)
+        }
+
+        fn rewind_pc(&mut self) -> u64 {
+            // Back to the instruction just fetched - the PC moves on by a word as each one is fetched
+            let addr = Converter::bin_to_dec_pos_only(self.pc.get_data().to_vec()).wrapping_sub(self.addressing().word_size());
+            self.pc.set_data(Converter::dec_to_bin_pos_only(addr, 64).try_into().unwrap());
+            addr
+        }
+
+        fn fault(&mut self, cause : TrapCause, value : [bool; 64]){
+            // A trap caused by the instruction being executed - ELR is the instruction itself, so the handler can find it or retry it
+            self.rewind_pc();
+            self.trap(cause, value);
+        }
+
+        pub fn trap(&mut self, cause : TrapCause, value : [bool; 64]){
+            // Enter Supervisor mode at the trap vector, saving the return address and the previous mode
+            // ELR holds the PC at the time of the trap - for SYSCALL and interrupts, this is the following instruction
+            self.system_regs.exception_link.set_data(self.pc.get_data());
+            self.faulted = !matches!(cause, TrapCause::Syscall | TrapCause::Interrupt);
+            self.system_regs.trap_cause.set_data(Converter::dec_to_bin_pos_only(cause as u64, 64).try_into().unwrap());
+            self.system_regs.trap_value.set_data(value);
+            self.system_regs.saved_privilege = self.privilege;
+            self.system_regs.saved_interrupt_enable = self.system_regs.interrupt_enable;
+            self.system_regs.interrupt_enable = false;
+            self.privilege = PrivilegeLevel::Supervisor;
+            self.pc.set_data(self.system_regs.trap_vector.get_data());
+            self.state = CpuState::Fetch;
+        }
+
         pub fn tick(&mut self){ // Called by clock
 
             match self.state{
 
                 CpuState::Fetch => {
 
+                    self.faulted = false;
+
+                    // Interrupts are only taken between instructions
+                    if self.system_regs.interrupt_enable
+                        && let Some(device_base) = self.data_access_manager.main_memory.address_decoder.irq_pending() {
+                        self.trap(TrapCause::Interrupt, Converter::bit48_to64(device_base));
+                        return;
+                    }
+
                     let mut read_addr = [false; 48];
                     read_addr[0..48].copy_from_slice(&self.pc.get_data()[0..48]);
+                    if !self.addressing().aligned(read_addr, 8){
+                        self.trap(TrapCause::MisalignedAccess, Converter::bit48_to64(read_addr));
+                        return;
+                    }
+                    if self.user_fault(read_addr){
+                        self.trap(TrapCause::AccessFault, Converter::bit48_to64(read_addr));
+                        return;
+                    }
+                    if self.data_access_manager.bus_busy(){ return; } // A store is still crossing to memory - try again next cycle
                     let (data, cache_hit) = self.data_access_manager.read(read_addr); // Read from cache where possible
 
 
@@ -57,10 +159,9 @@ pub(crate) mod control_unit{
                         self.state = CpuState::Stall // Wait for instruction in lieu of a cache hit
                     }
 
-                    // Increment PC using ALU
-                    let mut one_bit : [bool; 64] = [false; 64];
-                    one_bit[6] = true;
-                    let (increment, carry_out) = self.alu.add(self.pc.get_data(), one_bit, true);
+                    // Increment PC using ALU - by one word
+                    let word_size : [bool; 64] = Converter::dec_to_bin_pos_only(self.addressing().word_size(), 64).try_into().unwrap();
+                    let (increment, carry_out) = self.alu.add(self.pc.get_data(), word_size, true);
                     if carry_out{ self.pc.set_data([false; 64]); }
                     else{ self.pc.set_data(increment); }
 
@@ -85,54 +186,7 @@ pub(crate) mod control_unit{
                         self.state = CpuState::Fetch;
                     }
                     else {
-                        let parsed_type = FromPrimitive::from_i8(Converter::bin_to_dec_pos_only(mdr_data[0..4].to_vec()).to_string().parse().unwrap());
-
-                        if parsed_type.is_none() { self.decoded_instruction.instr_type = InstrType::OTH; } else { self.decoded_instruction.instr_type = parsed_type.unwrap(); }
-
-                        match self.decoded_instruction.instr_type {
-                            InstrType::ADD | InstrType::SUB | InstrType::MULT | InstrType::AND | InstrType::OR | InstrType::XOR => {
-                                self.decoded_instruction.return_register.copy_from_slice(&mdr_data[4..8]);
-                                self.decoded_instruction.reg_0 = mdr_data[8];
-                                self.decoded_instruction.input_val_0.copy_from_slice(&mdr_data[9..25]);
-                                self.decoded_instruction.reg_1 = mdr_data[25];
-                                self.decoded_instruction.input_val_1.copy_from_slice(&mdr_data[26..42]);
-                            }
-
-                            InstrType::NOT | InstrType::FLIP => {
-                                self.decoded_instruction.return_register.copy_from_slice(&mdr_data[4..8]);
-                                self.decoded_instruction.reg_0 = mdr_data[8];
-                                self.decoded_instruction.input_val_0.copy_from_slice(&mdr_data[9..25]);
-                            }
-
-                            InstrType::CMP => {
-                                self.decoded_instruction.reg_0 = mdr_data[4];
-                                self.decoded_instruction.input_val_0.copy_from_slice(&mdr_data[5..21]);
-                                self.decoded_instruction.reg_1 = mdr_data[21];
-                                self.decoded_instruction.input_val_1.copy_from_slice(&mdr_data[22..38]);
-                            }
-
-                            InstrType::STR | InstrType::LDR => {
-                                self.decoded_instruction.return_register.copy_from_slice(&mdr_data[4..8]);
-                                let mut addr_data: [bool; 48] = [false; 48];
-                                addr_data.copy_from_slice(&mdr_data[8..56]);
-                                self.decoded_instruction.addr = addr_data;
-                            }
-
-                            InstrType::B => {
-                                self.decoded_instruction.branch_condition = FromPrimitive::from_u64(Converter::bin_to_dec_pos_only(mdr_data[4..8].to_vec())).unwrap();
-                                let mut addr_data: [bool; 48] = [false; 48];
-                                self.decoded_instruction.reg_0 = mdr_data[8];
-                                addr_data.copy_from_slice(&mdr_data[9..57]);
-                                self.decoded_instruction.addr = addr_data;
-                            }
-
-                            InstrType::OUT => {
-                                self.decoded_instruction.return_register.copy_from_slice(&mdr_data[4..8]);
-                                self.decoded_instruction.ascii = mdr_data[8];
-                            }
-
-                            _ => {}
-                        }
+                        self.decoded_instruction = Disassembler::decode(mdr_data);
                         self.state = CpuState::Execute;
                     }
                 },
@@ -141,8 +195,13 @@ pub(crate) mod control_unit{
 
                     // Execute instruction based on intermediate representation, calling on relevant cpu components
 
+                    if self.privilege == PrivilegeLevel::User && self.decoded_instruction.instr_type.privileged(){
+                        self.fault(TrapCause::PrivilegedInstruction, self.memory_instr_reg.get_data());
+                        return;
+                    }
+
                     match self.decoded_instruction.instr_type.clone() {
-                        InstrType::ADD|InstrType::SUB|InstrType::MULT|InstrType::AND|InstrType::OR|InstrType::XOR => {
+                        InstrType::ADD|InstrType::SUB|InstrType::MULT|InstrType::AND|InstrType::OR|InstrType::XOR|InstrType::ADC|InstrType::SBC|InstrType::CMP => {
 
                             let mut val0 : [bool; 64] = [false; 64];
                             if self.decoded_instruction.reg_0{
@@ -151,7 +210,7 @@ pub(crate) mod control_unit{
                                 val0 = Converter::set_size(self.register_bank.get_data(reg_index).to_vec(), 64).try_into().unwrap();
                             }
                             else{
-                                val0 = Converter::set_size(self.decoded_instruction.input_val_0.to_vec(), 64).try_into().unwrap();
+                                val0 = self.immediate(self.decoded_instruction.input_val_0);
                             }
                             let mut val1 : [bool; 64] = [false; 64];
                             if self.decoded_instruction.reg_1{
@@ -160,7 +219,7 @@ pub(crate) mod control_unit{
                                 val1 = Converter::set_size(self.register_bank.get_data(reg_index).to_vec(), 64).try_into().unwrap();
                             }
                             else{
-                                val1 = Converter::set_size(self.decoded_instruction.input_val_1.to_vec(), 64).try_into().unwrap();
+                                val1 = self.immediate(self.decoded_instruction.input_val_1);
                             }
 
                             match self.decoded_instruction.instr_type.clone() {
@@ -170,6 +229,15 @@ pub(crate) mod control_unit{
                                 InstrType::SUB => {
                                     self.register_bank.set_data(self.decoded_instruction.return_register, self.alu.sub(val0, val1).0);
                                 },
+                                InstrType::CMP => { // Subtracts for the flags only - the result is discarded
+                                    self.alu.sub(val0, val1);
+                                },
+                                InstrType::ADC => {
+                                    self.register_bank.set_data(self.decoded_instruction.return_register, self.alu.adc(val0, val1).0);
+                                },
+                                InstrType::SBC => {
+                                    self.register_bank.set_data(self.decoded_instruction.return_register, self.alu.sbc(val0, val1).0);
+                                },
                                 InstrType::MULT => {
                                     self.register_bank.set_data(self.decoded_instruction.return_register, self.alu.mult(val0, val1));
                                 },
@@ -191,7 +259,7 @@ pub(crate) mod control_unit{
                                 val0 = Converter::set_size(self.register_bank.get_data(reg_index).to_vec(), 64).try_into().unwrap();
                             }
                             else{
-                                val0 = Converter::set_size(self.decoded_instruction.input_val_0.to_vec(), 64).try_into().unwrap();
+                                val0 = self.immediate(self.decoded_instruction.input_val_0);
                             }
 
                             let op_bits : [bool; 4] = Converter::dec_to_bin_pos_only(self.decoded_instruction.instr_type.clone() as u64, 4).try_into().unwrap();
@@ -208,7 +276,7 @@ pub(crate) mod control_unit{
                                 val0 = self.register_bank.get_data(reg_index);
                             }
                             else{
-                                val0 = Converter::set_size(self.decoded_instruction.input_val_0.to_vec(), 64).try_into().unwrap();
+                                val0 = self.immediate(self.decoded_instruction.input_val_0);
                             }
 
                             let mut single_bit = [false; 64];
@@ -226,18 +294,40 @@ pub(crate) mod control_unit{
                         },
 
                         InstrType::STR => {
-                            self.data_access_manager.write(self.decoded_instruction.addr, self.register_bank.get_data(self.decoded_instruction.return_register));
-                            self.state = CpuState::Fetch;
+                            if !self.access_allowed(){ return; }
+                            if self.data_access_manager.bus_busy(){ return; }
+                            let word_addr = self.addressing().word_start(self.decoded_instruction.addr);
+                            if self.decoded_instruction.access_size == 8{
+                                let value = self.store_word();
+                                self.data_access_manager.write(word_addr, value);
+                                self.state = CpuState::Fetch;
+                                return;
+                            }
+                            // Narrower stores read the rest of the word first, and write it back in MemoryComp
+                            let (data, cache_hit) = self.data_access_manager.read(word_addr);
+                            if !cache_hit{
+                                self.memory_data_stall = true;
+                                self.state = CpuState::Stall;
+                            }
+                            else{
+                                self.memory_data_reg.set_data(data);
+                                let value = self.store_word();
+                                self.data_access_manager.write(word_addr, value);
+                                self.state = CpuState::Fetch;
+                            }
                         },
 
                         InstrType::LDR => {
-                            let (data, cache_hit) = self.data_access_manager.read(self.decoded_instruction.addr);
+                            if !self.access_allowed(){ return; }
+                            if self.data_access_manager.bus_busy(){ return; }
+                            let (data, cache_hit) = self.data_access_manager.read(self.addressing().word_start(self.decoded_instruction.addr));
                             if !cache_hit{
-                                self.state = CpuState::Stall;
+                                self.memory_data_stall = true;
+                                self.state = CpuState::Stall; // Wait for data in lieu of a cache hit
                             }
                             else{
                                 self.memory_data_reg.set_data(data);
-                                self.register_bank.set_data(self.decoded_instruction.return_register, self.memory_data_reg.get_data());
+                                self.register_bank.set_data(self.decoded_instruction.return_register, self.load_value());
                                 self.state = CpuState::Fetch;
                             }
 
@@ -252,10 +342,18 @@ pub(crate) mod control_unit{
                                 BranchConditions::B => {valid_branch = true;}, // Branch Always
                                 BranchConditions::BEQ => { if self.alu.z { valid_branch = true; } }, // Branch if Equal
                                 BranchConditions::BNE => { if !self.alu.z { valid_branch = true; } }, // Branch if Not Equal
-                                BranchConditions::BLT => { if self.alu.n { valid_branch = true; } }, // Branch if Less Than
-                                BranchConditions::BGT => { if !self.alu.z && !self.alu.n { valid_branch = true; } }, // Branch if Greater Than
-                                BranchConditions::BLE => { if self.alu.z || self.alu.n { valid_branch = true; } }, // Branch if Less Than or Equal
-                                BranchConditions::BGE => { if self.alu.z || !self.alu.n { valid_branch = true; } }, // Branch if Greater Than or Equal
+                                BranchConditions::BLT => { if self.alu.n != self.alu.v { valid_branch = true; } }, // Branch if Less Than
+                                BranchConditions::BGT => { if !self.alu.z && self.alu.n == self.alu.v { valid_branch = true; } }, // Branch if Greater Than
+                                BranchConditions::BLE => { if self.alu.z || self.alu.n != self.alu.v { valid_branch = true; } }, // Branch if Less Than or Equal
+                                BranchConditions::BGE => { if self.alu.n == self.alu.v { valid_branch = true; } }, // Branch if Greater Than or Equal
+                                BranchConditions::BLO => { if !self.alu.c { valid_branch = true; } }, // Branch if Lower (unsigned)
+                                BranchConditions::BHS => { if self.alu.c { valid_branch = true; } }, // Branch if Higher or Same (unsigned)
+                                BranchConditions::BHI => { if self.alu.c && !self.alu.z { valid_branch = true; } }, // Branch if Higher (unsigned)
+                                BranchConditions::BLS => { if !self.alu.c || self.alu.z { valid_branch = true; } }, // Branch if Lower or Same (unsigned)
+                                BranchConditions::BVS => { if self.alu.v { valid_branch = true; } }, // Branch if Overflow Set
+                                BranchConditions::BVC => { if !self.alu.v { valid_branch = true; } }, // Branch if Overflow Clear
+                                BranchConditions::BCS => { if self.alu.c { valid_branch = true; } }, // Branch if Carry Set
+                                BranchConditions::BCC => { if !self.alu.c { valid_branch = true; } }, // Branch if Carry Clear
                                 _ => {}
                             }
                             if valid_branch {
@@ -283,31 +381,209 @@ pub(crate) mod control_unit{
 
                         InstrType::OUT => {
 
+                            // Written to the output sink - stdout, a file, or an in-memory buffer
                             let output_val= self.register_bank.get_data(self.decoded_instruction.return_register);
-                            if (self.decoded_instruction.ascii){
+                            if self.decoded_instruction.ascii{
                                 if 0 < Converter::bin_to_dec_2s_comp(output_val.to_vec()) && Converter::bin_to_dec_2s_comp(output_val.to_vec()) < 127{
-                                    print!("{}", char::from_u32(Converter::bin_to_dec_2s_comp(output_val.to_vec()) as u32).unwrap());
+                                    self.output.write_str(&char::from_u32(Converter::bin_to_dec_2s_comp(output_val.to_vec()) as u32).unwrap().to_string());
                                 }
                                 else{
-                                    print!("<INV>");
+                                    self.output.write_str("<INV>");
                                 }
                             }
                             else{
-                                println!("R{0} OUTPUT: {1}", Converter::bin_to_dec_pos_only(self.decoded_instruction.return_register.to_vec()), Converter::bin_to_dec_2s_comp(output_val.to_vec()));
+                                self.output.write_str(&format!("R{0} OUTPUT: {1}\n", Converter::bin_to_dec_pos_only(self.decoded_instruction.return_register.to_vec()), Converter::bin_to_dec_2s_comp(output_val.to_vec())));
                             }
-                            /*
-                            if self.decoded_instruction.ascii && Converter::bin_to_dec_2s_comp(output_val.to_vec()) >= 0 && Converter::bin_to_dec_2s_comp(output_val.to_vec()) <= 128{
-                                let mut file = File::open("./output.txt").expect("Output File Error");
-                                //file.write(String::from(char::from_u32(Converter::bin_to_dec_2s_comp(output_val.to_vec()) as u32).unwrap()).as_ref()).expect("Error Writing Output");
+                            self.state = CpuState::Fetch;
+                        },
+
+                        InstrType::SYSCALL => {
+                            // Trap into Supervisor mode, passing the syscall number in TVAL
+                            let syscall_number : [bool; 64] = Converter::set_size(self.decoded_instruction.input_val_0.to_vec(), 64).try_into().unwrap();
+                            self.trap(TrapCause::Syscall, syscall_number);
+                        },
+
+                        InstrType::SRET => {
+                            // Return from a trap, restoring the previous mode and interrupt enable
+                            self.pc.set_data(self.system_regs.exception_link.get_data());
+                            self.privilege = self.system_regs.saved_privilege;
+                            self.system_regs.interrupt_enable = self.system_regs.saved_interrupt_enable;
+                            self.state = CpuState::Fetch;
+                        },
+
+                        InstrType::MFS => {
+                            let sys_reg : SystemRegister = FromPrimitive::from_u64(Converter::bin_to_dec_pos_only(self.decoded_instruction.input_val_0[0..4].to_vec())).unwrap_or(SystemRegister::OTH);
+                            self.register_bank.set_data(self.decoded_instruction.return_register, self.system_regs.get_data(sys_reg));
+                            self.state = CpuState::Fetch;
+                        },
+
+                        InstrType::MTS => {
+                            let sys_reg : SystemRegister = FromPrimitive::from_u64(Converter::bin_to_dec_pos_only(self.decoded_instruction.input_val_0[0..4].to_vec())).unwrap_or(SystemRegister::OTH);
+                            self.system_regs.set_data(sys_reg, self.register_bank.get_data(self.decoded_instruction.return_register));
+                            self.state = CpuState::Fetch;
+                        },
+
+                        InstrType::IN => {
+                            // Reads a character (ASCII) or a decimal integer - -1 at end of input
+                            // Remains in the Execute state until input is available
+                            let input_val = if self.decoded_instruction.ascii { self.console_input.read_char() } else { self.console_input.read_integer() };
+                            if let Some(value) = input_val {
+                                self.register_bank.set_data(self.decoded_instruction.return_register, Converter::dec_to_bin_2s_comp(value));
                                 self.state = CpuState::Fetch;
                             }
+                        },
+
+                        InstrType::MOVZ | InstrType::MOVK | InstrType::MOVN => {
+                            // Moves 16 bits into the register - MOVZ clears the rest, MOVN sets the rest and inverts the 16 bits, MOVK keeps the rest
+                            let shift = Converter::bin_to_dec_pos_only(self.decoded_instruction.input_val_1[0..2].to_vec()) * 16;
+                            let immediate = Converter::bin_to_dec_pos_only(self.decoded_instruction.input_val_0.to_vec()) << shift;
+                            let value = match self.decoded_instruction.instr_type {
+                                InstrType::MOVZ => immediate,
+                                InstrType::MOVN => !immediate,
+                                _ => {
+                                    let current = Converter::bin_to_dec_pos_only(self.register_bank.get_data(self.decoded_instruction.return_register).to_vec());
+                                    current & !(0xFFFF << shift) | immediate
+                                }
+                            };
+                            self.register_bank.set_data(self.decoded_instruction.return_register, Converter::dec_to_bin_pos_only(value, 64).try_into().unwrap());
+                            self.state = CpuState::Fetch;
+                        },
+
+                        InstrType::FADD | InstrType::FSUB | InstrType::FMUL | InstrType::FDIV | InstrType::FSQRT => {
+                            let mut reg_index = [[false; 4]; 2];
+                            reg_index[0].copy_from_slice(&self.decoded_instruction.input_val_0[0..4]);
+                            reg_index[1].copy_from_slice(&self.decoded_instruction.input_val_1[0..4]);
+                            let (val0, val1) = (self.fpu.get_data(reg_index[0]), self.fpu.get_data(reg_index[1]));
+                            let result = match self.decoded_instruction.instr_type {
+                                InstrType::FADD => self.fpu.add(val0, val1),
+                                InstrType::FSUB => self.fpu.sub(val0, val1),
+                                InstrType::FMUL => self.fpu.mul(val0, val1),
+                                InstrType::FDIV => self.fpu.div(val0, val1),
+                                _ => self.fpu.sqrt(val0)
+                            };
+                            self.fpu.set_data(self.decoded_instruction.return_register, result);
+                            self.state = CpuState::Fetch;
+                        },
+
+                        InstrType::FCMP => {
+                            // Sets the integer flags, so the usual branches follow it - unordered (a NaN) sets C and V
+                            let mut reg_index = [[false; 4]; 2];
+                            reg_index[0].copy_from_slice(&self.decoded_instruction.input_val_0[0..4]);
+                            reg_index[1].copy_from_slice(&self.decoded_instruction.input_val_1[0..4]);
+                            let (val0, val1) = (self.fpu.get_data(reg_index[0]), self.fpu.get_data(reg_index[1]));
+                            (self.alu.n, self.alu.z, self.alu.c, self.alu.v) = match self.fpu.compare(val0, val1) {
+                                Some(Ordering::Less) => (true, false, false, false),
+                                Some(Ordering::Equal) => (false, true, true, false),
+                                Some(Ordering::Greater) => (false, false, true, false),
+                                None => (false, false, true, true)
+                            };
+                            self.state = CpuState::Fetch;
+                        },
+
+                        InstrType::FMOV | InstrType::FMOVI | InstrType::ITOF | InstrType::FTOI => {
+                            // Moves between register files copy the bits - ITOF and FTOI convert the value
+                            let mut reg_index = [false; 4];
+                            reg_index.copy_from_slice(&self.decoded_instruction.input_val_0[0..4]);
+                            match self.decoded_instruction.instr_type {
+                                InstrType::FMOV => {
+                                    let value = if self.decoded_instruction.reg_0 { self.register_bank.get_data(reg_index) } else { self.fpu.get_data(reg_index) };
+                                    if self.decoded_instruction.reg_1 { self.register_bank.set_data(self.decoded_instruction.return_register, value); }
+                                    else { self.fpu.set_data(self.decoded_instruction.return_register, value); }
+                                },
+                                InstrType::FMOVI => {
+                                    let mut value = [false; 64];
+                                    value[48..64].copy_from_slice(&self.decoded_instruction.input_val_0);
+                                    self.fpu.set_data(self.decoded_instruction.return_register, value);
+                                },
+                                InstrType::ITOF => {
+                                    let value = self.fpu.int_to_float(self.register_bank.get_data(reg_index));
+                                    self.fpu.set_data(self.decoded_instruction.return_register, value);
+                                },
+                                _ => {
+                                    let value = self.fpu.float_to_int(self.fpu.get_data(reg_index));
+                                    self.register_bank.set_data(self.decoded_instruction.return_register, value);
+                                }
+                            }
+                            self.state = CpuState::Fetch;
+                        },
+
+                        InstrType::FRSR => {
+                            self.register_bank.set_data(self.decoded_instruction.return_register, self.fpu.get_status());
+                            self.state = CpuState::Fetch;
+                        },
+
+                        InstrType::FWSR => {
+                            self.fpu.set_status(self.register_bank.get_data(self.decoded_instruction.return_register));
+                            self.state = CpuState::Fetch;
+                        },
+
+                        InstrType::VADD | InstrType::VSUB | InstrType::VMUL | InstrType::VQADD | InstrType::VQSUB | InstrType::VUQADD | InstrType::VUQSUB |
+                        InstrType::VAND | InstrType::VOR | InstrType::VXOR => {
+                            let mut reg_index = [[false; 4]; 2];
+                            reg_index[0].copy_from_slice(&self.decoded_instruction.input_val_0[0..4]);
+                            reg_index[1].copy_from_slice(&self.decoded_instruction.input_val_1[0..4]);
+                            let (val0, val1) = (self.simd.get_data(reg_index[0]), self.simd.get_data(reg_index[1]));
+                            let lanes = self.decoded_instruction.access_size;
+                            let result = match self.decoded_instruction.instr_type {
+                                InstrType::VADD => self.simd.add_sub(val0, val1, lanes, true, Saturation::Wrap),
+                                InstrType::VSUB => self.simd.add_sub(val0, val1, lanes, false, Saturation::Wrap),
+                                InstrType::VQADD => self.simd.add_sub(val0, val1, lanes, true, Saturation::Signed),
+                                InstrType::VQSUB => self.simd.add_sub(val0, val1, lanes, false, Saturation::Signed),
+                                InstrType::VUQADD => self.simd.add_sub(val0, val1, lanes, true, Saturation::Unsigned),
+                                InstrType::VUQSUB => self.simd.add_sub(val0, val1, lanes, false, Saturation::Unsigned),
+                                InstrType::VMUL => self.simd.mul(val0, val1, lanes),
+                                InstrType::VAND => self.simd.bitwise(val0, val1, InstrType::AND),
+                                InstrType::VOR => self.simd.bitwise(val0, val1, InstrType::OR),
+                                _ => self.simd.bitwise(val0, val1, InstrType::XOR)
+                            };
+                            self.simd.set_data(self.decoded_instruction.return_register, result);
+                            self.state = CpuState::Fetch;
+                        },
+
+                        InstrType::VDUP | InstrType::VADDV | InstrType::VMOV => {
+                            let mut reg_index = [false; 4];
+                            reg_index.copy_from_slice(&self.decoded_instruction.input_val_0[0..4]);
+                            let lanes = self.decoded_instruction.access_size;
+                            match self.decoded_instruction.instr_type {
+                                InstrType::VDUP => {
+                                    let value = self.simd.duplicate(self.register_bank.get_data(reg_index), lanes);
+                                    self.simd.set_data(self.decoded_instruction.return_register, value);
+                                },
+                                InstrType::VADDV => {
+                                    let value = self.simd.reduce(self.simd.get_data(reg_index), lanes);
+                                    self.register_bank.set_data(self.decoded_instruction.return_register, value);
+                                },
+                                _ => {
+                                    let value = if self.decoded_instruction.reg_0 { self.register_bank.get_data(reg_index) } else { self.simd.get_data(reg_index) };
+                                    if self.decoded_instruction.reg_1 { self.register_bank.set_data(self.decoded_instruction.return_register, value); }
+                                    else { self.simd.set_data(self.decoded_instruction.return_register, value); }
+                                }
+                            }
+                            self.state = CpuState::Fetch;
+                        },
+
+                        InstrType::VSTR => {
+                            // Whole words only, so no read is needed first
+                            if !self.access_allowed(){ return; }
+                            if self.data_access_manager.bus_busy(){ return; }
+                            let word_addr = self.addressing().word_start(self.decoded_instruction.addr);
+                            self.data_access_manager.write(word_addr, self.simd.get_data(self.decoded_instruction.return_register));
+                            self.state = CpuState::Fetch;
+                        },
+
+                        InstrType::VLDR => {
+                            if !self.access_allowed(){ return; }
+                            if self.data_access_manager.bus_busy(){ return; }
+                            let (data, cache_hit) = self.data_access_manager.read(self.addressing().word_start(self.decoded_instruction.addr));
+                            if !cache_hit{
+                                self.memory_data_stall = true;
+                                self.state = CpuState::Stall;
+                            }
                             else{
-                                let mut file = File::open("./output.txt").expect("Output File Error");
-                                //file.write(Converter::bin_to_dec_2s_comp(output_val.to_vec()).to_string().as_ref()).expect("Error Writing Output");
+                                self.memory_data_reg.set_data(data);
+                                self.simd.set_data(self.decoded_instruction.return_register, self.load_value());
                                 self.state = CpuState::Fetch;
                             }
-                            */
-                            self.state = CpuState::Fetch;
                         },
 
                         _ => {}
@@ -318,7 +594,14 @@ pub(crate) mod control_unit{
                 CpuState::Stall => {
                     // Stall state, waiting for memory
                     let (ready, data_bits) = self.data_access_manager.stall_read();
-                    if ready{
+                    if ready && self.data_access_manager.take_bus_error(){
+                        // Uncorrectable memory error - for an instruction, ELR is the instruction itself, so it can be retried
+                        let fault_addr = if self.memory_instr_stall { self.rewind_pc() } else { Converter::bin_to_dec_pos_only(self.decoded_instruction.addr.to_vec()) };
+                        self.memory_instr_stall = false;
+                        self.memory_data_stall = false;
+                        self.trap(TrapCause::MachineCheck, Converter::dec_to_bin_pos_only(fault_addr, 64).try_into().unwrap());
+                    }
+                    else if ready{
                         if self.memory_instr_stall{
                             // If waiting for an instruction
                             self.memory_instr_stall = false;
@@ -335,10 +618,177 @@ pub(crate) mod control_unit{
                 },
                 
                 CpuState::MemoryComp => {
-                    self.register_bank.set_data(self.decoded_instruction.return_register, self.memory_data_reg.get_data());
+                    if let InstrType::STR = self.decoded_instruction.instr_type{
+                        let word_addr = self.addressing().word_start(self.decoded_instruction.addr);
+                        let value = self.store_word();
+                        self.data_access_manager.write(word_addr, value);
+                    }
+                    else if let InstrType::VLDR = self.decoded_instruction.instr_type{
+                        self.simd.set_data(self.decoded_instruction.return_register, self.load_value());
+                    }
+                    else{
+                        self.register_bank.set_data(self.decoded_instruction.return_register, self.load_value());
+                    }
                     self.state = CpuState::Fetch;
                 }
             }
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::RunOptions;
+        use crate::clock::clock::Clock;
+        use crate::tests::{load_program, program_file};
+
+        fn run(name : &str, lines : &[&str], addressing : Addressing) -> Clock {
+            // Runs the program until it halts - a program which goes wrong and loops is stopped after 100000 cycles
+            let path = program_file(name, lines);
+            let (mut clk, _) = load_program(&path, RunOptions { addressing, ..RunOptions::default() });
+            std::fs::remove_file(&path).ok();
+            while !clk.ctrl.halt && clk.cycle_count < 100_000 { clk.refresh(); }
+            assert!(clk.ctrl.halt, "{0} did not halt", name);
+            clk
+        }
+
+        fn reg(clk : &mut Clock, reg : u64) -> u64 {
+            Converter::bin_to_dec_pos_only(clk.ctrl.register_bank.get_data(Converter::dec_to_bin_pos_only(reg, 4).try_into().unwrap()).to_vec())
+        }
+
+        fn sys(clk : &Clock, reg : SystemRegister) -> u64 {
+            Converter::bin_to_dec_pos_only(clk.ctrl.system_regs.get_data(reg).to_vec())
+        }
+
+        // Sets the trap vector to 0x280 and drops to User mode at 0x240, with access to [0, 0x800)
+        const USER_MODE : [&str; 9] = ["ADD R1, #0, #640", "MTS VEC, R1", "ADD R1, #0, #2048", "MTS ULIMIT, R1",
+            "ADD R1, #0, #576", "MTS ELR, R1", "ADD R1, #0, #4", "MTS STATUS, R1", "SRET"];
+
+        fn run_as_user(name : &str, instruction : &str) -> Clock {
+            // Runs a single instruction in User mode - the handler saves CAUSE, TVAL and ELR in R2, R3 and R4, and halts
+            let mut lines = USER_MODE.to_vec();
+            lines.extend([instruction, "MFS R2, CAUSE", "MFS R3, TVAL", "MFS R4, ELR", "HLT"]);
+            run(name, &lines, Addressing::Word)
+        }
+
+        #[test]
+        fn syscall_returns_to_next_instruction() {
+            let mut clk = run("syscall", &["ADD R1, #0, #384", "MTS VEC, R1", "SYSCALL #7", "ADD R5, #0, #1", "HLT", "HLT",
+                "MFS R2, CAUSE", "MFS R3, TVAL", "MFS R4, ELR", "SRET"], Addressing::Word);
+            assert_eq!((reg(&mut clk, 2), reg(&mut clk, 3), reg(&mut clk, 4)), (TrapCause::Syscall as u64, 7, 0xC0));
+            assert_eq!(reg(&mut clk, 5), 1); // Ran after SRET
+            assert_eq!(Converter::bin_to_dec_pos_only(clk.ctrl.pc.get_data().to_vec()), 0x140); // Halted on the HLT at 0x100
+            assert!(matches!(clk.ctrl.privilege, PrivilegeLevel::Supervisor));
+        }
+
+        #[test]
+        fn privileged_instruction_traps_at_itself() {
+            let mut clk = run_as_user("privileged", "MTS VEC, R1");
+            assert_eq!(reg(&mut clk, 2), TrapCause::PrivilegedInstruction as u64);
+            assert_eq!(reg(&mut clk, 4), 0x240); // The instruction itself, not the one after
+            assert_eq!(sys(&clk, SystemRegister::VEC), 640); // Not written
+            assert_eq!(sys(&clk, SystemRegister::STATUS) & 4, 4); // Trapped from User mode
+            assert!(matches!(clk.ctrl.privilege, PrivilegeLevel::Supervisor));
+        }
+
+        #[test]
+        fn user_access_outside_limits_faults() {
+            let mut clk = run_as_user("access_fault", "LDR R5, &000000001000");
+            assert_eq!((reg(&mut clk, 2), reg(&mut clk, 3), reg(&mut clk, 4)), (TrapCause::AccessFault as u64, 0x1000, 0x240));
+            assert_eq!(reg(&mut clk, 5), 0);
+            // Inside the limits - loads the first instruction, then carries on to the handler's MFS, which traps as it is still User mode
+            let mut clk = run_as_user("access_allowed", "LDR R5, &000000000000");
+            assert_ne!(reg(&mut clk, 5), 0);
+            assert_eq!((reg(&mut clk, 2), reg(&mut clk, 4)), (TrapCause::PrivilegedInstruction as u64, 0x280));
+        }
+
+        #[test]
+        fn add_and_subtract_128_bits() {
+            // R6:R5 = R2:R1 + R4:R3, then R8:R7 = R6:R5 - R4:R3
+            let cases : [(u128, u128); 4] = [(u64::MAX as u128, 1), (0x0123456789ABCDEF_FEDCBA9876543210, 0x0FEDCBA987654321_8123456789ABCDEF),
+                (1 << 64, 1), (u128::MAX, u128::MAX)];
+            for (index, (a, b)) in cases.into_iter().enumerate() {
+                let values = [format!("LI R1, {0}", a as u64), format!("LI R2, {0}", (a >> 64) as u64), format!("LI R3, {0}", b as u64), format!("LI R4, {0}", (b >> 64) as u64)];
+                let mut lines : Vec<&str> = values.iter().map(|line| line.as_str()).collect();
+                lines.extend(["ADD R5, R1, R3", "ADC R6, R2, R4", "SUB R7, R5, R3", "SBC R8, R6, R4", "HLT"]);
+                let mut clk = run(&format!("carry_chain_{0}", index), &lines, Addressing::Word);
+                let sum = a.wrapping_add(b);
+                assert_eq!((reg(&mut clk, 5), reg(&mut clk, 6)), (sum as u64, (sum >> 64) as u64), "{0:X} + {1:X}", a, b);
+                assert_eq!((reg(&mut clk, 7), reg(&mut clk, 8)), (a as u64, (a >> 64) as u64), "{0:X} + {1:X} - {1:X}", a, b);
+            }
+        }
+
+        #[test]
+        fn carry_through_adc_and_sbc() {
+            // ADD of -1 and 1 carries into the ADC - then -1 + -1 carries, and -1 + 1 + C carries out again
+            let mut clk = run("adc_carry", &["ADD R1, #0, #-1", "ADD R2, R1, #1", "ADC R3, #0, #0", "ADD R4, R1, R1", "ADC R5, R1, #1", "HLT"], Addressing::Word);
+            assert_eq!((reg(&mut clk, 2), reg(&mut clk, 3)), (0, 1));
+            assert_eq!(reg(&mut clk, 5), 1);
+            assert!(clk.ctrl.alu.c && !clk.ctrl.alu.z);
+            let mut clk = run("sbc_borrow", &["ADD R1, #0, #0", "SUB R2, R1, #1", "SBC R3, #0, #0", "SUB R4, #5, #3", "SBC R5, #5, #3", "HLT"], Addressing::Word);
+            assert_eq!((reg(&mut clk, 2), reg(&mut clk, 3)), (u64::MAX, u64::MAX)); // 0 - 1 borrows from the high word
+            assert_eq!(reg(&mut clk, 5), 2); // No borrow - SBC is an ordinary SUB
+            assert!(clk.ctrl.alu.c);
+        }
+
+        #[test]
+        fn branch_conditions() {
+            // For each pair, CMP A, B then each condition in turn - a taken branch sets that condition's bit of R3
+            type Condition = (&'static str, fn(u64, u64) -> bool);
+            let conditions : [Condition; 14] = [
+                ("EQ", |a, b| a == b), ("NE", |a, b| a != b),
+                ("LT", |a, b| (a as i64) < (b as i64)), ("GE", |a, b| (a as i64) >= (b as i64)),
+                ("GT", |a, b| (a as i64) > (b as i64)), ("LE", |a, b| (a as i64) <= (b as i64)),
+                ("LO", |a, b| a < b), ("HS", |a, b| a >= b), ("HI", |a, b| a > b), ("LS", |a, b| a <= b),
+                ("VS", |a, b| (a as i64).overflowing_sub(b as i64).1), ("VC", |a, b| !(a as i64).overflowing_sub(b as i64).1),
+                ("CS", |a, b| a >= b), ("CC", |a, b| a < b)
+            ];
+            let pairs : [(i64, i64); 10] = [(0, 1), (1, 0), (i64::MIN, 1), (1, i64::MIN), (i64::MAX, -1), (-1, i64::MAX),
+                (5, 5), (-1, -1), (i64::MIN, i64::MIN), (-1, 0)];
+            for (index, (a, b)) in pairs.into_iter().enumerate() {
+                let mut lines = vec![format!("LI R1, {0}", a), format!("LI R2, {0}", b)];
+                for (bit, (condition, _)) in conditions.iter().enumerate() {
+                    lines.extend(["CMP R1, R2".to_string(), format!("B{0} &taken{1}", condition, bit), format!("B &next{0}", bit),
+                        format!("taken{0}: OR R3, R3, #{1}", bit, 1 << bit), format!("next{0}:", bit)]);
+                }
+                lines.push("HLT".to_string());
+                let lines : Vec<&str> = lines.iter().map(|line| line.as_str()).collect();
+                let mut clk = run(&format!("branch_conditions_{0}", index), &lines, Addressing::Word);
+                let taken = reg(&mut clk, 3);
+                for (bit, (condition, expected)) in conditions.iter().enumerate() {
+                    assert_eq!(taken & (1 << bit) != 0, expected(a as u64, b as u64), "CMP {0}, {1} then B{2}", a, b, condition);
+                }
+            }
+        }
+
+        #[test]
+        fn narrow_loads_and_stores() {
+            // The word 0xF1E2D3C4B5A69788 at 0x800, then stores into the word after it
+            for addressing in [Addressing::Word, Addressing::Byte] {
+                let at = |byte : u64| format!("&{0:012X}", 0x800 + byte * addressing.word_size() / 8);
+                let lines = [format!("LDRB R1, {0}", at(1)), format!("LDRSB R2, {0}", at(1)), format!("LDRH R3, {0}", at(2)), format!("LDRSH R4, {0}", at(2)),
+                    format!("LDRW R5, {0}", at(4)), format!("LDRSW R6, {0}", at(4)), format!("LDRSB R7, {0}", at(7)), format!("LDR R8, {0}", at(0)),
+                    "LI R9, 0x0123456789ABCDEF".to_string(), format!("STRB R9, {0}", at(9)), format!("STRH R9, {0}", at(10)), format!("STRW R9, {0}", at(12)),
+                    format!("LDR R10, {0}", at(8)), "HLT".to_string(), ".org 000000000800".to_string(), ".dword 0xF1E2D3C4B5A69788".to_string(), ".dword 0".to_string()];
+                let lines : Vec<&str> = lines.iter().map(|line| line.as_str()).collect();
+                let mut clk = run(&format!("narrow_access_{0}", addressing as u8), &lines, addressing);
+                assert_eq!((reg(&mut clk, 1), reg(&mut clk, 2)), (0x97, 0xFFFF_FFFF_FFFF_FF97));
+                assert_eq!((reg(&mut clk, 3), reg(&mut clk, 4)), (0xB5A6, 0xFFFF_FFFF_FFFF_B5A6));
+                assert_eq!((reg(&mut clk, 5), reg(&mut clk, 6)), (0xF1E2_D3C4, 0xFFFF_FFFF_F1E2_D3C4));
+                assert_eq!((reg(&mut clk, 7), reg(&mut clk, 8)), (0xFFFF_FFFF_FFFF_FFF1, 0xF1E2_D3C4_B5A6_9788));
+                assert_eq!(reg(&mut clk, 10).to_le_bytes(), [0x00, 0xEF, 0xEF, 0xCD, 0xEF, 0xCD, 0xAB, 0x89]);
+            }
+        }
+
+        #[test]
+        fn misaligned_access_traps() {
+            // The handler at 0x38 saves CAUSE, TVAL and ELR in R2, R3 and R4, and halts
+            for instruction in ["LDRH R1, &000000000101", "LDRW R1, &000000000102", "STR R1, &000000000104"] {
+                let mut clk = run("misaligned", &["ADD R1, #0, #56", "MTS VEC, R1", instruction, "HLT", "HLT", "HLT", "HLT",
+                    "MFS R2, CAUSE", "MFS R3, TVAL", "MFS R4, ELR", "HLT"], Addressing::Byte);
+                let addr = u64::from_str_radix(&instruction[instruction.len() - 12..], 16).unwrap();
+                assert_eq!((reg(&mut clk, 2), reg(&mut clk, 3), reg(&mut clk, 4)), (TrapCause::MisalignedAccess as u64, addr, 0x10), "{}", instruction);
+            }
+        }
+    }
 }
\ No newline at end of file
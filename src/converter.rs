@@ -62,7 +62,7 @@ pub(crate) mod converter{
             let mut bits_vec: Vec<bool> = Vec::new();
             while val > 0 {
                 bits_vec.push(val % 2 != 0);
-                val = (val as f64 / 2.0).floor() as u64;
+                val /= 2;
             }
             Self::set_size(bits_vec, size)
         }
@@ -91,6 +91,15 @@ pub(crate) mod converter{
             bits
         }
 
+        pub fn sign_extend(mut bits: Vec<bool>, size: u8) -> Vec<bool> {
+            // As set_size, but extends with copies of the top bit, so a two's complement value keeps its sign
+            let top = bits.last().cloned().unwrap_or(false);
+            while bits.len() < size as usize {
+                bits.push(top);
+            }
+            Self::set_size(bits, size)
+        }
+
         pub fn bit48_to64(data: [bool; 48]) -> [bool; 64] {
             let mut return_bits = [false; 64];
             return_bits[0..48].copy_from_slice(&data);
@@ -0,0 +1,362 @@
+pub(crate) mod debugger{
+    /*
+     * Interactive command-line debugger - run with --debug
+     *
+     * Addresses are hex (with or without a leading '0x'), or a label from the program file
+     * Values are decimal, or hex with a leading '0x'
+     * An empty line repeats the previous command
+     *
+     *   break <addr>         b     Stop when the instruction at <addr> is about to be fetched
+     *   delete <addr>        d     Remove a breakpoint
+     *   breakpoints          bl    List breakpoints
+     *   step [n]             s     Run n instructions (default 1)
+     *   tick [n]             t     Run n clock cycles - one CPU state (Fetch/Decode/Execute/Stall) each
+     *   continue             c     Run until a breakpoint, or the program halts
+     *   rstep [n]            rs    Go back n instructions
+     *   rtick [n]            rt    Go back n clock cycles
+     *   rcontinue            rc    Go back to the previous breakpoint, or as far as the history reaches
+     *   goto <cycle>               Go forwards or backwards to a cycle
+     *   history                    How far back the history reaches
+     *   regs                 r     Registers, flags, PC, CPU state and mode
+     *   fregs                      Float registers, and the FPSR's exception flags and rounding mode
+     *   vregs [b|h|w|d]            Vector registers, split into signed lanes of that size (default h)
+     *   x <addr> [n]               Examine n words of memory (default 1)
+     *   set <addr> <value>         Write a word of memory
+     *   set R<n>|pc <value>        Write a register, or the PC
+     *   disas [n]                  Disassemble n instructions either side of the current one (default 4)
+     *   cache                      L1 and L2 cache contents, least recently used first
+     *   dram                       DRAM controller timings, statistics and open rows
+     *   save <file>                Save a checkpoint of the whole machine
+     *   load <file>                Restore a checkpoint
+     *   quit                 q     End the emulator
+     *   help                 h
+     *
+     * Going backwards needs the history, which is on by default in the debugger - see history.rs
+     * Ending input (Ctrl-D) leaves the debugger, and the program runs on to completion
+     * IN reads stdin too - use --input to give the program its input when debugging
+     */
+    use std::collections::{HashMap, HashSet};
+    use std::io::BufRead;
+    use crate::checkpoint::checkpoint;
+    use crate::clock::clock::Clock;
+    use crate::control_unit::control_unit::CpuState;
+    use crate::converter::converter::Converter;
+    use crate::disassembler::disassembler::Disassembler;
+    use crate::history::history;
+    use crate::system_regs::system_regs::PrivilegeLevel;
+
+    pub(crate) struct Debugger {
+        breakpoints : HashSet<u64>,
+        symbols : HashMap<String, [bool; 48]>
+    }
+
+    pub fn run(clock : &mut Clock, symbols : HashMap<String, [bool; 48]>){
+        let mut debugger = Debugger { breakpoints: HashSet::new(), symbols };
+        println!(" ----- DEBUGGER - 'help' FOR COMMANDS -----");
+        debugger.show_location(clock);
+        let mut last_command = "".to_string();
+        let mut lines = std::io::stdin().lock().lines();
+        loop {
+            print!("(dbg) ");
+            std::io::Write::flush(&mut std::io::stdout()).expect("Error Writing Output");
+            let mut command = match lines.next() {
+                Some(Ok(line)) => line.trim().to_string(),
+                _ => {
+                    println!();
+                    if !clock.ctrl.halt { clock.start(); } // Carry on without the debugger
+                    return;
+                }
+            };
+            if command.is_empty() { command = last_command.clone(); }
+            if !debugger.execute(clock, &command) { return; }
+            last_command = command;
+        }
+    }
+
+    impl Debugger {
+
+        fn execute(&mut self, clock : &mut Clock, command : &str) -> bool {
+            // Runs a single command - returns false once the user quits
+            let args : Vec<&str> = command.split_whitespace().collect();
+            if args.is_empty() { return true; }
+            match args[0] {
+                "break" | "b" => match args.get(1).and_then(|addr| self.parse_address(addr)) {
+                    Some(addr) => {
+                        self.breakpoints.insert(addr);
+                        println!("Breakpoint at {0}", self.describe_address(addr));
+                    }
+                    None => println!("Usage: break <addr>")
+                },
+                "delete" | "d" => match args.get(1).and_then(|addr| self.parse_address(addr)) {
+                    Some(addr) => {
+                        if !self.breakpoints.remove(&addr) { println!("No breakpoint at {0}", self.describe_address(addr)); }
+                    }
+                    None => println!("Usage: delete <addr>")
+                },
+                "breakpoints" | "bl" => {
+                    let mut sorted : Vec<&u64> = self.breakpoints.iter().collect();
+                    sorted.sort();
+                    for addr in sorted { println!("  {0}", self.describe_address(*addr)); }
+                }
+                "step" | "s" => {
+                    let count = Self::parse_count(args.get(1), 1);
+                    for _ in 0..count {
+                        if Self::halted(clock) { break; }
+                        clock.step_instruction();
+                    }
+                    self.show_location(clock);
+                }
+                "tick" | "t" => {
+                    let count = Self::parse_count(args.get(1), 1);
+                    for _ in 0..count {
+                        if Self::halted(clock) { break; }
+                        clock.refresh();
+                    }
+                    self.show_location(clock);
+                }
+                "continue" | "c" => {
+                    while !Self::halted(clock) {
+                        clock.step_instruction();
+                        let pc = Converter::bin_to_dec_pos_only(clock.ctrl.pc.get_data().to_vec());
+                        if !clock.ctrl.halt && self.breakpoints.contains(&pc) {
+                            println!("Breakpoint hit");
+                            break;
+                        }
+                    }
+                    self.show_location(clock);
+                }
+                "rstep" | "rs" => {
+                    for _ in 0..Self::parse_count(args.get(1), 1) {
+                        if !history::step_back_instruction(clock) { println!("Reached the start of the history"); break; }
+                    }
+                    self.show_location(clock);
+                }
+                "rtick" | "rt" => {
+                    for _ in 0..Self::parse_count(args.get(1), 1) {
+                        if !history::step_back(clock) { println!("Reached the start of the history"); break; }
+                    }
+                    self.show_location(clock);
+                }
+                "rcontinue" | "rc" => {
+                    loop {
+                        if !history::step_back_instruction(clock) { println!("Reached the start of the history"); break; }
+                        let pc = Converter::bin_to_dec_pos_only(clock.ctrl.pc.get_data().to_vec());
+                        if self.breakpoints.contains(&pc) {
+                            println!("Breakpoint hit");
+                            break;
+                        }
+                    }
+                    self.show_location(clock);
+                }
+                "goto" => match args.get(1).and_then(|cycle| cycle.parse().ok()) {
+                    Some(cycle) => {
+                        if !history::goto_cycle(clock, cycle) { println!("Cycle {0} is out of reach", cycle); }
+                        self.show_location(clock);
+                    }
+                    None => println!("Usage: goto <cycle>")
+                },
+                "history" => match &clock.history {
+                    Some(history) => println!("Back to cycle {0} - {1}", history.earliest_cycle().unwrap_or(clock.cycle_count), history.summary()),
+                    None => println!("History is off")
+                },
+                "regs" | "r" => self.show_registers(clock),
+                "fregs" => Self::show_float_registers(clock),
+                "vregs" => match args.get(1).copied().unwrap_or("h") {
+                    "b" => Self::show_vector_registers(clock, 1),
+                    "h" => Self::show_vector_registers(clock, 2),
+                    "w" => Self::show_vector_registers(clock, 4),
+                    "d" => Self::show_vector_registers(clock, 8),
+                    _ => println!("Usage: vregs [b|h|w|d]")
+                },
+                "x" => match args.get(1).and_then(|addr| self.parse_address(addr)) {
+                    Some(addr) => {
+                        let word_size = clock.ctrl.data_access_manager.main_memory.addressing.word_size();
+                        for word in 0..Self::parse_count(args.get(2), 1) {
+                            let word_addr = addr + word * word_size;
+                            let data = clock.ctrl.data_access_manager.main_memory.read(Self::to_addr_bits(word_addr));
+                            println!("  {0}: 0x{1:016X}  {2}", self.describe_address(word_addr), Converter::bin_to_dec_pos_only(data.to_vec()), Converter::bin_to_dec_2s_comp(data.to_vec()));
+                        }
+                    }
+                    None => println!("Usage: x <addr> [n]")
+                },
+                "set" => {
+                    let value = args.get(2).and_then(|value| Self::parse_value(value));
+                    match (args.get(1), value) {
+                        (Some(target), Some(value)) => self.set(clock, target, value),
+                        _ => println!("Usage: set <addr>|R<n>|pc <value>")
+                    }
+                }
+                "disas" => {
+                    let count = Self::parse_count(args.get(1), 4);
+                    let current = Self::current_address(clock);
+                    let word_size = clock.ctrl.data_access_manager.main_memory.addressing.word_size();
+                    let start = current.saturating_sub(count * word_size);
+                    for addr in (start..=current + count * word_size).step_by(word_size as usize) {
+                        if let Some(label) = self.label_at(addr) { println!("{0}:", label); }
+                        let data = clock.ctrl.data_access_manager.main_memory.read(Self::to_addr_bits(addr));
+                        let marker = if addr == current { "=>" } else { "  " };
+                        println!("{0} 0x{1:012X}  {2}", marker, addr, self.annotate(Disassembler::disassemble(data)));
+                    }
+                }
+                "cache" => {
+                    let (l1_used, l1_size) = clock.ctrl.data_access_manager.l1_cache.utilisation();
+                    println!("L1 ({0}/{1})", l1_used, l1_size);
+                    Self::show_cache(clock.ctrl.data_access_manager.l1_cache.contents());
+                    let (l2_used, l2_size) = clock.ctrl.data_access_manager.l2_cache.utilisation();
+                    println!("L2 ({0}/{1})", l2_used, l2_size);
+                    Self::show_cache(clock.ctrl.data_access_manager.l2_cache.contents());
+                }
+                "dram" => {
+                    let memory_cycle = clock.domains.memory_cycle(clock.cycle_count);
+                    for line in clock.ctrl.data_access_manager.main_memory.dram.describe(memory_cycle) { println!("{}", line); }
+                }
+                "save" => match args.get(1) {
+                    Some(path) => checkpoint::save(clock, path),
+                    None => println!("Usage: save <file>")
+                },
+                "load" => match args.get(1) {
+                    Some(path) => match checkpoint::restore(clock, path) {
+                        Ok(()) => {
+                            if let Some(history) = clock.history.as_mut() { history.clear(); }
+                            self.show_location(clock);
+                        }
+                        Err(error) => println!("Could not load checkpoint - {0}", error)
+                    },
+                    None => println!("Usage: load <file>")
+                },
+                "quit" | "q" => return false,
+                "help" | "h" => println!("Commands: break, delete, breakpoints, step, tick, continue, rstep, rtick, rcontinue, goto, history, regs, fregs, vregs, x, set, disas, cache, dram, save, load, quit - see src/debugger.rs"),
+                other => println!("Unknown command: {0}", other)
+            }
+            true
+        }
+
+        fn set(&self, clock : &mut Clock, target : &str, value : i64){
+            let bits = Converter::dec_to_bin_2s_comp(value);
+            if target.eq_ignore_ascii_case("pc") {
+                clock.ctrl.pc.set_data(bits);
+                clock.ctrl.state = CpuState::Fetch; // Abandon the current instruction
+                self.show_location(clock);
+            }
+            else if let Some(reg) = target.strip_prefix(['R', 'r']).and_then(|reg| reg.parse::<u64>().ok()).filter(|reg| *reg < 15) {
+                clock.ctrl.register_bank.set_data(Converter::dec_to_bin_pos_only(reg, 4).try_into().unwrap(), bits);
+            }
+            else if let Some(addr) = self.parse_address(target) {
+                clock.ctrl.data_access_manager.poke(Self::to_addr_bits(addr), bits);
+            }
+            else {
+                println!("Unknown register or address: {0}", target);
+            }
+        }
+
+        fn halted(clock : &Clock) -> bool {
+            if clock.ctrl.halt { println!("The program has halted"); }
+            clock.ctrl.halt
+        }
+
+        fn current_address(clock : &Clock) -> u64 {
+            // The PC is incremented as soon as an instruction is fetched
+            let pc = Converter::bin_to_dec_pos_only(clock.ctrl.pc.get_data().to_vec());
+            match clock.ctrl.state {
+                CpuState::Fetch => pc,
+                _ => pc.saturating_sub(clock.ctrl.data_access_manager.main_memory.addressing.word_size())
+            }
+        }
+
+        fn show_location(&self, clock : &mut Clock){
+            let addr = Self::current_address(clock);
+            let data = clock.ctrl.data_access_manager.main_memory.read(Self::to_addr_bits(addr));
+            println!("=> {0}  {1}    [{2}, cycle {3}]", self.describe_address(addr), self.annotate(Disassembler::disassemble(data)), Self::state_name(&clock.ctrl.state), clock.cycle_count);
+        }
+
+        fn show_registers(&self, clock : &mut Clock){
+            for reg in 0..15u64 {
+                let data = clock.ctrl.register_bank.get_data(Converter::dec_to_bin_pos_only(reg, 4).try_into().unwrap());
+                println!("  R{0:<3} 0x{1:016X}  {2}", reg, Converter::bin_to_dec_pos_only(data.to_vec()), Converter::bin_to_dec_2s_comp(data.to_vec()));
+            }
+            println!("  PC   {0}", self.describe_address(Converter::bin_to_dec_pos_only(clock.ctrl.pc.get_data().to_vec())));
+            println!("  FLAGS Z={0} N={1} V={2} C={3}", clock.ctrl.alu.z as u8, clock.ctrl.alu.n as u8, clock.ctrl.alu.v as u8, clock.ctrl.alu.c as u8);
+            let mode = if clock.ctrl.privilege == PrivilegeLevel::User { "User" } else { "Supervisor" };
+            println!("  STATE {0}, {1} mode, cycle {2}", Self::state_name(&clock.ctrl.state), mode, clock.cycle_count);
+        }
+
+        fn show_float_registers(clock : &mut Clock){
+            for reg in 0..16u64 {
+                let data = Converter::bin_to_dec_pos_only(clock.ctrl.fpu.get_data(Converter::dec_to_bin_pos_only(reg, 4).try_into().unwrap()).to_vec());
+                println!("  F{0:<3} 0x{1:016X}  {2:?}", reg, data, f64::from_bits(data));
+            }
+            let fpu = &clock.ctrl.fpu;
+            println!("  FPSR NX={0} UF={1} OF={2} DZ={3} NV={4} ROUNDING={5}", fpu.nx as u8, fpu.uf as u8, fpu.of as u8, fpu.dz as u8, fpu.nv as u8, fpu.rounding as u8);
+        }
+
+        fn show_vector_registers(clock : &mut Clock, lane_bytes : usize){
+            // Lanes are listed highest first, as in the hex word
+            for reg in 0..16u64 {
+                let data = clock.ctrl.simd.get_data(Converter::dec_to_bin_pos_only(reg, 4).try_into().unwrap());
+                let lanes : Vec<String> = data.chunks(lane_bytes * 8).rev().map(|lane| Converter::bin_to_dec_2s_comp(lane.to_vec()).to_string()).collect();
+                println!("  V{0:<3} 0x{1:016X}  [{2}]", reg, Converter::bin_to_dec_pos_only(data.to_vec()), lanes.join(", "));
+            }
+        }
+
+        fn show_cache(contents : Vec<([bool; 48], [bool; 64])>){
+            for (addr, value) in contents {
+                println!("  0x{0}: 0x{1:016X}", Disassembler::hex_addr(addr), Converter::bin_to_dec_pos_only(value.to_vec()));
+            }
+        }
+
+        fn state_name(state : &CpuState) -> &'static str {
+            match state {
+                CpuState::Fetch => "Fetch",
+                CpuState::Decode => "Decode",
+                CpuState::Execute => "Execute",
+                CpuState::Stall => "Stall",
+                CpuState::MemoryComp => "MemoryComp"
+            }
+        }
+
+        fn label_at(&self, addr : u64) -> Option<&String> {
+            self.symbols.iter().find(|(_, label_addr)| Converter::bin_to_dec_pos_only(label_addr.to_vec()) == addr).map(|(label, _)| label)
+        }
+
+        fn annotate(&self, instruction : String) -> String {
+            // Names the label an '&<addr>' operand refers to
+            let label = instruction.split_once('&')
+                .and_then(|(_, addr)| u64::from_str_radix(addr, 16).ok())
+                .and_then(|addr| self.label_at(addr));
+            match label {
+                Some(label) => format!("{0}  <{1}>", instruction, label),
+                None => instruction
+            }
+        }
+
+        fn describe_address(&self, addr : u64) -> String {
+            match self.label_at(addr) {
+                Some(label) => format!("0x{0:012X} <{1}>", addr, label),
+                None => format!("0x{0:012X}", addr)
+            }
+        }
+
+        fn parse_address(&self, text : &str) -> Option<u64> {
+            if let Some(addr) = self.symbols.get(text) {
+                return Some(Converter::bin_to_dec_pos_only(addr.to_vec()));
+            }
+            let hex = text.trim_start_matches("0x").trim_start_matches('&');
+            u64::from_str_radix(hex, 16).ok().filter(|addr| *addr < (1 << 48))
+        }
+
+        fn parse_value(text : &str) -> Option<i64> {
+            match text.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16).ok().map(|value| value as i64),
+                None => text.parse().ok()
+            }
+        }
+
+        fn parse_count(text : Option<&&str>, default : u64) -> u64 {
+            text.and_then(|count| count.parse().ok()).unwrap_or(default)
+        }
+
+        fn to_addr_bits(addr : u64) -> [bool; 48] {
+            Converter::dec_to_bin_pos_only(addr, 48).try_into().unwrap()
+        }
+    }
+}
@@ -0,0 +1,286 @@
+pub(crate) mod devices{
+    use std::collections::VecDeque;
+    use std::fs::File;
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use crate::converter::converter::Converter;
+    use crate::io_bus::io_bus::Device;
+
+    // Register offsets are in bits from the base address - one 64-bit register every 0x40 (8 bytes apart with byte addressing)
+
+    #[derive(Default)]
+    pub(crate) struct Timer {
+        // Counts clock cycles, raising an interrupt each time the count reaches the compare value
+        // 0x00 COUNT   - current count (R/W)
+        // 0x40 COMPARE - count at which to interrupt and wrap back to zero (R/W)
+        // 0x80 CONTROL - Bit 0: enable, Bit 1: interrupt enable, Bit 2: interrupt pending (write 1 to clear)
+        count : u64,
+        compare : u64,
+        enabled : bool,
+        irq_enabled : bool,
+        pending : bool
+    }
+
+    impl Device for Timer {
+        fn read(&mut self, offset : [bool; 48]) -> [bool; 64] {
+            match Converter::bin_to_dec_pos_only(offset.to_vec()) {
+                0x00 => Converter::dec_to_bin_pos_only(self.count, 64).try_into().unwrap(),
+                0x40 => Converter::dec_to_bin_pos_only(self.compare, 64).try_into().unwrap(),
+                0x80 => {
+                    let mut control = [false; 64];
+                    control[0] = self.enabled;
+                    control[1] = self.irq_enabled;
+                    control[2] = self.pending;
+                    control
+                }
+                _ => [false; 64]
+            }
+        }
+
+        fn write(&mut self, offset : [bool; 48], val : [bool; 64]) {
+            match Converter::bin_to_dec_pos_only(offset.to_vec()) {
+                0x00 => { self.count = Converter::bin_to_dec_pos_only(val.to_vec()); }
+                0x40 => { self.compare = Converter::bin_to_dec_pos_only(val.to_vec()); }
+                0x80 => {
+                    self.enabled = val[0];
+                    self.irq_enabled = val[1];
+                    if val[2] { self.pending = false; } // Acknowledge interrupt
+                }
+                _ => {}
+            }
+        }
+
+        fn tick(&mut self) {
+            if !self.enabled { return; }
+            self.count += 1;
+            if self.compare != 0 && self.count >= self.compare {
+                self.count = 0;
+                self.pending = true;
+            }
+        }
+
+        fn irq(&self) -> bool {
+            self.irq_enabled && self.pending
+        }
+
+        fn save_state(&self) -> Vec<u64> {
+            vec![self.count, self.compare, self.enabled as u64, self.irq_enabled as u64, self.pending as u64]
+        }
+
+        fn restore_state(&mut self, state : Vec<u64>) {
+            if state.len() != 5 { return; }
+            self.count = state[0];
+            self.compare = state[1];
+            self.enabled = state[2] != 0;
+            self.irq_enabled = state[3] != 0;
+            self.pending = state[4] != 0;
+        }
+    }
+
+
+    struct InputBuffer {
+        bytes : VecDeque<u8>,
+        eof : bool, // Set once the source has no more data to give
+        stdin_pending : bool // Stdin is only read once the program first asks for input
+    }
+
+    #[derive(Clone)]
+    pub(crate) struct ConsoleInput {
+        // Characters from stdin, or from a scripted input file
+        // Clones share the same buffer, so the IN instruction and the memory-mapped registers see the same stream
+        // 0x00 STATUS  - Bit 0: data available, Bit 1: end of input reached
+        // 0x40 DATA    - Reading pops the next character (0 if none available - does not block)
+        // 0x80 CONTROL - Bit 0: interrupt when data is available
+        buffer : Arc<Mutex<InputBuffer>>,
+        irq_enabled : bool
+    }
+
+    impl ConsoleInput {
+
+        pub fn from_stdin() -> ConsoleInput {
+            let input = ConsoleInput::from_bytes(vec![], false);
+            input.buffer.lock().unwrap().stdin_pending = true;
+            input
+        }
+
+        fn start_stdin_reader(&self){
+            // Stdin is read on a separate thread, so polling the device never blocks the clock
+            let mut buffer = self.buffer.lock().unwrap();
+            if !buffer.stdin_pending { return; }
+            buffer.stdin_pending = false;
+            let shared_buffer = self.buffer.clone();
+            thread::spawn(move || {
+                let mut byte = [0u8; 1];
+                let mut stdin = std::io::stdin();
+                loop {
+                    match stdin.read(&mut byte) {
+                        Ok(1) => { shared_buffer.lock().unwrap().bytes.push_back(byte[0]); }
+                        _ => { shared_buffer.lock().unwrap().eof = true; break; }
+                    }
+                }
+            });
+        }
+
+        pub fn from_file(path : &str) -> ConsoleInput {
+            let mut contents = vec![];
+            File::open(path).expect("Input File Error").read_to_end(&mut contents).expect("Input File Error");
+            ConsoleInput::from_bytes(contents, true)
+        }
+
+        pub fn from_bytes(bytes : Vec<u8>, eof : bool) -> ConsoleInput {
+            ConsoleInput {
+                buffer: Arc::new(Mutex::new(InputBuffer { bytes: VecDeque::from(bytes), eof, stdin_pending: false })),
+                irq_enabled: false
+            }
+        }
+
+        pub fn available(&self) -> bool {
+            self.start_stdin_reader();
+            !self.buffer.lock().unwrap().bytes.is_empty()
+        }
+
+        pub fn at_eof(&self) -> bool {
+            self.start_stdin_reader();
+            let buffer = self.buffer.lock().unwrap();
+            buffer.eof && buffer.bytes.is_empty()
+        }
+
+        pub fn read_char(&self) -> Option<i64> {
+            // None while waiting for input - Some(-1) at end of input
+            self.start_stdin_reader();
+            let mut buffer = self.buffer.lock().unwrap();
+            match buffer.bytes.pop_front() {
+                Some(byte) => Some(byte as i64),
+                None => if buffer.eof { Some(-1) } else { None }
+            }
+        }
+
+        pub fn read_integer(&self) -> Option<i64> {
+            // Reads a whitespace-delimited decimal integer, skipping anything non-numeric before it
+            // None while the number is incomplete - Some(-1) at end of input
+            self.start_stdin_reader();
+            let mut buffer = self.buffer.lock().unwrap();
+            while let Some(&byte) = buffer.bytes.front() {
+                if byte.is_ascii_digit() || byte == b'-' { break; }
+                buffer.bytes.pop_front();
+            }
+            let mut length = 0;
+            while length < buffer.bytes.len() && (buffer.bytes[length].is_ascii_digit() || (length == 0 && buffer.bytes[length] == b'-')) {
+                length += 1;
+            }
+            if length == buffer.bytes.len() && !buffer.eof { return None; } // Number may continue
+            if length == 0 { return Some(-1); }
+            let token : String = buffer.bytes.drain(0..length).map(|byte| byte as char).collect();
+            Some(token.parse().unwrap_or(-1))
+        }
+    }
+
+    impl Device for ConsoleInput {
+        fn read(&mut self, offset : [bool; 48]) -> [bool; 64] {
+            match Converter::bin_to_dec_pos_only(offset.to_vec()) {
+                0x00 => {
+                    let mut status = [false; 64];
+                    status[0] = self.available();
+                    status[1] = self.at_eof();
+                    status
+                }
+                0x40 => {
+                    match self.read_char() {
+                        Some(character) if character >= 0 => Converter::dec_to_bin_pos_only(character as u64, 64).try_into().unwrap(),
+                        _ => [false; 64]
+                    }
+                }
+                0x80 => {
+                    let mut control = [false; 64];
+                    control[0] = self.irq_enabled;
+                    control
+                }
+                _ => [false; 64]
+            }
+        }
+
+        fn write(&mut self, offset : [bool; 48], val : [bool; 64]) {
+            if Converter::bin_to_dec_pos_only(offset.to_vec()) == 0x80 {
+                self.irq_enabled = val[0];
+            }
+        }
+
+        fn tick(&mut self) {}
+
+        fn irq(&self) -> bool {
+            self.irq_enabled && self.available()
+        }
+
+        fn save_state(&self) -> Vec<u64> {
+            // Input already buffered is not part of the checkpoint
+            vec![self.irq_enabled as u64]
+        }
+
+        fn restore_state(&mut self, state : Vec<u64>) {
+            if let Some(irq_enabled) = state.first() { self.irq_enabled = *irq_enabled != 0; }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn send(input : &ConsoleInput, bytes : &[u8]){
+            input.buffer.lock().unwrap().bytes.extend(bytes);
+        }
+
+        fn close(input : &ConsoleInput){
+            input.buffer.lock().unwrap().eof = true;
+        }
+
+        #[test]
+        fn integer_split_across_reads() {
+            let input = ConsoleInput::from_bytes(b"12".to_vec(), false);
+            assert_eq!(input.read_integer(), None); // May continue
+            send(&input, b"3 4");
+            assert_eq!(input.read_integer(), Some(123));
+            assert_eq!(input.read_integer(), None);
+            close(&input);
+            assert_eq!(input.read_integer(), Some(4)); // Ended by the end of input
+            assert_eq!(input.read_integer(), Some(-1));
+            assert!(input.at_eof());
+        }
+
+        #[test]
+        fn negative_integers() {
+            let input = ConsoleInput::from_bytes(b"-42\n-0 - 7".to_vec(), true);
+            assert_eq!(input.read_integer(), Some(-42));
+            assert_eq!(input.read_integer(), Some(0));
+            assert_eq!(input.read_integer(), Some(-1)); // A '-' on its own
+            assert_eq!(input.read_integer(), Some(7));
+            let input = ConsoleInput::from_bytes(b"-".to_vec(), false);
+            assert_eq!(input.read_integer(), None);
+        }
+
+        #[test]
+        fn integers_skip_non_digits() {
+            let input = ConsoleInput::from_bytes(b"abc, 7;x8\n  foo".to_vec(), true);
+            assert_eq!(input.read_integer(), Some(7));
+            assert_eq!(input.read_integer(), Some(8));
+            assert_eq!(input.read_integer(), Some(-1));
+            assert_eq!(input.read_integer(), Some(-1));
+            let input = ConsoleInput::from_bytes(b"abc".to_vec(), false);
+            assert_eq!(input.read_integer(), None);
+            assert!(!input.available()); // Skipped bytes are gone
+        }
+
+        #[test]
+        fn characters() {
+            let input = ConsoleInput::from_bytes(b"5\nhi".to_vec(), false);
+            assert_eq!(input.read_integer(), Some(5));
+            assert_eq!(input.read_char(), Some(b'\n' as i64)); // The delimiter is left for read_char
+            assert_eq!(input.read_char(), Some(b'h' as i64));
+            assert_eq!(input.read_char(), Some(b'i' as i64));
+            assert_eq!(input.read_char(), None);
+            close(&input);
+            assert_eq!(input.read_char(), Some(-1));
+            assert_eq!(input.read_char(), Some(-1));
+        }
+    }
+}
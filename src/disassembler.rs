@@ -0,0 +1,350 @@
+pub(crate) mod disassembler{
+    use num_traits::FromPrimitive;
+    use crate::assembler::assembler::{BranchConditions, InstrType, ParsedInstruction, EXTENDED_OPCODE};
+    use crate::converter::converter::Converter;
+    use crate::system_regs::system_regs::SystemRegister;
+
+    pub(crate) struct Disassembler {} // Convert 64-bit binary back into the intermediate representation (ParsedInstruction), then into assembly text
+
+    impl Disassembler {
+
+        pub fn decode(mdr_data : [bool; 64]) -> ParsedInstruction {
+            // Binary representation decodings can be found in the design document
+            let mut decoded_instruction = ParsedInstruction::default();
+
+            let mut opcode = Converter::bin_to_dec_pos_only(mdr_data[0..4].to_vec());
+            if opcode == EXTENDED_OPCODE as u64 { opcode = Converter::bin_to_dec_pos_only(mdr_data[4..12].to_vec()); } // Extended instruction
+            decoded_instruction.instr_type = FromPrimitive::from_u64(opcode).unwrap_or(InstrType::OTH);
+
+            match decoded_instruction.instr_type {
+                InstrType::ADD | InstrType::SUB | InstrType::MULT | InstrType::AND | InstrType::OR | InstrType::XOR => {
+                    decoded_instruction.return_register.copy_from_slice(&mdr_data[4..8]);
+                    decoded_instruction.reg_0 = mdr_data[8];
+                    decoded_instruction.input_val_0.copy_from_slice(&mdr_data[9..25]);
+                    decoded_instruction.reg_1 = mdr_data[25];
+                    decoded_instruction.input_val_1.copy_from_slice(&mdr_data[26..42]);
+                }
+
+                InstrType::NOT | InstrType::FLIP => {
+                    decoded_instruction.return_register.copy_from_slice(&mdr_data[4..8]);
+                    decoded_instruction.reg_0 = mdr_data[8];
+                    decoded_instruction.input_val_0.copy_from_slice(&mdr_data[9..25]);
+                }
+
+                InstrType::CMP => {
+                    decoded_instruction.reg_0 = mdr_data[4];
+                    decoded_instruction.input_val_0.copy_from_slice(&mdr_data[5..21]);
+                    decoded_instruction.reg_1 = mdr_data[21];
+                    decoded_instruction.input_val_1.copy_from_slice(&mdr_data[22..38]);
+                }
+
+                InstrType::STR | InstrType::LDR => {
+                    decoded_instruction.return_register.copy_from_slice(&mdr_data[4..8]);
+                    decoded_instruction.addr.copy_from_slice(&mdr_data[8..56]);
+                    decoded_instruction.access_size = match Converter::bin_to_dec_pos_only(mdr_data[56..58].to_vec()) {
+                        0 => 8,
+                        size_code => 1 << (size_code - 1)
+                    };
+                    decoded_instruction.signed = mdr_data[58];
+                }
+
+                InstrType::B => {
+                    decoded_instruction.branch_condition = FromPrimitive::from_u64(Converter::bin_to_dec_pos_only(mdr_data[4..8].to_vec())).unwrap_or(BranchConditions::OTH);
+                    decoded_instruction.reg_0 = mdr_data[8];
+                    decoded_instruction.addr.copy_from_slice(&mdr_data[9..57]);
+                }
+
+                InstrType::OUT => {
+                    decoded_instruction.return_register.copy_from_slice(&mdr_data[4..8]);
+                    decoded_instruction.ascii = mdr_data[8];
+                }
+
+                InstrType::SYSCALL => {
+                    decoded_instruction.input_val_0.copy_from_slice(&mdr_data[12..28]);
+                }
+
+                InstrType::MFS | InstrType::MTS => {
+                    decoded_instruction.return_register.copy_from_slice(&mdr_data[12..16]);
+                    decoded_instruction.input_val_0[0..4].copy_from_slice(&mdr_data[16..20]);
+                }
+
+                InstrType::IN => {
+                    decoded_instruction.return_register.copy_from_slice(&mdr_data[12..16]);
+                    decoded_instruction.ascii = mdr_data[16];
+                }
+
+                InstrType::MOVZ | InstrType::MOVK | InstrType::MOVN => {
+                    decoded_instruction.return_register.copy_from_slice(&mdr_data[12..16]);
+                    decoded_instruction.input_val_1[0..2].copy_from_slice(&mdr_data[16..18]);
+                    decoded_instruction.input_val_0.copy_from_slice(&mdr_data[18..34]);
+                }
+
+                InstrType::FADD | InstrType::FSUB | InstrType::FMUL | InstrType::FDIV | InstrType::FSQRT | InstrType::FCMP | InstrType::FMOV |
+                InstrType::ITOF | InstrType::FTOI | InstrType::FRSR | InstrType::FWSR => {
+                    decoded_instruction.return_register.copy_from_slice(&mdr_data[12..16]);
+                    decoded_instruction.input_val_0[0..4].copy_from_slice(&mdr_data[16..20]);
+                    decoded_instruction.input_val_1[0..4].copy_from_slice(&mdr_data[20..24]);
+                    decoded_instruction.reg_0 = mdr_data[24];
+                    decoded_instruction.reg_1 = mdr_data[25];
+                }
+
+                InstrType::FMOVI => {
+                    decoded_instruction.return_register.copy_from_slice(&mdr_data[12..16]);
+                    decoded_instruction.input_val_0.copy_from_slice(&mdr_data[16..32]);
+                }
+
+                InstrType::VADD | InstrType::VSUB | InstrType::VMUL | InstrType::VQADD | InstrType::VQSUB | InstrType::VUQADD | InstrType::VUQSUB |
+                InstrType::VAND | InstrType::VOR | InstrType::VXOR | InstrType::VDUP | InstrType::VADDV | InstrType::VMOV => {
+                    decoded_instruction.return_register.copy_from_slice(&mdr_data[12..16]);
+                    decoded_instruction.input_val_0[0..4].copy_from_slice(&mdr_data[16..20]);
+                    decoded_instruction.input_val_1[0..4].copy_from_slice(&mdr_data[20..24]);
+                    decoded_instruction.reg_0 = mdr_data[24];
+                    decoded_instruction.reg_1 = mdr_data[25];
+                    decoded_instruction.access_size = 1 << Converter::bin_to_dec_pos_only(mdr_data[26..28].to_vec());
+                }
+
+                InstrType::VLDR | InstrType::VSTR => {
+                    decoded_instruction.return_register.copy_from_slice(&mdr_data[12..16]);
+                    decoded_instruction.addr.copy_from_slice(&mdr_data[16..64]);
+                }
+
+                InstrType::ADC | InstrType::SBC => {
+                    decoded_instruction.return_register.copy_from_slice(&mdr_data[12..16]);
+                    decoded_instruction.reg_0 = mdr_data[16];
+                    decoded_instruction.input_val_0.copy_from_slice(&mdr_data[17..33]);
+                    decoded_instruction.reg_1 = mdr_data[33];
+                    decoded_instruction.input_val_1.copy_from_slice(&mdr_data[34..50]);
+                }
+
+                _ => {}
+            }
+            decoded_instruction
+        }
+
+        pub fn disassemble(mdr_data : [bool; 64]) -> String {
+            // Produces the same syntax the assembler accepts
+            if !mdr_data.contains(&true) { return "".to_string(); } // Empty word - skipped by the CPU
+            let instr = Self::decode(mdr_data);
+            let dest = Self::register(instr.return_register.to_vec());
+            match instr.instr_type {
+                InstrType::ADD | InstrType::SUB | InstrType::MULT | InstrType::AND | InstrType::OR | InstrType::XOR | InstrType::ADC | InstrType::SBC => {
+                    format!("{0} {1}, {2}, {3}", Self::mnemonic(&instr.instr_type), dest, Self::operand(instr.reg_0, instr.input_val_0, &instr.instr_type), Self::operand(instr.reg_1, instr.input_val_1, &instr.instr_type))
+                }
+                InstrType::NOT | InstrType::FLIP => {
+                    format!("{0} {1}, {2}", Self::mnemonic(&instr.instr_type), dest, Self::operand(instr.reg_0, instr.input_val_0, &instr.instr_type))
+                }
+                InstrType::CMP => {
+                    format!("CMP {0}, {1}", Self::operand(instr.reg_0, instr.input_val_0, &instr.instr_type), Self::operand(instr.reg_1, instr.input_val_1, &instr.instr_type))
+                }
+                InstrType::LDR | InstrType::STR => {
+                    format!("{0}{1} {2}, &{3}", Self::mnemonic(&instr.instr_type), Self::access_suffix(&instr), dest, Self::hex_addr(instr.addr))
+                }
+                InstrType::B => {
+                    let target = if instr.reg_0 { Self::register(instr.addr[0..4].to_vec()) } else { "&".to_string() + &Self::hex_addr(instr.addr) };
+                    format!("{0} {1}", Self::condition(&instr.branch_condition), target)
+                }
+                InstrType::OUT | InstrType::IN => {
+                    format!("{0} {1} {2}", Self::mnemonic(&instr.instr_type), if instr.ascii { "A" } else { "R" }, dest)
+                }
+                InstrType::SYSCALL => {
+                    format!("SYSCALL #{0}", Converter::bin_to_dec_pos_only(instr.input_val_0.to_vec()))
+                }
+                InstrType::MFS => {
+                    format!("MFS {0}, {1}", dest, Self::system_register(instr.input_val_0))
+                }
+                InstrType::MTS => {
+                    format!("MTS {0}, {1}", Self::system_register(instr.input_val_0), dest)
+                }
+                InstrType::MOVZ | InstrType::MOVK | InstrType::MOVN => {
+                    let shift = Converter::bin_to_dec_pos_only(instr.input_val_1[0..2].to_vec()) * 16;
+                    let immediate = Converter::bin_to_dec_pos_only(instr.input_val_0.to_vec());
+                    if shift == 0 { format!("{0} {1}, #{2}", Self::mnemonic(&instr.instr_type), dest, immediate) }
+                    else { format!("{0} {1}, #{2}, LSL #{3}", Self::mnemonic(&instr.instr_type), dest, immediate, shift) }
+                }
+                InstrType::FADD | InstrType::FSUB | InstrType::FMUL | InstrType::FDIV => {
+                    format!("{0} {1}, {2}, {3}", Self::mnemonic(&instr.instr_type), Self::float_register(instr.return_register.to_vec()),
+                        Self::float_register(instr.input_val_0[0..4].to_vec()), Self::float_register(instr.input_val_1[0..4].to_vec()))
+                }
+                InstrType::FSQRT => {
+                    format!("FSQRT {0}, {1}", Self::float_register(instr.return_register.to_vec()), Self::float_register(instr.input_val_0[0..4].to_vec()))
+                }
+                InstrType::FCMP => {
+                    format!("FCMP {0}, {1}", Self::float_register(instr.input_val_0[0..4].to_vec()), Self::float_register(instr.input_val_1[0..4].to_vec()))
+                }
+                InstrType::FMOV => {
+                    let dest = if instr.reg_1 { dest } else { Self::float_register(instr.return_register.to_vec()) };
+                    let source = if instr.reg_0 { Self::register(instr.input_val_0[0..4].to_vec()) } else { Self::float_register(instr.input_val_0[0..4].to_vec()) };
+                    format!("FMOV {0}, {1}", dest, source)
+                }
+                InstrType::FMOVI => {
+                    let immediate = f64::from_bits(Converter::bin_to_dec_pos_only(instr.input_val_0.to_vec()) << 48);
+                    format!("FMOV {0}, #{1:?}", Self::float_register(instr.return_register.to_vec()), immediate)
+                }
+                InstrType::ITOF => {
+                    format!("ITOF {0}, {1}", Self::float_register(instr.return_register.to_vec()), Self::register(instr.input_val_0[0..4].to_vec()))
+                }
+                InstrType::FTOI => {
+                    format!("FTOI {0}, {1}", dest, Self::float_register(instr.input_val_0[0..4].to_vec()))
+                }
+                InstrType::FRSR | InstrType::FWSR => {
+                    format!("{0} {1}", Self::mnemonic(&instr.instr_type), dest)
+                }
+                InstrType::VADD | InstrType::VSUB | InstrType::VMUL | InstrType::VQADD | InstrType::VQSUB | InstrType::VUQADD | InstrType::VUQSUB |
+                InstrType::VAND | InstrType::VOR | InstrType::VXOR => {
+                    format!("{0}{1} {2}, {3}, {4}", Self::mnemonic(&instr.instr_type), Self::lane_suffix(&instr), Self::vector_register(instr.return_register.to_vec()),
+                        Self::vector_register(instr.input_val_0[0..4].to_vec()), Self::vector_register(instr.input_val_1[0..4].to_vec()))
+                }
+                InstrType::VDUP => {
+                    format!("VDUP{0} {1}, {2}", Self::lane_suffix(&instr), Self::vector_register(instr.return_register.to_vec()), Self::register(instr.input_val_0[0..4].to_vec()))
+                }
+                InstrType::VADDV => {
+                    format!("VADDV{0} {1}, {2}", Self::lane_suffix(&instr), dest, Self::vector_register(instr.input_val_0[0..4].to_vec()))
+                }
+                InstrType::VMOV => {
+                    let dest = if instr.reg_1 { dest } else { Self::vector_register(instr.return_register.to_vec()) };
+                    let source = if instr.reg_0 { Self::register(instr.input_val_0[0..4].to_vec()) } else { Self::vector_register(instr.input_val_0[0..4].to_vec()) };
+                    format!("VMOV {0}, {1}", dest, source)
+                }
+                InstrType::VLDR | InstrType::VSTR => {
+                    format!("{0} {1}, &{2}", Self::mnemonic(&instr.instr_type), Self::vector_register(instr.return_register.to_vec()), Self::hex_addr(instr.addr))
+                }
+                InstrType::HLT | InstrType::SRET => Self::mnemonic(&instr.instr_type).to_string(),
+                InstrType::OTH => format!("<DATA 0x{:016X}>", Converter::bin_to_dec_pos_only(mdr_data.to_vec()))
+            }
+        }
+
+        pub fn hex_addr(addr : [bool; 48]) -> String {
+            format!("{:012X}", Converter::bin_to_dec_pos_only(addr.to_vec()))
+        }
+
+        fn register(index : Vec<bool>) -> String {
+            format!("R{0}", Converter::bin_to_dec_pos_only(index))
+        }
+
+        fn float_register(index : Vec<bool>) -> String {
+            format!("F{0}", Converter::bin_to_dec_pos_only(index))
+        }
+
+        fn vector_register(index : Vec<bool>) -> String {
+            format!("V{0}", Converter::bin_to_dec_pos_only(index))
+        }
+
+        fn operand(reg : bool, val : [bool; 16], instr_type : &InstrType) -> String {
+            // 'R' for a register reference, '#' for a literal - signed unless the instruction is bitwise
+            if reg { Self::register(val[0..4].to_vec()) }
+            else if instr_type.signed_immediates() { format!("#{0}", Converter::bin_to_dec_2s_comp(val.to_vec())) }
+            else { format!("#{0}", Converter::bin_to_dec_pos_only(val.to_vec())) }
+        }
+
+        fn access_suffix(instr : &ParsedInstruction) -> String {
+            // LDRB, LDRSH, STRW...
+            let size = match instr.access_size {
+                1 => "B",
+                2 => "H",
+                4 => "W",
+                _ => ""
+            };
+            format!("{0}{1}", if instr.signed { "S" } else { "" }, size)
+        }
+
+        fn lane_suffix(instr : &ParsedInstruction) -> &'static str {
+            // VADD.B, VQSUB.H, VADDV.W... - a single 64-bit lane is left without one
+            match instr.access_size {
+                1 => ".B",
+                2 => ".H",
+                4 => ".W",
+                _ => ""
+            }
+        }
+
+        fn system_register(val : [bool; 16]) -> &'static str {
+            match FromPrimitive::from_u64(Converter::bin_to_dec_pos_only(val[0..4].to_vec())).unwrap_or(SystemRegister::OTH) {
+                SystemRegister::ELR => "ELR",
+                SystemRegister::VEC => "VEC",
+                SystemRegister::CAUSE => "CAUSE",
+                SystemRegister::TVAL => "TVAL",
+                SystemRegister::STATUS => "STATUS",
+                SystemRegister::UBASE => "UBASE",
+                SystemRegister::ULIMIT => "ULIMIT",
+                SystemRegister::OTH => "OTH"
+            }
+        }
+
+        fn mnemonic(instr_type : &InstrType) -> &'static str {
+            match instr_type {
+                InstrType::ADD => "ADD",
+                InstrType::SUB => "SUB",
+                InstrType::B => "B",
+                InstrType::LDR => "LDR",
+                InstrType::STR => "STR",
+                InstrType::HLT => "HLT",
+                InstrType::OUT => "OUT",
+                InstrType::MULT => "MULT",
+                InstrType::CMP => "CMP",
+                InstrType::AND => "AND",
+                InstrType::OR => "OR",
+                InstrType::XOR => "XOR",
+                InstrType::NOT => "NOT",
+                InstrType::FLIP => "FLIP",
+                InstrType::SYSCALL => "SYSCALL",
+                InstrType::SRET => "SRET",
+                InstrType::MFS => "MFS",
+                InstrType::MTS => "MTS",
+                InstrType::IN => "IN",
+                InstrType::MOVZ => "MOVZ",
+                InstrType::MOVK => "MOVK",
+                InstrType::MOVN => "MOVN",
+                InstrType::ADC => "ADC",
+                InstrType::SBC => "SBC",
+                InstrType::FADD => "FADD",
+                InstrType::FSUB => "FSUB",
+                InstrType::FMUL => "FMUL",
+                InstrType::FDIV => "FDIV",
+                InstrType::FSQRT => "FSQRT",
+                InstrType::FCMP => "FCMP",
+                InstrType::FMOV | InstrType::FMOVI => "FMOV",
+                InstrType::ITOF => "ITOF",
+                InstrType::FTOI => "FTOI",
+                InstrType::FRSR => "FRSR",
+                InstrType::FWSR => "FWSR",
+                InstrType::VADD => "VADD",
+                InstrType::VSUB => "VSUB",
+                InstrType::VMUL => "VMUL",
+                InstrType::VQADD => "VQADD",
+                InstrType::VQSUB => "VQSUB",
+                InstrType::VUQADD => "VUQADD",
+                InstrType::VUQSUB => "VUQSUB",
+                InstrType::VAND => "VAND",
+                InstrType::VOR => "VOR",
+                InstrType::VXOR => "VXOR",
+                InstrType::VDUP => "VDUP",
+                InstrType::VADDV => "VADDV",
+                InstrType::VMOV => "VMOV",
+                InstrType::VLDR => "VLDR",
+                InstrType::VSTR => "VSTR",
+                InstrType::OTH => "OTH"
+            }
+        }
+
+        fn condition(condition : &BranchConditions) -> &'static str {
+            match condition {
+                BranchConditions::B => "B",
+                BranchConditions::BEQ => "BEQ",
+                BranchConditions::BNE => "BNE",
+                BranchConditions::BLT => "BLT",
+                BranchConditions::BGT => "BGT",
+                BranchConditions::BLE => "BLE",
+                BranchConditions::BGE => "BGE",
+                BranchConditions::BLO => "BLO",
+                BranchConditions::BHS => "BHS",
+                BranchConditions::BHI => "BHI",
+                BranchConditions::BLS => "BLS",
+                BranchConditions::BVS => "BVS",
+                BranchConditions::BVC => "BVC",
+                BranchConditions::BCS => "BCS",
+                BranchConditions::BCC => "BCC",
+                BranchConditions::OTH => "B??"
+            }
+        }
+    }
+}
@@ -0,0 +1,207 @@
+pub(crate) mod dram_controller{
+    /*
+     * DRAM controller - sits between the memory bus and the storage in MainMemory, and decides how long each access takes
+     *
+     * Memory is organised as BANKS banks of rows, each row ROW_WORDS 64-bit words long. Consecutive rows of the address
+     * space are interleaved across the banks (word -> column, then bank, then row). Each bank keeps its last row open:
+     *   Row hit       the row is already open                  CAS
+     *   Row miss      the bank has no open row                 RCD + CAS
+     *   Row conflict  another row is open, and is precharged   RP + RCD + CAS
+     *
+     * A refresh starts every refresh interval, precharging every bank, and blocks accesses for the refresh time
+     * Each refresh recharges one row number in every bank, in turn, so every row is refreshed once every
+     * REFRESH_ROWS refreshes. If a retention time is set, a row not refreshed or accessed within it has decayed,
+     * and loses its contents the next time it is opened
+     *
+     * All times are memory clock cycles. The refresh schedule only depends on the memory cycle number, so the
+     * controller's state is just the open rows, the access in progress and, if rows can decay, when each was last used
+     */
+    use std::collections::HashMap;
+
+    pub(crate) const BANKS : usize = 8;
+    pub(crate) const ROW_WORDS : u64 = 128; // 1KB rows
+    const REFRESH_ROWS : u64 = 8192;
+
+    #[derive(Clone, Copy)]
+    pub(crate) struct DramTiming {
+        pub(crate) enabled : bool, // Off - every access is served on the memory cycle it arrives, as an ideal memory
+        pub(crate) cas : u64,
+        pub(crate) rcd : u64,
+        pub(crate) rp : u64,
+        pub(crate) refresh_interval : u64, // 0 to never refresh
+        pub(crate) refresh_time : u64,
+        pub(crate) retention : u64 // 0 if rows never decay
+    }
+
+    impl DramTiming {
+        pub fn parse(spec : &str) -> Result<DramTiming, String> {
+            // 'off', or comma separated settings, e.g. cas=3,refi=0,retention=5000
+            let mut timing = DramTiming::default();
+            if spec == "off" {
+                timing.enabled = false;
+                return Ok(timing);
+            }
+            for setting in spec.split(',') {
+                let (name, value) = setting.split_once('=').ok_or(format!("Expected <name>=<cycles>, found '{0}'", setting))?;
+                let value : u64 = value.parse().map_err(|_| format!("Invalid number of cycles '{0}'", value))?;
+                match name {
+                    "cas" => timing.cas = value,
+                    "rcd" => timing.rcd = value,
+                    "rp" => timing.rp = value,
+                    "refi" => timing.refresh_interval = value,
+                    "rfc" => timing.refresh_time = value,
+                    "retention" => timing.retention = value,
+                    other => return Err(format!("Unknown DRAM setting '{0}'", other))
+                }
+            }
+            if timing.refresh_interval > 0 && timing.refresh_time >= timing.refresh_interval {
+                return Err("The refresh time must be shorter than the refresh interval".to_string());
+            }
+            Ok(timing)
+        }
+    }
+
+    impl Default for DramTiming {
+        // DDR-like timings, for a 100MHz memory clock - a refresh every 7.8us, and every row refreshed every 64ms
+        fn default() -> Self { DramTiming { enabled: true, cas: 2, rcd: 2, rp: 2, refresh_interval: 780, refresh_time: 26, retention: 0 } }
+    }
+
+    #[derive(Clone, Copy, PartialEq, Default)]
+    pub(crate) struct DramStats {
+        pub(crate) row_hits : u64,
+        pub(crate) row_misses : u64,
+        pub(crate) row_conflicts : u64,
+        pub(crate) refresh_stalls : u64, // Memory cycles a request waited for a refresh to finish
+        pub(crate) decayed_rows : u64
+    }
+
+    #[derive(Clone, PartialEq)]
+    pub(crate) struct DramState {
+        pub(crate) busy : u64, // Memory cycles left on the access in progress
+        pub(crate) open_rows : [Option<(u64, u64)>; BANKS], // (row, memory cycle it was opened on)
+        pub(crate) last_used : HashMap<u64, u64>, // Bank and row (as the word address / ROW_WORDS) -> memory cycle, only kept if rows can decay
+        pub(crate) stats : DramStats
+    }
+
+    impl DramState {
+        pub fn save_state(&self) -> Vec<u64> {
+            // busy, the five stats, then (open, row, opened) per bank, then (row, cycle) pairs
+            let stats = self.stats;
+            let mut state = vec![self.busy, stats.row_hits, stats.row_misses, stats.row_conflicts, stats.refresh_stalls, stats.decayed_rows];
+            for open in self.open_rows {
+                let (row, opened) = open.unwrap_or((0, 0));
+                state.extend([open.is_some() as u64, row, opened]);
+            }
+            let mut last_used : Vec<(u64, u64)> = self.last_used.iter().map(|(row, cycle)| (*row, *cycle)).collect();
+            last_used.sort();
+            for (row, cycle) in last_used { state.extend([row, cycle]); }
+            state
+        }
+
+        pub fn restore_state(state : &[u64]) -> Option<DramState> {
+            let header = 6 + BANKS * 3;
+            if state.len() < header || !(state.len() - header).is_multiple_of(2) { return None; }
+            let mut open_rows = [None; BANKS];
+            for (bank, open) in open_rows.iter_mut().enumerate() {
+                let fields = &state[6 + bank * 3..9 + bank * 3];
+                if fields[0] != 0 { *open = Some((fields[1], fields[2])); }
+            }
+            Some(DramState {
+                busy: state[0],
+                open_rows,
+                last_used: state[header..].chunks(2).map(|pair| (pair[0], pair[1])).collect(),
+                stats: DramStats { row_hits: state[1], row_misses: state[2], row_conflicts: state[3], refresh_stalls: state[4], decayed_rows: state[5] }
+            })
+        }
+    }
+
+    impl Default for DramState {
+        fn default() -> Self { DramState { busy: 0, open_rows: [None; BANKS], last_used: HashMap::new(), stats: DramStats::default() } }
+    }
+
+    pub(crate) struct DramController {
+        pub(crate) timing : DramTiming,
+        pub(crate) state : DramState
+    }
+
+    impl DramController {
+
+        pub fn new(timing : DramTiming) -> DramController {
+            DramController { timing, state: DramState::default() }
+        }
+
+        pub fn refreshing(&self, cycle : u64) -> bool {
+            self.timing.refresh_interval > 0 && cycle % self.timing.refresh_interval < self.timing.refresh_time
+        }
+
+        fn refreshed_since(&self, opened : u64, cycle : u64) -> bool {
+            // True if a refresh started after the given cycle, up to and including this one
+            self.timing.refresh_interval > 0 && cycle / self.timing.refresh_interval > opened / self.timing.refresh_interval
+        }
+
+        fn last_refresh(&self, row : u64, cycle : u64) -> u64 {
+            // Memory cycle the row was last refreshed on - 0 (power on) if it hasn't been yet
+            if self.timing.refresh_interval == 0 { return 0; }
+            let current = cycle / self.timing.refresh_interval;
+            let slot = row % REFRESH_ROWS;
+            if current < slot { return 0; }
+            (current - (current - slot) % REFRESH_ROWS) * self.timing.refresh_interval
+        }
+
+        pub fn activate(&mut self, word : u64, cycle : u64) -> (u64, bool) {
+            // Starts an access to the word (address / 64) - returns its latency, and whether the row had decayed
+            let page = word / ROW_WORDS;
+            let bank = (page % BANKS as u64) as usize;
+            let row = page / BANKS as u64;
+
+            let mut decayed = false;
+            if self.timing.retention > 0 {
+                let last_used = self.state.last_used.get(&page).cloned().unwrap_or(0).max(self.last_refresh(row, cycle));
+                decayed = cycle - last_used > self.timing.retention;
+                if decayed { self.state.stats.decayed_rows += 1; }
+                self.state.last_used.insert(page, cycle);
+            }
+
+            let open = self.state.open_rows[bank].filter(|(_, opened)| !self.refreshed_since(*opened, cycle)); // Refresh precharges every bank
+            let timing = self.timing;
+            let latency = match open {
+                Some((open_row, _)) if open_row == row => {
+                    self.state.stats.row_hits += 1;
+                    return (timing.cas, decayed);
+                }
+                Some(_) => {
+                    self.state.stats.row_conflicts += 1;
+                    timing.rp + timing.rcd + timing.cas
+                }
+                None => {
+                    self.state.stats.row_misses += 1;
+                    timing.rcd + timing.cas
+                }
+            };
+            self.state.open_rows[bank] = Some((row, cycle));
+            (latency, decayed)
+        }
+
+        pub fn describe(&self, cycle : u64) -> Vec<String> {
+            // For the debugger
+            if !self.timing.enabled { return vec!["DRAM controller off - ideal memory".to_string()]; }
+            let timing = self.timing;
+            let stats = self.state.stats;
+            let mut lines = vec![
+                format!("CAS {0}, RCD {1}, RP {2}, refresh every {3} for {4}, retention {5} (memory cycles)",
+                    timing.cas, timing.rcd, timing.rp, timing.refresh_interval, timing.refresh_time, timing.retention),
+                format!("{0} row hits, {1} misses, {2} conflicts, {3} cycles waiting for refresh, {4} decayed rows",
+                    stats.row_hits, stats.row_misses, stats.row_conflicts, stats.refresh_stalls, stats.decayed_rows)
+            ];
+            for (bank, open) in self.state.open_rows.iter().enumerate() {
+                match open.filter(|(_, opened)| !self.refreshed_since(*opened, cycle)) {
+                    Some((row, _)) => lines.push(format!("BANK {0}: ROW {1:X} OPEN", bank, row)),
+                    None => lines.push(format!("BANK {0}: PRECHARGED", bank))
+                }
+            }
+            if self.state.busy > 0 { lines.push(format!("Access in progress - {0} cycles left", self.state.busy)); }
+            if self.refreshing(cycle) { lines.push("Refreshing".to_string()); }
+            lines
+        }
+    }
+}
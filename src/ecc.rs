@@ -0,0 +1,140 @@
+pub(crate) mod ecc{
+    /*
+     * SECDED error-correcting code - a (72, 64) extended Hamming code
+     *
+     * The 64 data bits sit at the non-power-of-two positions 3, 5, 6, 7, 9 ... 71 of a 71-bit Hamming codeword, and
+     * the 7 check bits hold the XOR of the positions of every set data bit. An eighth bit is the parity of everything,
+     * so a single flipped bit (data or check) can be found and corrected, and any two flipped bits detected
+     *
+     * Check byte layout - bits 0-6 Hamming check bits, bit 7 overall parity
+     */
+
+    #[derive(Clone, Copy, PartialEq, Default)]
+    pub(crate) enum EccMode {
+        #[default]
+        Off,
+        Memory, // Main memory only
+        All // Main memory and the caches
+    }
+
+    impl EccMode {
+        pub fn parse(name : &str) -> Option<EccMode> {
+            match name {
+                "off" => Some(EccMode::Off),
+                "memory" => Some(EccMode::Memory),
+                "all" => Some(EccMode::All),
+                _ => None
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, PartialEq)]
+    pub(crate) enum EccResult {
+        Clean,
+        Corrected(u64, u8), // Corrected data and check bits
+        Uncorrectable
+    }
+
+    #[derive(Clone, Copy, PartialEq, Default)]
+    pub(crate) struct EccStats {
+        pub(crate) corrected : u64,
+        pub(crate) uncorrectable : u64
+    }
+
+    fn position(bit : u32) -> u32 {
+        // Codeword position of data bit 0-63 - skipping the powers of two
+        let mut position = bit + 3;
+        let mut power = 4;
+        while power <= position {
+            position += 1;
+            power *= 2;
+        }
+        position
+    }
+
+    fn data_bit(position : u32) -> Option<u32> {
+        // Inverse of position() - None for check bit positions, and positions past the end
+        if position.is_power_of_two() || !(3..=71).contains(&position) { return None; }
+        Some(position - 1 - (32 - position.leading_zeros()))
+    }
+
+    fn syndrome(data : u64) -> u8 {
+        let mut check = 0;
+        for bit in 0..64 {
+            if data >> bit & 1 == 1 { check ^= position(bit); }
+        }
+        check as u8
+    }
+
+    pub fn encode(data : u64) -> u8 {
+        let check = syndrome(data);
+        let parity = (data.count_ones() + check.count_ones()) % 2;
+        check | (parity as u8) << 7
+    }
+
+    pub fn check(data : u64, stored : u8) -> EccResult {
+        let difference = syndrome(data) ^ (stored & 0x7F);
+        let parity_error = (data.count_ones() + stored.count_ones()) % 2 == 1;
+        match (difference, parity_error) {
+            (0, false) => EccResult::Clean,
+            (_, false) => EccResult::Uncorrectable, // Two bits flipped
+            (0, true) => EccResult::Corrected(data, encode(data)), // The parity bit itself
+            (position, true) if position.is_power_of_two() => EccResult::Corrected(data, encode(data)), // A check bit
+            (position, true) => match data_bit(position as u32) {
+                Some(bit) => EccResult::Corrected(data ^ 1 << bit, stored),
+                None => EccResult::Uncorrectable // Three or more bits flipped
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn samples() -> Vec<u64> {
+            // Edge cases, then a spread of values from a fixed linear congruential sequence
+            let mut values = vec![0, u64::MAX, 1, 1 << 63, 0x5555555555555555, 0xAAAAAAAAAAAAAAAA];
+            let mut value : u64 = 12345;
+            for _ in 0..10 {
+                value = value.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                values.push(value);
+            }
+            values
+        }
+
+        fn flip(data : u64, check : u8, bit : u32) -> (u64, u8) {
+            // Bits 0-63 are data, 64-71 the check byte
+            if bit < 64 { (data ^ 1 << bit, check) } else { (data, check ^ 1 << (bit - 64)) }
+        }
+
+        #[test]
+        fn round_trip() {
+            for data in samples() {
+                assert!(check(data, encode(data)) == EccResult::Clean, "{:016X}", data);
+            }
+        }
+
+        #[test]
+        fn corrects_every_single_bit_flip() {
+            for data in samples() {
+                for bit in 0..72 {
+                    let (bad_data, bad_check) = flip(data, encode(data), bit);
+                    assert!(check(bad_data, bad_check) == EccResult::Corrected(data, encode(data)), "{:016X} bit {}", data, bit);
+                }
+            }
+        }
+
+        #[test]
+        fn detects_every_double_bit_flip() {
+            for data in samples() {
+                for first in 0..72 {
+                    for second in first + 1..72 {
+                        let (bad_data, bad_check) = flip(data, encode(data), first);
+                        let (bad_data, bad_check) = flip(bad_data, bad_check, second);
+                        assert!(check(bad_data, bad_check) == EccResult::Uncorrectable, "{:016X} bits {} and {}", data, first, second);
+                    }
+                }
+            }
+        }
+    }
+}
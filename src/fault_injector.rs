@@ -0,0 +1,240 @@
+pub(crate) mod fault_injector{
+    /*
+     * Fault injection - flips bits in memory, the caches, registers or the buses, to study soft errors
+     *
+     * SCHEDULED FAULTS   --fault <cycle>:<target>[:<bits>]
+     *   Injected once <cycle> cycles have run. <bits> is a '+' separated list of bit numbers (0 is the least
+     *   significant), e.g. 3+17 for a double-bit error - one random bit if not given
+     *   Targets:
+     *     RAM@<addr>      A word of main memory (hex address)
+     *     RAM             A random word of main memory that has been written
+     *     R<n>            General purpose register 1-14
+     *     PC / MIR / MDR
+     *     ADDR_BUS / DATA_BUS
+     *     L1 / L2         A random line of the cache
+     *
+     * RANDOM FAULTS      --fault-random <seed>:<rate>[:<kinds>]
+     *   Each cycle has a <rate> chance (e.g. 0.001) of a single bit flip, in one of the comma separated kinds -
+     *   RAM, REG, BUS or CACHE (RAM only if not given)
+     *
+     * Random choices only depend on the seed and the cycle, so a run with faults can be repeated exactly, and
+     * run backwards - faults are undone along with the rest of the cycle they were injected in
+     */
+    use crate::control_unit::control_unit::ControlUnit;
+    use crate::converter::converter::Converter;
+    use crate::history::history::History;
+    use crate::reg64::reg64::Reg64;
+
+    #[derive(Clone, PartialEq)]
+    pub(crate) enum FaultTarget {
+        Ram(Option<u64>),
+        Register(u8),
+        Pc,
+        Mir,
+        Mdr,
+        AddressBus,
+        DataBus,
+        L1,
+        L2
+    }
+
+    pub(crate) struct Fault {
+        cycle : u64,
+        target : FaultTarget,
+        bits : Vec<u32> // Random bit if empty
+    }
+
+    pub(crate) struct RandomFaults {
+        seed : u64,
+        rate : f64, // Chance of a fault each cycle
+        kinds : Vec<String>
+    }
+
+    #[derive(Default)]
+    pub(crate) struct FaultInjector {
+        pub(crate) faults : Vec<Fault>,
+        pub(crate) random : Option<RandomFaults>,
+        pub(crate) injected : u64
+    }
+
+    fn mix(value : u64) -> u64 {
+        // SplitMix64 - a hash of the value, good enough for a reproducible random choice
+        let mut z = value.wrapping_add(0x9E3779B97F4A7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    fn flip_register(register : &mut Reg64, bits : &[u32]){
+        let mut data = register.get_data();
+        for bit in bits { data[*bit as usize] = !data[*bit as usize]; }
+        register.set_data(data);
+    }
+
+    fn mask(bits : &[u32]) -> u64 {
+        bits.iter().fold(0, |mask, bit| mask | 1 << bit)
+    }
+
+    impl Fault {
+        pub fn parse(spec : &str) -> Result<Fault, String> {
+            let fields : Vec<&str> = spec.split(':').collect();
+            if fields.len() < 2 || fields.len() > 3 { return Err("Fault must be <cycle>:<target>[:<bits>]".to_string()); }
+            let cycle = fields[0].parse().map_err(|_| format!("Invalid fault cycle '{0}'", fields[0]))?;
+            let target = match fields[1].to_uppercase().as_str() {
+                "RAM" => FaultTarget::Ram(None),
+                "PC" => FaultTarget::Pc,
+                "MIR" => FaultTarget::Mir,
+                "MDR" => FaultTarget::Mdr,
+                "ADDR_BUS" => FaultTarget::AddressBus,
+                "DATA_BUS" => FaultTarget::DataBus,
+                "L1" => FaultTarget::L1,
+                "L2" => FaultTarget::L2,
+                other => if let Some(addr) = other.strip_prefix("RAM@") {
+                    FaultTarget::Ram(Some(u64::from_str_radix(addr.trim_start_matches("0X"), 16).map_err(|_| format!("Invalid fault address '{0}'", addr))?))
+                } else if let Some(reg) = other.strip_prefix('R') && let Ok(reg) = reg.parse::<u8>() && (1..15).contains(&reg) {
+                    FaultTarget::Register(reg)
+                } else {
+                    return Err(format!("Unknown fault target '{0}'", fields[1]));
+                }
+            };
+            let limit = if matches!(target, FaultTarget::AddressBus) { 48 } else { 64 };
+            let mut bits = vec![];
+            if let Some(list) = fields.get(2) {
+                for bit in list.split('+') {
+                    bits.push(bit.parse().ok().filter(|bit| *bit < limit).ok_or(format!("Invalid bit number '{0}'", bit))?);
+                }
+            }
+            Ok(Fault { cycle, target, bits })
+        }
+    }
+
+    impl RandomFaults {
+        pub fn parse(spec : &str) -> Result<RandomFaults, String> {
+            let fields : Vec<&str> = spec.split(':').collect();
+            if fields.len() < 2 || fields.len() > 3 { return Err("Random faults must be <seed>:<rate>[:<kinds>]".to_string()); }
+            let seed = fields[0].parse().map_err(|_| format!("Invalid seed '{0}'", fields[0]))?;
+            let rate = fields[1].parse().ok().filter(|rate| (0.0..=1.0).contains(rate)).ok_or(format!("Invalid fault rate '{0}'", fields[1]))?;
+            let kinds : Vec<String> = fields.get(2).unwrap_or(&"RAM").split(',').map(|kind| kind.to_uppercase()).collect();
+            if let Some(kind) = kinds.iter().find(|kind| !["RAM", "REG", "BUS", "CACHE"].contains(&kind.as_str())) {
+                return Err(format!("Unknown fault kind '{0}'", kind));
+            }
+            Ok(RandomFaults { seed, rate, kinds })
+        }
+    }
+
+    impl FaultInjector {
+
+        pub fn inject(&mut self, ctrl : &mut ControlUnit, cycle : u64, mut history : Option<&mut History>){
+            // Called at the start of each cycle, with the number of cycles run so far
+            let seed = self.random.as_ref().map(|random| random.seed).unwrap_or(0);
+            let mut due : Vec<(FaultTarget, Vec<u32>)> = self.faults.iter().filter(|fault| fault.cycle == cycle).map(|fault| (fault.target.clone(), fault.bits.clone())).collect();
+            if let Some(random) = &self.random {
+                let roll = mix(seed ^ mix(cycle));
+                if (roll as f64 / u64::MAX as f64) < random.rate {
+                    let choice = mix(roll);
+                    let target = match random.kinds[(choice % random.kinds.len() as u64) as usize].as_str() {
+                        "REG" => match (choice >> 8) % 17 {
+                            14 => FaultTarget::Pc,
+                            15 => FaultTarget::Mir,
+                            16 => FaultTarget::Mdr,
+                            reg => FaultTarget::Register(reg as u8 + 1)
+                        },
+                        "BUS" => if (choice >> 8).is_multiple_of(2) { FaultTarget::AddressBus } else { FaultTarget::DataBus },
+                        "CACHE" => if (choice >> 8).is_multiple_of(2) { FaultTarget::L1 } else { FaultTarget::L2 },
+                        _ => FaultTarget::Ram(None)
+                    };
+                    due.push((target, vec![]));
+                }
+            }
+
+            for (index, (target, mut bits)) in due.into_iter().enumerate() {
+                let choice = mix(seed ^ mix(cycle) ^ mix(index as u64 + 1)); // Random bit, word or line
+                if bits.is_empty() {
+                    let limit = if target == FaultTarget::AddressBus { 48 } else { 64 };
+                    bits.push((choice % limit) as u32);
+                }
+                let bit_list : Vec<String> = bits.iter().map(|bit| bit.to_string()).collect();
+                let description = match target {
+                    FaultTarget::Ram(addr) => {
+                        let memory = &mut ctrl.data_access_manager.main_memory;
+                        let addr = match addr {
+                            Some(addr) => addr - addr % memory.addressing.word_size(),
+                            None => {
+                                let mut written : Vec<u64> = memory.ram_map.keys().map(|key| Converter::bin_to_dec_pos_only(key.to_vec())).collect();
+                                if written.is_empty() { continue; }
+                                written.sort();
+                                written[((choice >> 16) % written.len() as u64) as usize]
+                            }
+                        };
+                        let key : [bool; 48] = Converter::dec_to_bin_pos_only(addr, 48).try_into().unwrap();
+                        let old_value = memory.ram_map.contains_key(&key).then(|| Converter::bin_to_dec_pos_only(memory.read(key).to_vec()));
+                        if let Some(history) = history.as_mut() { history.note_memory(addr, old_value, memory.check_bits.get(&key).cloned()); }
+                        memory.flip_bits(key, mask(&bits));
+                        format!("RAM {:012X}", addr)
+                    }
+                    FaultTarget::Register(reg) => {
+                        flip_register(&mut ctrl.register_bank.registers[reg as usize], &bits);
+                        format!("R{0}", reg)
+                    }
+                    FaultTarget::Pc => { flip_register(&mut ctrl.pc, &bits); "PC".to_string() }
+                    FaultTarget::Mir => { flip_register(&mut ctrl.memory_instr_reg, &bits); "MIR".to_string() }
+                    FaultTarget::Mdr => { flip_register(&mut ctrl.memory_data_reg, &bits); "MDR".to_string() }
+                    FaultTarget::AddressBus => {
+                        for bit in &bits { ctrl.data_access_manager.main_memory.address_bus.bits[*bit as usize] ^= true; }
+                        "ADDRESS BUS".to_string()
+                    }
+                    FaultTarget::DataBus => {
+                        for bit in &bits { ctrl.data_access_manager.main_memory.data_bus.bits[*bit as usize] ^= true; }
+                        "DATA BUS".to_string()
+                    }
+                    FaultTarget::L1 | FaultTarget::L2 => {
+                        let is_l1 = target == FaultTarget::L1;
+                        let (used, _) = if is_l1 { ctrl.data_access_manager.l1_cache.utilisation() } else { ctrl.data_access_manager.l2_cache.utilisation() };
+                        if used == 0 { continue; }
+                        let line = ((choice >> 16) % used as u64) as usize;
+                        if is_l1 { ctrl.data_access_manager.l1_cache.flip_bits(line, mask(&bits)); } else { ctrl.data_access_manager.l2_cache.flip_bits(line, mask(&bits)); }
+                        format!("{0} LINE {1}", if is_l1 { "L1" } else { "L2" }, line)
+                    }
+                };
+                self.injected += 1;
+                eprintln!("FAULT: cycle {0} - flipped bit {1} of {2}", cycle, bit_list.join("+"), description);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::RunOptions;
+        use crate::ecc::ecc::EccMode;
+        use crate::tests::{program_file, run_program};
+
+        fn run_with_fault(name : &str, fault : &str) -> (u64, u64, u64, String) {
+            // OUTs 7 unless the OUT at 0x300 (flipped on the first cycle, well before it is fetched) can't be corrected,
+            // in which case the machine check handler OUTs the trap cause - returns the injected, corrected and uncorrectable counts
+            let mut lines = vec!["ADD R1, #0, #896", "MTS VEC, R1", "ADD R2, #0, #7"];
+            lines.resize(12, "ADD R2, R2, #0");
+            lines.extend(["OUT R R2", "HLT", "MFS R3, CAUSE", "OUT R R3", "HLT"]);
+            let path = program_file(name, &lines);
+            let options = RunOptions { ecc: EccMode::Memory, faults: vec![Fault::parse(fault).unwrap()], ..RunOptions::default() };
+            let (clk, output) = run_program(&path, options);
+            std::fs::remove_file(&path).ok();
+            let stats = clk.ctrl.data_access_manager.main_memory.ecc_stats;
+            (clk.faults.as_ref().unwrap().injected, stats.corrected, stats.uncorrectable, output)
+        }
+
+        #[test]
+        fn single_bit_fault_corrected() {
+            let (injected, corrected, uncorrectable, output) = run_with_fault("fault_single", "1:RAM@300:5");
+            assert_eq!((injected, corrected, uncorrectable), (1, 1, 0));
+            assert_eq!(output.trim(), "R2 OUTPUT: 7");
+        }
+
+        #[test]
+        fn double_bit_fault_uncorrectable() {
+            let (injected, corrected, uncorrectable, output) = run_with_fault("fault_double", "1:RAM@300:5+40");
+            assert_eq!((injected, corrected, uncorrectable), (1, 0, 1));
+            assert_eq!(output.trim(), "R3 OUTPUT: 4"); // Machine check
+        }
+    }
+}
@@ -0,0 +1,549 @@
+pub(crate) mod fpu{
+    /*
+     * Floating-point unit - IEEE 754 double precision, with its own file of 16 registers, F0-F15
+     *
+     * Significands go through the same gate-level adder/subtractor as the ALU, a 64-bit half at a time with the carry
+     * passed between the halves - directly for addition and subtraction, as shift-and-add for multiplication, and as
+     * shift-and-subtract (one result bit per step) for division and square root
+     * Unpacking, alignment, normalisation and rounding around them are plain integer logic
+     *
+     * FPSR - Bit 0: Inexact, Bit 1: Underflow, Bit 2: Overflow, Bit 3: Divide by zero, Bit 4: Invalid, Bits 5-6: Rounding mode
+     * Results are rounded with the FPSR's mode, and exceptions raised are ORed into its flags - they stay set until written
+     * Underflow is raised for an inexact result which was tiny (below 2^-1022) before rounding
+     * NaN results are always the default quiet NaN - payloads aren't carried through
+     */
+    use std::cmp::Ordering;
+    use num_derive::FromPrimitive;
+    use num_traits::FromPrimitive;
+    use crate::adders::adders::AddSub64bit;
+    use crate::converter::converter::Converter;
+    use crate::reg64::reg64::Reg64;
+
+    const DEFAULT_NAN : u64 = 0x7FF8_0000_0000_0000;
+    const INFINITY : u64 = 0x7FF0_0000_0000_0000;
+    const SIGN : u64 = 1 << 63;
+    const BIAS : i32 = 1023;
+    const TOP : u32 = 62; // Significands are worked on with their leading 1 here - leaving 10 bits below the 53 kept, for rounding
+    const ROUND_BITS : u32 = TOP - 52;
+
+    #[repr(u8)]
+    #[derive(Clone, Copy, PartialEq, FromPrimitive)]
+    pub(crate) enum RoundingMode {
+        Nearest = 0, // Ties to even
+        Zero = 1,
+        Down = 2, // Towards -infinity
+        Up = 3 // Towards +infinity
+    }
+
+    #[derive(PartialEq)]
+    enum Class {
+        Zero,
+        Finite,
+        Infinite,
+        NaN
+    }
+
+    struct Unpacked {
+        sign : bool,
+        class : Class,
+        exp : i32, // Finite values are sig * 2^(exp - TOP), with the leading 1 of sig at TOP
+        sig : u64
+    }
+
+    fn word(value : u64) -> [bool; 64] { Converter::dec_to_bin_pos_only(value, 64).try_into().unwrap() }
+    fn value(bits : [bool; 64]) -> u64 { Converter::bin_to_dec_pos_only(bits.to_vec()) }
+
+    fn shift_right_sticky(value : u128, shift : u32) -> u128 {
+        // Shift right, ORing anything shifted out into the lowest bit - so rounding can still tell the result was inexact
+        if shift == 0 { value }
+        else if shift >= 128 { (value != 0) as u128 }
+        else { value >> shift | (value & ((1 << shift) - 1) != 0) as u128 }
+    }
+
+    fn normalise(exp : i32, sig : u64) -> (i32, u64) {
+        // Move the leading 1 of a non-zero significand to TOP
+        let leading_zeros = sig.leading_zeros() as i32;
+        let shift = leading_zeros - (63 - TOP as i32);
+        if shift >= 0 { (exp - shift, sig << shift) }
+        else { (exp + 1, shift_right_sticky(sig as u128, 1) as u64) }
+    }
+
+    fn unpack(bits : u64) -> Unpacked {
+        let sign = bits & SIGN != 0;
+        let field = (bits >> 52 & 0x7FF) as i32;
+        let mantissa = bits & ((1 << 52) - 1);
+        match (field, mantissa) {
+            (0, 0) => Unpacked { sign, class: Class::Zero, exp: 0, sig: 0 },
+            (0x7FF, 0) => Unpacked { sign, class: Class::Infinite, exp: 0, sig: 0 },
+            (0x7FF, _) => Unpacked { sign, class: Class::NaN, exp: 0, sig: 0 },
+            _ => {
+                // Subnormals have no hidden bit, and the exponent of the smallest normal
+                let (exp, sig) = if field == 0 { (1 - BIAS, mantissa) } else { (field - BIAS, mantissa | 1 << 52) };
+                let (exp, sig) = normalise(exp, sig << ROUND_BITS);
+                Unpacked { sign, class: Class::Finite, exp, sig }
+            }
+        }
+    }
+
+    fn signalling(bits : u64) -> bool {
+        // A NaN with the top mantissa bit clear
+        bits & INFINITY == INFINITY && bits & (1 << 51) == 0 && bits & ((1 << 51) - 1) != 0
+    }
+
+    pub(crate) struct Fpu {
+        adder_subtractor_64bit : AddSub64bit,
+        pub(crate) registers : [Reg64; 16],
+        pub(crate) rounding : RoundingMode,
+        pub(crate) nx : bool, // Inexact
+        pub(crate) uf : bool, // Underflow
+        pub(crate) of : bool, // Overflow
+        pub(crate) dz : bool, // Divide by zero
+        pub(crate) nv : bool  // Invalid operation
+    }
+
+    impl Fpu {
+
+        pub fn get_data(&self, index : [bool; 4]) -> [bool; 64] {
+            self.registers[Converter::bin_to_dec_pos_only(index.to_vec()) as usize].get_data()
+        }
+
+        pub fn set_data(&mut self, index : [bool; 4], data : [bool; 64]){
+            self.registers[Converter::bin_to_dec_pos_only(index.to_vec()) as usize].set_data(data);
+        }
+
+        pub fn get_status(&self) -> [bool; 64] {
+            let mut status = [false; 64];
+            status[0..5].copy_from_slice(&[self.nx, self.uf, self.of, self.dz, self.nv]);
+            status[5..7].copy_from_slice(&Converter::dec_to_bin_pos_only(self.rounding as u64, 2));
+            status
+        }
+
+        pub fn set_status(&mut self, status : [bool; 64]){
+            [self.nx, self.uf, self.of, self.dz, self.nv] = [status[0], status[1], status[2], status[3], status[4]];
+            self.rounding = RoundingMode::from_u64(Converter::bin_to_dec_pos_only(status[5..7].to_vec())).unwrap_or(RoundingMode::Nearest);
+        }
+
+        fn add_sub(&self, val0 : u128, val1 : u128, add : bool) -> (u128, bool) {
+            // 128-bit addition or subtraction on the 64-bit adder - the low halves, then the high halves with the carry (or borrow) between them
+            let (low, carry) = self.adder_subtractor_64bit.value_with_carry(word(val0 as u64), word(val1 as u64), false, add);
+            let (high, carry) = self.adder_subtractor_64bit.value_with_carry(word((val0 >> 64) as u64), word((val1 >> 64) as u64), carry, add);
+            ((value(high) as u128) << 64 | value(low) as u128, carry)
+        }
+
+        fn round_up(&self, sign : bool, odd : bool, versus_half : Ordering, inexact : bool) -> bool {
+            // Whether to round the magnitude up, given the bits rounded off compared to half of the last bit kept
+            match self.rounding {
+                RoundingMode::Nearest => versus_half == Ordering::Greater || (versus_half == Ordering::Equal && odd),
+                RoundingMode::Zero => false,
+                RoundingMode::Down => sign && inexact,
+                RoundingMode::Up => !sign && inexact
+            }
+        }
+
+        fn invalid(&mut self) -> u64 {
+            self.nv = true;
+            DEFAULT_NAN
+        }
+
+        fn nan(&mut self, operands : &[u64]) -> u64 {
+            // Any NaN operand gives a NaN - only a signalling one is an invalid operation
+            if operands.iter().any(|operand| signalling(*operand)) { self.nv = true; }
+            DEFAULT_NAN
+        }
+
+        fn exact_zero(&self) -> u64 {
+            // x - x is +0, except when rounding down
+            if self.rounding == RoundingMode::Down { SIGN } else { 0 }
+        }
+
+        fn pack(&mut self, sign : bool, exp : i32, sig : u64) -> u64 {
+            // Round a finite non-zero result to 53 bits, raising Inexact, Underflow and Overflow
+            let sign_bit = if sign { SIGN } else { 0 };
+            let mut biased = exp + BIAS;
+            let mut sig = sig;
+            let tiny = biased < 1;
+            if tiny { // Subnormal - shifted down to the smallest exponent
+                sig = shift_right_sticky(sig as u128, (1 - biased) as u32) as u64;
+                biased = 1;
+            }
+            let rounded_off = sig & ((1 << ROUND_BITS) - 1);
+            let mut kept = sig >> ROUND_BITS;
+            if self.round_up(sign, kept & 1 == 1, rounded_off.cmp(&(1 << (ROUND_BITS - 1))), rounded_off != 0) { kept += 1; }
+            if rounded_off != 0 {
+                self.nx = true;
+                if tiny { self.uf = true; }
+            }
+            // The hidden bit adds into the exponent field - a subnormal rounded up to 2^-1022 becomes normal, and 2^53 moves up an exponent
+            if biased < 0x7FF {
+                let bits = ((biased as u64 - 1) << 52) + kept;
+                if bits < INFINITY { return sign_bit | bits; }
+            }
+            self.of = true;
+            self.nx = true;
+            let to_infinity = match self.rounding {
+                RoundingMode::Nearest => true,
+                RoundingMode::Zero => false,
+                RoundingMode::Down => sign,
+                RoundingMode::Up => !sign
+            };
+            sign_bit | if to_infinity { INFINITY } else { INFINITY - 1 } // Otherwise the largest finite value
+        }
+
+        fn add_values(&mut self, val0 : u64, val1 : u64) -> u64 {
+            let (a, b) = (unpack(val0), unpack(val1));
+            match (&a.class, &b.class) {
+                (Class::NaN, _) | (_, Class::NaN) => return self.nan(&[val0, val1]),
+                (Class::Infinite, Class::Infinite) if a.sign != b.sign => return self.invalid(),
+                (Class::Infinite, _) => return val0,
+                (_, Class::Infinite) => return val1,
+                (Class::Zero, Class::Zero) => return if a.sign == b.sign { val0 } else { self.exact_zero() },
+                (Class::Zero, _) => return val1,
+                (_, Class::Zero) => return val0,
+                _ => {}
+            }
+            // Align the smaller magnitude to the larger's exponent
+            let (large, small) = if (a.exp, a.sig) >= (b.exp, b.sig) { (a, b) } else { (b, a) };
+            let aligned = shift_right_sticky(small.sig as u128, (large.exp - small.exp) as u32);
+            let (result, _) = self.add_sub(large.sig as u128, aligned, large.sign == small.sign);
+            if result == 0 { return self.exact_zero(); }
+            let (exp, sig) = normalise(large.exp, result as u64);
+            self.pack(large.sign, exp, sig)
+        }
+
+        pub fn add(&mut self, val0 : [bool; 64], val1 : [bool; 64]) -> [bool; 64] {
+            word(self.add_values(value(val0), value(val1)))
+        }
+
+        pub fn sub(&mut self, val0 : [bool; 64], val1 : [bool; 64]) -> [bool; 64] {
+            word(self.add_values(value(val0), value(val1) ^ SIGN))
+        }
+
+        pub fn mul(&mut self, val0 : [bool; 64], val1 : [bool; 64]) -> [bool; 64] {
+            let (val0, val1) = (value(val0), value(val1));
+            let (a, b) = (unpack(val0), unpack(val1));
+            let sign = if a.sign != b.sign { SIGN } else { 0 };
+            match (&a.class, &b.class) {
+                (Class::NaN, _) | (_, Class::NaN) => return word(self.nan(&[val0, val1])),
+                (Class::Infinite, Class::Zero) | (Class::Zero, Class::Infinite) => return word(self.invalid()),
+                (Class::Infinite, _) | (_, Class::Infinite) => return word(sign | INFINITY),
+                (Class::Zero, _) | (_, Class::Zero) => return word(sign),
+                _ => {}
+            }
+            // Shift-and-add on the 53-bit significands, for a 106-bit product - a value of product * 2^(exp_a + exp_b - 104)
+            let (sig_a, sig_b) = (a.sig >> ROUND_BITS, b.sig >> ROUND_BITS);
+            let mut product = 0;
+            for bit in 0..53 {
+                if sig_b >> bit & 1 == 1 { product = self.add_sub(product, (sig_a as u128) << bit, true).0; }
+            }
+            let (exp, sig) = normalise(a.exp + b.exp, shift_right_sticky(product, 104 - TOP) as u64);
+            word(self.pack(sign != 0, exp, sig))
+        }
+
+        pub fn div(&mut self, val0 : [bool; 64], val1 : [bool; 64]) -> [bool; 64] {
+            let (val0, val1) = (value(val0), value(val1));
+            let (a, b) = (unpack(val0), unpack(val1));
+            let sign = if a.sign != b.sign { SIGN } else { 0 };
+            match (&a.class, &b.class) {
+                (Class::NaN, _) | (_, Class::NaN) => return word(self.nan(&[val0, val1])),
+                (Class::Infinite, Class::Infinite) | (Class::Zero, Class::Zero) => return word(self.invalid()),
+                (Class::Infinite, _) => return word(sign | INFINITY),
+                (_, Class::Infinite) | (Class::Zero, _) => return word(sign),
+                (_, Class::Zero) => {
+                    self.dz = true;
+                    return word(sign | INFINITY);
+                }
+                _ => {}
+            }
+            // Restoring division, one quotient bit per step - sig_a * 2^TOP / sig_b, between 2^(TOP - 1) and 2^(TOP + 1)
+            let (sig_a, sig_b) = (a.sig >> ROUND_BITS, b.sig >> ROUND_BITS);
+            let (mut remainder, mut quotient) = (sig_a as u128, 0u64);
+            for _ in 0..=TOP {
+                quotient <<= 1;
+                let (difference, borrow) = self.add_sub(remainder, sig_b as u128, false);
+                if !borrow {
+                    remainder = difference;
+                    quotient |= 1;
+                }
+                remainder <<= 1;
+            }
+            let (exp, sig) = normalise(a.exp - b.exp, quotient | (remainder != 0) as u64);
+            word(self.pack(sign != 0, exp, sig))
+        }
+
+        pub fn sqrt(&mut self, val0 : [bool; 64]) -> [bool; 64] {
+            let val0 = value(val0);
+            let a = unpack(val0);
+            match a.class {
+                Class::NaN => return word(self.nan(&[val0])),
+                Class::Zero => return word(val0), // Keeps its sign
+                _ if a.sign => return word(self.invalid()),
+                Class::Infinite => return word(val0),
+                Class::Finite => {}
+            }
+            // Halving the exponent needs it even - a value of radicand * 2^(exp - 52)
+            let (mut exp, mut radicand) = (a.exp, a.sig >> ROUND_BITS);
+            if exp % 2 != 0 {
+                radicand <<= 1;
+                exp -= 1;
+            }
+            // Digit by digit, two radicand bits per root bit - radicand * 2^72 is between 2^124 and 2^126, so its root is between 2^TOP and 2^(TOP + 1)
+            let radicand = (radicand as u128) << 72;
+            let (mut remainder, mut root) = (0u128, 0u128);
+            for pair in (0..=TOP).rev() {
+                remainder = remainder << 2 | (radicand >> (pair * 2) & 3);
+                let trial = root << 2 | 1;
+                root <<= 1;
+                let (difference, borrow) = self.add_sub(remainder, trial, false);
+                if !borrow {
+                    remainder = difference;
+                    root |= 1;
+                }
+            }
+            let (exp, sig) = normalise(exp / 2, root as u64 | (remainder != 0) as u64);
+            word(self.pack(false, exp, sig))
+        }
+
+        pub fn compare(&mut self, val0 : [bool; 64], val1 : [bool; 64]) -> Option<Ordering> {
+            // None if either is a NaN (unordered) - -0 and +0 are equal
+            let (val0, val1) = (value(val0), value(val1));
+            if unpack(val0).class == Class::NaN || unpack(val1).class == Class::NaN {
+                self.nan(&[val0, val1]);
+                return None;
+            }
+            let key = |bits : u64| -> i128 { if bits & SIGN != 0 { -((bits & !SIGN) as i128) } else { bits as i128 } };
+            Some(key(val0).cmp(&key(val1)))
+        }
+
+        pub fn int_to_float(&mut self, val0 : [bool; 64]) -> [bool; 64] {
+            // Signed 64-bit integer to double - rounded if it has more than 53 significant bits
+            let integer = value(val0) as i64;
+            if integer == 0 { return [false; 64]; }
+            let (exp, sig) = normalise(TOP as i32, integer.unsigned_abs());
+            word(self.pack(integer < 0, exp, sig))
+        }
+
+        pub fn float_to_int(&mut self, val0 : [bool; 64]) -> [bool; 64] {
+            // Double to signed 64-bit integer, rounded with the rounding mode - NaN gives 0, and anything out of range saturates, both Invalid
+            let bits = value(val0);
+            let a = unpack(bits);
+            let saturated = if a.sign { i64::MIN } else { i64::MAX };
+            match a.class {
+                Class::NaN => {
+                    self.nv = true;
+                    return [false; 64];
+                }
+                Class::Infinite => {
+                    self.nv = true;
+                    return word(saturated as u64);
+                }
+                Class::Zero => return [false; 64],
+                Class::Finite => {}
+            }
+            if a.exp > TOP as i32 { // At least 2^63 in magnitude - only -2^63 itself fits
+                if !(a.sign && a.exp == TOP as i32 + 1 && a.sig == 1 << TOP) { self.nv = true; }
+                return word(saturated as u64);
+            }
+            let shift = (TOP as i32 - a.exp) as u32;
+            let (whole, round_bits) = if shift < 2 { (a.sig >> shift, 0) } else {
+                // Keep a half bit and a sticky bit below the integer
+                let shifted = shift_right_sticky(a.sig as u128, shift - 2) as u64;
+                (shifted >> 2, shifted & 3)
+            };
+            let magnitude = whole + self.round_up(a.sign, whole & 1 == 1, round_bits.cmp(&2), round_bits != 0) as u64;
+            if magnitude > if a.sign { 1 << 63 } else { i64::MAX as u64 } {
+                self.nv = true;
+                return word(saturated as u64);
+            }
+            if round_bits != 0 { self.nx = true; }
+            word(if a.sign { magnitude.wrapping_neg() } else { magnitude })
+        }
+    }
+
+    impl Default for Fpu {
+        fn default() -> Self {
+            Fpu {
+                adder_subtractor_64bit: AddSub64bit::default(),
+                registers: [Reg64::default(); 16],
+                rounding: RoundingMode::Nearest,
+                nx: false, uf: false, of: false, dz: false, nv: false
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[derive(Clone, Copy)]
+        enum Op { Add, Sub, Mul, Div, Sqrt }
+
+        const OPS : [Op; 5] = [Op::Add, Op::Sub, Op::Mul, Op::Div, Op::Sqrt];
+
+        fn mix(value : u64) -> u64 {
+            // SplitMix64, for a fixed sequence of operands
+            let mut z = value.wrapping_add(0x9E3779B97F4A7C15);
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+            z ^ (z >> 31)
+        }
+
+        fn operand(seed : u64, moderate : bool) -> f64 {
+            // Any bit pattern, or one with an exponent near 1 - so results are normal, and additions overlap and cancel
+            let bits = mix(seed);
+            if !moderate && bits & 1 == 0 { return f64::from_bits(bits); }
+            f64::from_bits(bits & !(0x7FF << 52) | (1013 + mix(bits) % 20) << 52)
+        }
+
+        fn run(fpu : &mut Fpu, op : Op, a : f64, b : f64) -> f64 {
+            let (a, b) = (word(a.to_bits()), word(b.to_bits()));
+            f64::from_bits(value(match op {
+                Op::Add => fpu.add(a, b),
+                Op::Sub => fpu.sub(a, b),
+                Op::Mul => fpu.mul(a, b),
+                Op::Div => fpu.div(a, b),
+                Op::Sqrt => fpu.sqrt(a)
+            }))
+        }
+
+        fn native(op : Op, a : f64, b : f64) -> f64 {
+            match op {
+                Op::Add => a + b,
+                Op::Sub => a - b,
+                Op::Mul => a * b,
+                Op::Div => a / b,
+                Op::Sqrt => a.sqrt()
+            }
+        }
+
+        fn error_sign(op : Op, a : f64, b : f64, rounded : f64) -> Ordering {
+            // How the exact result compares to the correctly rounded one - each residual is exact while the result is normal
+            let residual = match op {
+                Op::Add | Op::Sub => {
+                    let b = if let Op::Sub = op { -b } else { b };
+                    let b_part = rounded - a;
+                    (a - (rounded - b_part)) + (b - b_part) // TwoSum
+                }
+                Op::Mul => a.mul_add(b, -rounded),
+                Op::Div => (-rounded).mul_add(b, a) * b.signum(),
+                Op::Sqrt => (-rounded).mul_add(rounded, a)
+            };
+            residual.partial_cmp(&0.0).unwrap()
+        }
+
+        fn same(ours : f64, expected : f64) -> bool {
+            // Bit for bit, apart from NaNs, which are always the default NaN here
+            if expected.is_nan() { ours.to_bits() == DEFAULT_NAN } else { ours.to_bits() == expected.to_bits() }
+        }
+
+        fn with_rounding(rounding : RoundingMode) -> Fpu {
+            Fpu { rounding, ..Fpu::default() }
+        }
+
+        fn flags(fpu : &Fpu) -> [bool; 5] {
+            [fpu.nx, fpu.uf, fpu.of, fpu.dz, fpu.nv]
+        }
+
+        #[test]
+        fn matches_native_nearest() {
+            for case in 0..300 {
+                let (a, b) = (operand(case * 2, false), operand(case * 2 + 1, false));
+                for op in OPS {
+                    let a = if let Op::Sqrt = op { a.abs() } else { a };
+                    let ours = run(&mut Fpu::default(), op, a, b);
+                    assert!(same(ours, native(op, a, b)), "case {0}: {1:e} {2:e} gave {3:e}, expected {4:e}", case, a, b, ours, native(op, a, b));
+                }
+            }
+        }
+
+        #[test]
+        fn directed_rounding() {
+            // The nearest result, or the double next to it on the side each mode rounds towards
+            let modes = [RoundingMode::Zero, RoundingMode::Down, RoundingMode::Up];
+            for case in 0..200 {
+                let (a, b) = (operand(case * 2, true), operand(case * 2 + 1, true));
+                for op in OPS {
+                    let a = if let Op::Sqrt = op { a.abs() } else { a };
+                    let nearest = native(op, a, b);
+                    let error = error_sign(op, a, b, nearest);
+                    for mode in modes {
+                        let towards_down = match mode {
+                            RoundingMode::Down => true,
+                            RoundingMode::Up => false,
+                            _ => nearest > 0.0
+                        };
+                        let expected = match error {
+                            Ordering::Less if towards_down => nearest.next_down(),
+                            Ordering::Greater if !towards_down => nearest.next_up(),
+                            _ => nearest
+                        };
+                        let mut fpu = with_rounding(mode);
+                        let ours = run(&mut fpu, op, a, b);
+                        assert!(same(ours, expected), "case {0}: {1:e} {2:e} gave {3:e}, expected {4:e}", case, a, b, ours, expected);
+                        assert_eq!(fpu.nx, error != Ordering::Equal);
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn fixed_rounding_cases() {
+            let tiny = 2f64.powi(-60);
+            let cases = [
+                // (a + b, nearest, towards zero, down, up)
+                (1.0, tiny, 1.0, 1.0, 1.0, 1f64.next_up()),
+                (-1.0, -tiny, -1.0, -1.0, (-1f64).next_down(), -1.0),
+                (1.0, 2f64.powi(-53), 1.0, 1.0, 1.0, 1f64.next_up()), // A tie - to even
+                (1f64.next_up(), 2f64.powi(-53), 1f64.next_up().next_up(), 1f64.next_up(), 1f64.next_up(), 1f64.next_up().next_up()),
+                (f64::MAX, f64::MAX, f64::INFINITY, f64::MAX, f64::MAX, f64::INFINITY),
+                (-f64::MAX, -f64::MAX, f64::NEG_INFINITY, -f64::MAX, f64::NEG_INFINITY, -f64::MAX),
+                (1.0, -1.0, 0.0, 0.0, -0.0, 0.0) // Exact zero
+            ];
+            for (a, b, nearest, zero, down, up) in cases {
+                for (mode, expected) in [(RoundingMode::Nearest, nearest), (RoundingMode::Zero, zero), (RoundingMode::Down, down), (RoundingMode::Up, up)] {
+                    let ours = run(&mut with_rounding(mode), Op::Add, a, b);
+                    assert!(same(ours, expected), "{0:e} + {1:e} gave {2:e}, expected {3:e}", a, b, ours, expected);
+                }
+            }
+            let third = run(&mut with_rounding(RoundingMode::Up), Op::Div, 1.0, 3.0);
+            assert_eq!(third.to_bits(), (1.0f64 / 3.0).to_bits() + 1);
+        }
+
+        #[test]
+        fn exception_flags() {
+            let min_normal = f64::MIN_POSITIVE;
+            let cases = [
+                // (op, a, b, [NX, UF, OF, DZ, NV])
+                (Op::Add, 1.0, 2.0, [false, false, false, false, false]),
+                (Op::Div, 1.0, 3.0, [true, false, false, false, false]),
+                (Op::Mul, min_normal, 0.5, [false, false, false, false, false]), // Tiny, but exact
+                (Op::Mul, min_normal.next_up(), 0.5, [true, true, false, false, false]),
+                (Op::Mul, f64::MAX, 2.0, [true, false, true, false, false]),
+                (Op::Div, 1.0, 0.0, [false, false, false, true, false]),
+                (Op::Div, 0.0, 0.0, [false, false, false, false, true]),
+                (Op::Sub, f64::INFINITY, f64::INFINITY, [false, false, false, false, true]),
+                (Op::Mul, f64::INFINITY, 0.0, [false, false, false, false, true]),
+                (Op::Sqrt, -1.0, 0.0, [false, false, false, false, true]),
+                (Op::Add, f64::NAN, 1.0, [false, false, false, false, false]), // Quiet NaN
+                (Op::Add, f64::from_bits(0x7FF0_0000_0000_0001), 1.0, [false, false, false, false, true]) // Signalling NaN
+            ];
+            for (op, a, b, expected) in cases {
+                let mut fpu = Fpu::default();
+                run(&mut fpu, op, a, b);
+                assert_eq!(flags(&fpu), expected, "{0:e} {1:e}", a, b);
+            }
+        }
+
+        #[test]
+        fn conversions() {
+            let mut fpu = Fpu::default();
+            assert_eq!(value(fpu.int_to_float(word((1 << 53) + 1))), ((1u64 << 53) as f64).to_bits()); // Ties to even
+            assert!(fpu.nx);
+            let mut fpu = with_rounding(RoundingMode::Zero);
+            assert_eq!(value(fpu.float_to_int(word((-2.75f64).to_bits()))) as i64, -2);
+            assert!(fpu.nx && !fpu.nv);
+            let mut fpu = Fpu::default();
+            assert_eq!(value(fpu.float_to_int(word(1e300f64.to_bits()))) as i64, i64::MAX);
+            assert!(fpu.nv);
+        }
+    }
+}
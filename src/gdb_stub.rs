@@ -0,0 +1,340 @@
+pub(crate) mod gdb_stub{
+    /*
+     * GDB Remote Serial Protocol server, so programs can be debugged with a stock gdb/lldb:
+     *   (gdb) target remote localhost:<port>
+     *
+     * Registers (all 64-bit, little-endian on the wire): r0-r14, pc, flags
+     * Flags - Bit 0: Z, Bit 1: N, Bit 2: V, Bit 3: C, Bit 8: CPU is in User mode (read only)
+     *
+     * With byte addressing, gdb sees the emulator's addresses as they are. In word addressing mode they are bit
+     * addresses (one 64-bit word every 0x40), so gdb sees every address divided by 8 - each emulator word then
+     * appears as 8 consecutive bytes
+     *
+     * reverse-stepi and reverse-continue are supported, using the execution history (see history.rs)
+     */
+    use std::collections::HashSet;
+    use std::io::{ErrorKind, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use crate::clock::clock::Clock;
+    use crate::converter::converter::Converter;
+    use crate::history::history;
+    use crate::system_regs::system_regs::PrivilegeLevel;
+
+    const REGISTER_COUNT : usize = 17;
+    const SIGINT : u8 = 2;
+    const SIGTRAP : u8 = 5;
+    const REPLAY_BEGIN : &str = "T05replaylog:begin;"; // Stopped at the start of the execution history
+
+    const TARGET_XML : &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.cpu_emu.core">
+    <reg name="r0" bitsize="64" type="int64" regnum="0"/>
+    <reg name="r1" bitsize="64" type="int64"/>
+    <reg name="r2" bitsize="64" type="int64"/>
+    <reg name="r3" bitsize="64" type="int64"/>
+    <reg name="r4" bitsize="64" type="int64"/>
+    <reg name="r5" bitsize="64" type="int64"/>
+    <reg name="r6" bitsize="64" type="int64"/>
+    <reg name="r7" bitsize="64" type="int64"/>
+    <reg name="r8" bitsize="64" type="int64"/>
+    <reg name="r9" bitsize="64" type="int64"/>
+    <reg name="r10" bitsize="64" type="int64"/>
+    <reg name="r11" bitsize="64" type="int64"/>
+    <reg name="r12" bitsize="64" type="int64"/>
+    <reg name="r13" bitsize="64" type="int64"/>
+    <reg name="r14" bitsize="64" type="int64"/>
+    <reg name="pc" bitsize="64" type="code_ptr"/>
+    <reg name="flags" bitsize="64" type="int64"/>
+  </feature>
+</target>
+"#;
+
+    pub(crate) struct GdbStub {
+        stream : TcpStream,
+        breakpoints : HashSet<u64>, // Emulator addresses
+        no_ack : bool
+    }
+
+    pub fn serve(clock : &mut Clock, port : u16){
+        // Waits for gdb to attach, then hands control of the clock to it until it detaches or kills the target
+        let listener = TcpListener::bind(("127.0.0.1", port)).expect("GDB Socket Error");
+        println!(" ----- WAITING FOR GDB ON localhost:{0} -----", port);
+        let (stream, _) = listener.accept().expect("GDB Socket Error");
+        println!(" ----- GDB CONNECTED -----");
+        let mut stub = GdbStub { stream, breakpoints: HashSet::new(), no_ack: false };
+        while let Some(packet) = stub.read_packet() {
+            if !stub.handle_packet(clock, &packet) { break; }
+        }
+        println!(" ----- GDB DISCONNECTED -----");
+        if !clock.ctrl.halt { clock.start(); } // Carry on without the debugger
+    }
+
+    impl GdbStub {
+
+        fn read_byte(&mut self) -> Option<u8> {
+            let mut byte = [0u8; 1];
+            match self.stream.read(&mut byte) {
+                Ok(1) => Some(byte[0]),
+                _ => None
+            }
+        }
+
+        fn read_packet(&mut self) -> Option<String> {
+            // Packets are '$<data>#<2 hex digit checksum>' - anything outside of a packet (acks, Ctrl-C) is skipped
+            loop {
+                let byte = self.read_byte()?;
+                if byte != b'$' { continue; }
+                let mut data = vec![];
+                loop {
+                    let byte = self.read_byte()?;
+                    if byte == b'#' { break; }
+                    data.push(byte);
+                }
+                let checksum = [self.read_byte()?, self.read_byte()?];
+                let expected = u8::from_str_radix(&String::from_utf8_lossy(&checksum), 16).unwrap_or(0);
+                let actual = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+                if !self.no_ack {
+                    let _ = self.stream.write_all(if expected == actual { b"+" } else { b"-" });
+                }
+                if expected == actual {
+                    return Some(String::from_utf8_lossy(&data).to_string());
+                }
+            }
+        }
+
+        fn write_packet(&mut self, data : &str){
+            let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+            let packet = format!("${0}#{1:02x}", data, checksum);
+            let _ = self.stream.write_all(packet.as_bytes()); // gdb's acknowledgement is skipped by read_packet
+        }
+
+        fn interrupt_requested(&mut self) -> bool {
+            // Ctrl-C is sent as a single 0x03 byte, outside of any packet
+            let mut byte = [0u8; 1];
+            self.stream.set_nonblocking(true).expect("GDB Socket Error");
+            let result = self.stream.peek(&mut byte);
+            self.stream.set_nonblocking(false).expect("GDB Socket Error");
+            match result {
+                Ok(1) if byte[0] == 0x03 => { let _ = self.read_byte(); true }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => false,
+                _ => false
+            }
+        }
+
+        fn handle_packet(&mut self, clock : &mut Clock, packet : &str) -> bool {
+            // Replies to a packet - returns false once the session has ended
+            let reply = match packet.chars().next().unwrap_or(' ') {
+                '?' => Self::stop_reply(clock, SIGTRAP),
+                'g' => (0..REGISTER_COUNT).map(|reg| Self::hex_u64(Self::read_register(clock, reg))).collect(),
+                'G' => {
+                    for reg in 0..REGISTER_COUNT {
+                        if let Some(value) = Self::parse_le_u64(packet.get(1 + reg * 16..17 + reg * 16).unwrap_or("")) {
+                            Self::write_register(clock, reg, value);
+                        }
+                    }
+                    "OK".to_string()
+                }
+                'p' => match usize::from_str_radix(&packet[1..], 16) {
+                    Ok(reg) if reg < REGISTER_COUNT => Self::hex_u64(Self::read_register(clock, reg)),
+                    _ => "E01".to_string()
+                },
+                'P' => {
+                    let (reg, value) = packet[1..].split_once('=').unwrap_or(("", ""));
+                    match (usize::from_str_radix(reg, 16), Self::parse_le_u64(value)) {
+                        (Ok(reg), Some(value)) if reg < REGISTER_COUNT => { Self::write_register(clock, reg, value); "OK".to_string() }
+                        _ => "E01".to_string()
+                    }
+                }
+                'm' => {
+                    let (addr, length) = packet[1..].split_once(',').unwrap_or(("", ""));
+                    match (u64::from_str_radix(addr, 16), u64::from_str_radix(length, 16)) {
+                        (Ok(addr), Ok(length)) => match addr.checked_add(length) {
+                            Some(end) => (addr..end).map(|byte_addr| format!("{:02x}", Self::read_byte_at(clock, byte_addr))).collect(),
+                            None => "E01".to_string() // Reads past the top of the address space
+                        },
+                        _ => "E01".to_string()
+                    }
+                }
+                'M' => {
+                    let (header, data) = packet[1..].split_once(':').unwrap_or(("", ""));
+                    let (addr, length) = header.split_once(',').unwrap_or(("", ""));
+                    let bytes : Option<Vec<u8>> = if data.len() % 2 == 0 && data.is_ascii() {
+                        (0..data.len()).step_by(2).map(|index| u8::from_str_radix(&data[index..index + 2], 16).ok()).collect()
+                    } else { None };
+                    match (u64::from_str_radix(addr, 16), u64::from_str_radix(length, 16), bytes) {
+                        (Ok(addr), Ok(length), Some(bytes)) if length == bytes.len() as u64 && addr.checked_add(length).is_some() => {
+                            for (byte_addr, byte) in (addr..).zip(bytes) {
+                                Self::write_byte_at(clock, byte_addr, byte);
+                            }
+                            "OK".to_string()
+                        }
+                        _ => "E01".to_string()
+                    }
+                }
+                's' => {
+                    if clock.ctrl.halt || self.step(clock) { Self::stop_reply(clock, SIGTRAP) } else { Self::stop_reply(clock, SIGINT) }
+                }
+                'c' => self.run(clock),
+                'b' if packet == "bs" => {
+                    // Reverse step - needs the execution history
+                    if history::step_back_instruction(clock) { Self::stop_reply(clock, SIGTRAP) } else { REPLAY_BEGIN.to_string() }
+                }
+                'b' if packet == "bc" => self.run_backwards(clock),
+                'Z' | 'z' => {
+                    // Software and hardware breakpoints are handled the same way - Z<type>,<addr>,<kind>
+                    let fields : Vec<&str> = packet[1..].split(',').collect();
+                    match (fields.first(), fields.get(1).and_then(|addr| u64::from_str_radix(addr, 16).ok())) {
+                        (Some(&"0"), Some(addr)) | (Some(&"1"), Some(addr)) => {
+                            if packet.starts_with('Z') { self.breakpoints.insert(addr * Self::address_scale(clock)); }
+                            else { self.breakpoints.remove(&(addr * Self::address_scale(clock))); }
+                            "OK".to_string()
+                        }
+                        _ => "".to_string() // Watchpoints are not supported
+                    }
+                }
+                'H' => "OK".to_string(),
+                'k' => return false,
+                'D' => { self.write_packet("OK"); return false; }
+                'q' => self.handle_query(packet),
+                'Q' => {
+                    if packet == "QStartNoAckMode" {
+                        self.write_packet("OK"); // Still acknowledged - no-ack mode starts after this reply
+                        self.no_ack = true;
+                        return true;
+                    }
+                    "".to_string()
+                }
+                _ => "".to_string() // Empty reply - not supported
+            };
+            self.write_packet(&reply);
+            true
+        }
+
+        fn handle_query(&mut self, packet : &str) -> String {
+            if packet.starts_with("qSupported") {
+                "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+;ReverseStep+;ReverseContinue+".to_string()
+            }
+            else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+                let (offset, length) = range.split_once(',').unwrap_or(("0", "0"));
+                let offset = usize::from_str_radix(offset, 16).unwrap_or(0).min(TARGET_XML.len());
+                let length = usize::from_str_radix(length, 16).unwrap_or(0);
+                let end = (offset + length).min(TARGET_XML.len());
+                let prefix = if end == TARGET_XML.len() { "l" } else { "m" };
+                prefix.to_string() + &TARGET_XML[offset..end]
+            }
+            else if packet == "qAttached" { "1".to_string() }
+            else if packet == "qC" { "QC1".to_string() }
+            else if packet == "qfThreadInfo" { "m1".to_string() }
+            else if packet == "qsThreadInfo" { "l".to_string() }
+            else { "".to_string() }
+        }
+
+        fn run(&mut self, clock : &mut Clock) -> String {
+            // Continue until a breakpoint, the program halting, or Ctrl-C
+            let mut instructions : u64 = 0;
+            loop {
+                if clock.ctrl.halt { return Self::stop_reply(clock, SIGTRAP); }
+                if !self.step(clock) { return Self::stop_reply(clock, SIGINT); }
+                instructions += 1;
+                let pc = Converter::bin_to_dec_pos_only(clock.ctrl.pc.get_data().to_vec());
+                if self.breakpoints.contains(&pc) { return Self::stop_reply(clock, SIGTRAP); }
+                if instructions.is_multiple_of(1000) && self.interrupt_requested() { return Self::stop_reply(clock, SIGINT); }
+            }
+        }
+
+        fn step(&mut self, clock : &mut Clock) -> bool {
+            // Runs one instruction, checking for Ctrl-C every 1000 cycles - returns false if interrupted
+            // An IN waiting on an empty console stays in Execute, so the instruction may never finish on its own
+            while !clock.step_cycles(1000) {
+                if self.interrupt_requested() { return false; }
+            }
+            true
+        }
+
+        fn run_backwards(&mut self, clock : &mut Clock) -> String {
+            // Reverse continue, until a breakpoint or the start of the history
+            let mut instructions : u64 = 0;
+            loop {
+                if !history::step_back_instruction(clock) { return REPLAY_BEGIN.to_string(); }
+                instructions += 1;
+                let pc = Converter::bin_to_dec_pos_only(clock.ctrl.pc.get_data().to_vec());
+                if self.breakpoints.contains(&pc) { return Self::stop_reply(clock, SIGTRAP); }
+                if instructions.is_multiple_of(1000) && self.interrupt_requested() { return Self::stop_reply(clock, SIGINT); }
+            }
+        }
+
+        fn stop_reply(clock : &Clock, signal : u8) -> String {
+            if clock.ctrl.halt { "W00".to_string() } // Exited
+            else { format!("S{:02x}", signal) }
+        }
+
+        fn read_register(clock : &mut Clock, reg : usize) -> u64 {
+            match reg {
+                0..=14 => {
+                    let reg_index : [bool; 4] = Converter::dec_to_bin_pos_only(reg as u64, 4).try_into().unwrap();
+                    Converter::bin_to_dec_pos_only(clock.ctrl.register_bank.get_data(reg_index).to_vec())
+                }
+                15 => Converter::bin_to_dec_pos_only(clock.ctrl.pc.get_data().to_vec()) / Self::address_scale(clock),
+                _ => {
+                    let mut flags = 0;
+                    if clock.ctrl.alu.z { flags |= 1; }
+                    if clock.ctrl.alu.n { flags |= 2; }
+                    if clock.ctrl.alu.v { flags |= 4; }
+                    if clock.ctrl.alu.c { flags |= 8; }
+                    if clock.ctrl.privilege == PrivilegeLevel::User { flags |= 0x100; }
+                    flags
+                }
+            }
+        }
+
+        fn write_register(clock : &mut Clock, reg : usize, value : u64){
+            match reg {
+                0..=14 => {
+                    let reg_index : [bool; 4] = Converter::dec_to_bin_pos_only(reg as u64, 4).try_into().unwrap();
+                    clock.ctrl.register_bank.set_data(reg_index, Converter::dec_to_bin_pos_only(value, 64).try_into().unwrap());
+                }
+                15 => clock.ctrl.pc.set_data(Converter::dec_to_bin_pos_only(value * Self::address_scale(clock), 64).try_into().unwrap()),
+                _ => {
+                    clock.ctrl.alu.z = value & 1 != 0;
+                    clock.ctrl.alu.n = value & 2 != 0;
+                    clock.ctrl.alu.v = value & 4 != 0;
+                    clock.ctrl.alu.c = value & 8 != 0;
+                }
+            }
+        }
+
+        fn address_scale(clock : &Clock) -> u64 {
+            // Emulator address units per gdb byte
+            clock.ctrl.data_access_manager.main_memory.addressing.word_size() / 8
+        }
+
+        fn word_addr(clock : &Clock, byte_addr : u64) -> [bool; 48] {
+            Converter::dec_to_bin_pos_only(((byte_addr / 8) * 8).wrapping_mul(Self::address_scale(clock)), 48).try_into().unwrap()
+        }
+
+        fn read_byte_at(clock : &mut Clock, byte_addr : u64) -> u8 {
+            // Read straight from main memory - caches are write-through, so memory is always up to date
+            let word = clock.ctrl.data_access_manager.main_memory.read(Self::word_addr(clock, byte_addr));
+            let shift = ((byte_addr % 8) * 8) as usize;
+            Converter::bin_to_dec_pos_only(word[shift..shift + 8].to_vec()) as u8
+        }
+
+        fn write_byte_at(clock : &mut Clock, byte_addr : u64, byte : u8){
+            let addr = Self::word_addr(clock, byte_addr);
+            let mut word = clock.ctrl.data_access_manager.main_memory.read(addr);
+            let shift = ((byte_addr % 8) * 8) as usize;
+            word[shift..shift + 8].copy_from_slice(&Converter::dec_to_bin_pos_only(byte as u64, 8));
+            clock.ctrl.data_access_manager.poke(addr, word);
+        }
+
+        fn hex_u64(value : u64) -> String {
+            format!("{:016x}", value.swap_bytes()) // Little-endian byte order
+        }
+
+        fn parse_le_u64(hex : &str) -> Option<u64> {
+            if hex.len() != 16 { return None; }
+            u64::from_str_radix(hex, 16).ok().map(|value| value.swap_bytes())
+        }
+    }
+}
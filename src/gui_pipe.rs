@@ -0,0 +1,147 @@
+pub(crate) mod gui_pipe{
+    /*
+     * Link to an external front end (the GUI), over a Unix domain socket
+     *
+     * Every message is a single line of ASCII, terminated by '\n', with fields separated by '//'
+     * Addresses are 12 hex digits, memory data 16 hex digits, and register values signed decimal
+     *
+     * EMULATOR -> FRONT END (only sent when the value has changed since it was last sent)
+     *   REG//<n>//<value>           General purpose register n (0-14)
+     *   STATE//<n>                  CPU state - 0 Fetch, 1 Decode, 2 Execute, 3 Stall, 4 MemoryComp
+     *   INSTR//<pc>//<instr>        PC, and the instruction register contents
+     *   L1_UTIL//<used>/<size>      Cache lines in use
+     *   L2_UTIL//<used>/<size>
+     *   CYCLE//<n>                  Cycles run so far
+     *   PAUSED//<0|1>
+     *   SPEED//<hz>                 Measured clock speed over the last second or so of running
+     *   HALT                        The program has finished
+     *
+     * EMULATOR -> FRONT END (always sent)
+     *   RAM//<addr>//<data>         Reply to GET, and sent on every store
+     *
+     * FRONT END -> EMULATOR
+     *   INC_CLK / DEC_CLK           Change the clock speed by 1Hz (doubling or halving below 1Hz, down to 1e-6Hz), and run in real time
+     *   SET_CLK//<hz>               Set the clock speed, and run in real time - may be fractional, e.g. 0.5
+     *   SET_CLK//MAX                Run as fast as possible
+     *   GET//<addr>                 Request a RAM message for the address
+     *   SET//<addr>//<data>         Write data directly into main memory
+     *   PAUSE / RESUME              Stop and restart the clock
+     *   STEP                        Run a single cycle while paused
+     */
+    use std::collections::HashMap;
+    use std::io::{ErrorKind, Read, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::sync::Mutex;
+
+    struct GuiLink {
+        stream : UnixStream,
+        read_buffer : String,
+        last_sent : HashMap<String, String> // Last message sent for each key, to only send changes
+    }
+
+    static LINK : Mutex<Option<GuiLink>> = Mutex::new(None);
+
+    pub fn connect(path : &str){
+        // Waits for the front end to connect before returning
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path).expect("GUI Socket Error");
+        println!(" ----- WAITING FOR GUI ON {0} -----", path);
+        let (stream, _) = listener.accept().expect("GUI Socket Error");
+        stream.set_nonblocking(true).expect("GUI Socket Error");
+        *LINK.lock().unwrap() = Some(GuiLink { stream, read_buffer: String::new(), last_sent: HashMap::new() });
+        println!(" ----- GUI CONNECTED -----");
+    }
+
+    pub fn connected() -> bool {
+        LINK.lock().unwrap().is_some()
+    }
+
+    fn write_line(link : &mut GuiLink, message : &str) -> bool {
+        // The stream is non-blocking for reads, so briefly switch back to blocking for each write
+        let _ = link.stream.set_nonblocking(false);
+        let result = link.stream.write_all((message.to_string() + "\n").as_bytes());
+        let _ = link.stream.set_nonblocking(true);
+        result.is_ok()
+    }
+
+    fn disconnect(guard : &mut Option<GuiLink>){
+        // The emulator carries on without a front end if the GUI goes away
+        if guard.take().is_some() {
+            println!(" ----- GUI DISCONNECTED -----");
+        }
+    }
+
+    pub fn send(message : String){
+        let mut guard = LINK.lock().unwrap();
+        if let Some(link) = guard.as_mut() && !write_line(link, &message) {
+            disconnect(&mut guard);
+        }
+    }
+
+    fn change_key(message : &str) -> String {
+        // The message type - e.g. 'INSTR' for 'INSTR//<pc>//<instr>' - plus the index for per-register and per-address
+        // messages, e.g. 'REG//3' for 'REG//3//42'
+        let mut fields = message.split("//");
+        let kind = fields.next().unwrap_or("");
+        match (kind, fields.next()) {
+            ("REG" | "RAM", Some(index)) => format!("{0}//{1}", kind, index),
+            _ => kind.to_string()
+        }
+    }
+
+    pub fn send_on_change(message : String){
+        let mut guard = LINK.lock().unwrap();
+        if let Some(link) = guard.as_mut() {
+            let key = change_key(&message);
+            if link.last_sent.get(&key) == Some(&message) { return; }
+            if !write_line(link, &message) { disconnect(&mut guard); return; }
+            link.last_sent.insert(key, message);
+        }
+    }
+
+    pub fn receive() -> String {
+        // Returns the next complete line from the front end, or an empty string if there is none yet
+        let mut guard = LINK.lock().unwrap();
+        let link = match guard.as_mut() {
+            Some(link) => link,
+            None => return "".to_string()
+        };
+        let mut bytes = [0u8; 256];
+        let mut closed = false;
+        loop {
+            match link.stream.read(&mut bytes) {
+                Ok(0) => { closed = true; break; }
+                Ok(count) => link.read_buffer.push_str(&String::from_utf8_lossy(&bytes[0..count])),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => { closed = true; break; }
+            }
+        }
+        if closed && !link.read_buffer.contains('\n') {
+            disconnect(&mut guard);
+            return "".to_string();
+        }
+        match link.read_buffer.find('\n') {
+            Some(index) => {
+                let line = link.read_buffer[0..index].trim().to_string();
+                link.read_buffer.drain(0..=index);
+                line
+            }
+            None => "".to_string()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn change_keys() {
+            // A PC revisited by a loop must still replace the last INSTR sent
+            assert_eq!(change_key("INSTR//000000000040//0000000000000011"), "INSTR");
+            assert_eq!(change_key("STATE//2"), "STATE");
+            assert_eq!(change_key("HALT"), "HALT");
+            assert_eq!(change_key("REG//3//42"), "REG//3");
+            assert_eq!(change_key("RAM//000000000040//0000000000000001"), "RAM//000000000040");
+        }
+    }
+}
@@ -0,0 +1,445 @@
+pub(crate) mod history{
+    /*
+     * Execution history, so the machine can be run backwards
+     *
+     * Every cycle adds an undo record holding the CPU state from before the cycle, plus whatever the cycle changed
+     * elsewhere - the old value of any RAM word changed (by a store, DRAM decay, ECC scrubbing or an injected fault), and the
+     * cache contents if they were updated
+     * Full checkpoints are also kept at intervals, to go back further than the undo log reaches
+     *
+     * Old undo records are dropped once the log is over its memory budget. Checkpoints are thinned out -
+     * every other one dropped, and the interval doubled - once there are more than MAX_CHECKPOINTS
+     *
+     * Not undone - output already written by OUT, input already read, and changes made from a debugger
+     */
+    use std::collections::VecDeque;
+    use crate::assembler::assembler::ParsedInstruction;
+    use crate::clock::clock::Clock;
+    use crate::checkpoint::checkpoint;
+    use crate::control_unit::control_unit::{ControlUnit, CpuState};
+    use crate::converter::converter::Converter;
+    use crate::dram_controller::dram_controller::DramState;
+    use crate::output_sink::output_sink::BufferSink;
+    use crate::system_regs::system_regs::{PrivilegeLevel, SystemRegister};
+
+    const MAX_CHECKPOINTS : usize = 32;
+    const SYSTEM_REGISTERS : [SystemRegister; 7] = [SystemRegister::ELR, SystemRegister::VEC, SystemRegister::CAUSE, SystemRegister::TVAL,
+        SystemRegister::STATUS, SystemRegister::UBASE, SystemRegister::ULIMIT];
+
+    fn to_u64(bits : &[bool]) -> u64 { Converter::bin_to_dec_pos_only(bits.to_vec()) }
+    fn to_word(value : u64) -> [bool; 64] { Converter::dec_to_bin_pos_only(value, 64).try_into().unwrap() }
+    fn to_addr(value : u64) -> [bool; 48] { Converter::dec_to_bin_pos_only(value, 48).try_into().unwrap() }
+
+    struct CoreState {
+        // Everything in the CPU, buses and devices - small enough to save every cycle
+        halt : bool,
+        state : CpuState,
+        pc : u64,
+        memory_instr_reg : u64,
+        memory_data_reg : u64,
+        stalls : (bool, bool),
+        decoded_instruction : ParsedInstruction,
+        flags : (bool, bool, bool, bool),
+        privilege : PrivilegeLevel,
+        system_regs : [u64; 7],
+        registers : [u64; 15],
+        float_registers : [u64; 16],
+        float_status : u64,
+        vector_registers : [u64; 16],
+        address_bus : u64,
+        data_bus : u64,
+        control_bus : [bool; 5],
+        control_sync : (u8, u8), // Synchronizer edges left - memory, CPU
+        dram : DramState,
+        devices : Vec<Vec<u64>>
+    }
+
+    impl CoreState {
+        fn capture(ctrl : &mut ControlUnit) -> CoreState {
+            let mut registers = [0; 15];
+            for (reg, value) in registers.iter_mut().enumerate() {
+                *value = to_u64(&ctrl.register_bank.get_data(Converter::dec_to_bin_pos_only(reg as u64, 4).try_into().unwrap()));
+            }
+            let mut float_registers = [0; 16];
+            for (reg, value) in float_registers.iter_mut().enumerate() {
+                *value = to_u64(&ctrl.fpu.get_data(Converter::dec_to_bin_pos_only(reg as u64, 4).try_into().unwrap()));
+            }
+            let mut vector_registers = [0; 16];
+            for (reg, value) in vector_registers.iter_mut().enumerate() {
+                *value = to_u64(&ctrl.simd.get_data(Converter::dec_to_bin_pos_only(reg as u64, 4).try_into().unwrap()));
+            }
+            let memory = &ctrl.data_access_manager.main_memory;
+            CoreState {
+                halt: ctrl.halt,
+                state: ctrl.state.clone(),
+                pc: to_u64(&ctrl.pc.get_data()),
+                memory_instr_reg: to_u64(&ctrl.memory_instr_reg.get_data()),
+                memory_data_reg: to_u64(&ctrl.memory_data_reg.get_data()),
+                stalls: (ctrl.memory_instr_stall, ctrl.memory_data_stall),
+                decoded_instruction: ctrl.decoded_instruction.clone(),
+                flags: (ctrl.alu.z, ctrl.alu.n, ctrl.alu.v, ctrl.alu.c),
+                privilege: ctrl.privilege,
+                system_regs: SYSTEM_REGISTERS.map(|reg| to_u64(&ctrl.system_regs.get_data(reg))),
+                registers,
+                float_registers,
+                float_status: to_u64(&ctrl.fpu.get_status()),
+                vector_registers,
+                address_bus: to_u64(&memory.address_bus.bits),
+                data_bus: to_u64(&memory.data_bus.bits),
+                control_bus: [memory.control_bus.ready_memory, memory.control_bus.ready_cpu, memory.control_bus.str, memory.control_bus.lock, memory.control_bus.error],
+                control_sync: (memory.control_bus.memory_sync, memory.control_bus.cpu_sync),
+                dram: memory.dram.state.clone(),
+                devices: memory.address_decoder.save_state()
+            }
+        }
+
+        fn apply(self, ctrl : &mut ControlUnit){
+            ctrl.halt = self.halt;
+            ctrl.state = self.state;
+            ctrl.pc.set_data(to_word(self.pc));
+            ctrl.memory_instr_reg.set_data(to_word(self.memory_instr_reg));
+            ctrl.memory_data_reg.set_data(to_word(self.memory_data_reg));
+            (ctrl.memory_instr_stall, ctrl.memory_data_stall) = self.stalls;
+            ctrl.decoded_instruction = self.decoded_instruction;
+            (ctrl.alu.z, ctrl.alu.n, ctrl.alu.v, ctrl.alu.c) = self.flags;
+            ctrl.privilege = self.privilege;
+            for (reg, value) in SYSTEM_REGISTERS.iter().zip(self.system_regs) {
+                ctrl.system_regs.set_data(*reg, to_word(value));
+            }
+            for (reg, value) in self.registers.iter().enumerate() {
+                ctrl.register_bank.set_data(Converter::dec_to_bin_pos_only(reg as u64, 4).try_into().unwrap(), to_word(*value));
+            }
+            for (reg, value) in self.float_registers.iter().enumerate() {
+                ctrl.fpu.set_data(Converter::dec_to_bin_pos_only(reg as u64, 4).try_into().unwrap(), to_word(*value));
+            }
+            ctrl.fpu.set_status(to_word(self.float_status));
+            for (reg, value) in self.vector_registers.iter().enumerate() {
+                ctrl.simd.set_data(Converter::dec_to_bin_pos_only(reg as u64, 4).try_into().unwrap(), to_word(*value));
+            }
+            let memory = &mut ctrl.data_access_manager.main_memory;
+            memory.address_bus.bits = to_addr(self.address_bus);
+            memory.data_bus.bits = to_word(self.data_bus);
+            [memory.control_bus.ready_memory, memory.control_bus.ready_cpu, memory.control_bus.str, memory.control_bus.lock, memory.control_bus.error] = self.control_bus;
+            (memory.control_bus.memory_sync, memory.control_bus.cpu_sync) = self.control_sync;
+            memory.dram.state = self.dram;
+            memory.address_decoder.restore_state(self.devices);
+        }
+
+        fn size(&self) -> usize {
+            size_of::<CoreState>() + self.devices.iter().map(|device| device.len() * 8 + 24).sum::<usize>() + self.dram.last_used.len() * 16
+        }
+    }
+
+    type CacheContents = (Vec<(u64, u64)>, Vec<(u64, u64)>); // L1 and L2, least recently used first
+
+    fn capture_caches(ctrl : &ControlUnit) -> CacheContents {
+        let convert = |contents : Vec<([bool; 48], [bool; 64])>| contents.iter().map(|(key, value)| (to_u64(key), to_u64(value))).collect();
+        (convert(ctrl.data_access_manager.l1_cache.contents()), convert(ctrl.data_access_manager.l2_cache.contents()))
+    }
+
+    fn apply_caches(ctrl : &mut ControlUnit, caches : CacheContents){
+        let convert = |contents : Vec<(u64, u64)>| contents.iter().map(|(key, value)| (to_addr(*key), to_word(*value))).collect();
+        ctrl.data_access_manager.l1_cache.restore(convert(caches.0));
+        ctrl.data_access_manager.l2_cache.restore(convert(caches.1));
+    }
+
+    struct UndoRecord {
+        cycle : u64, // Cycle count before the cycle ran
+        core : CoreState,
+        caches : Option<CacheContents>, // Only if the cycle changed them
+        memory : Vec<(u64, Option<u64>, Option<u8>)> // Addresses changed, and their old values and ECC check bits (None if never written)
+    }
+
+    impl UndoRecord {
+        fn size(&self) -> usize {
+            let caches = self.caches.as_ref().map(|(l1, l2)| (l1.len() + l2.len()) * 16).unwrap_or(0);
+            size_of::<UndoRecord>() + self.core.size() + caches + self.memory.len() * 32
+        }
+    }
+
+    pub(crate) struct History {
+        records : VecDeque<UndoRecord>,
+        records_size : usize,
+        budget : usize, // Bytes
+        pending : Option<UndoRecord>, // The cycle in progress
+        caches_before : Option<CacheContents>,
+        checkpoints : Vec<(u64, String)>, // (cycle, checkpoint), oldest first
+        checkpoint_interval : u64
+    }
+
+    impl History {
+
+        pub fn new(budget_mb : usize) -> History {
+            History {
+                records: VecDeque::new(), records_size: 0, budget: budget_mb * 1024 * 1024,
+                pending: None, caches_before: None, checkpoints: vec![], checkpoint_interval: 1000
+            }
+        }
+
+        pub fn clear(&mut self){
+            // Called when the machine state is replaced - the history no longer leads to it
+            self.records.clear();
+            self.records_size = 0;
+            self.pending = None;
+            self.caches_before = None;
+            self.checkpoints.clear();
+        }
+
+        pub fn begin(&mut self, ctrl : &mut ControlUnit, cycle : u64){
+            // Called before each cycle
+            let caches = self.caches_before.take().unwrap_or_else(|| capture_caches(ctrl));
+            self.pending = Some(UndoRecord { cycle, core: CoreState::capture(ctrl), caches: Some(caches), memory: vec![] });
+        }
+
+        pub fn before_memory(&mut self, ctrl : &mut ControlUnit){
+            // Called after the CPU's half of the cycle, before main memory acts on the buses
+            let memory = &mut ctrl.data_access_manager.main_memory;
+            if memory.control_bus.ready_memory && memory.control_bus.str && !memory.address_decoder.claims(memory.address_bus.bits) {
+                let addr = memory.address_bus.bits;
+                let old_value = memory.ram_map.contains_key(&addr).then(|| to_u64(&memory.read(addr)));
+                let old_check = memory.check_bits.get(&addr).cloned();
+                if let Some(record) = self.pending.as_mut() { record.memory.push((to_u64(&addr), old_value, old_check)); }
+            }
+        }
+
+        pub fn end(&mut self, ctrl : &mut ControlUnit){
+            // Called after each cycle
+            let mut record = match self.pending.take() {
+                Some(record) => record,
+                None => return
+            };
+            record.memory.extend(ctrl.data_access_manager.main_memory.changed_words.iter().cloned());
+            let caches_after = capture_caches(ctrl);
+            if record.caches.as_ref() == Some(&caches_after) { record.caches = None; }
+            self.caches_before = Some(caches_after);
+            self.records_size += record.size();
+            self.records.push_back(record);
+            while self.records_size > self.budget && let Some(oldest) = self.records.pop_front() {
+                self.records_size -= oldest.size();
+            }
+        }
+
+        pub fn note_memory(&mut self, addr : u64, old_value : Option<u64>, old_check : Option<u8>){
+            // A RAM word changed outside of the memory's half of the cycle
+            if let Some(record) = self.pending.as_mut() { record.memory.push((addr, old_value, old_check)); }
+        }
+
+        pub fn wants_checkpoint(&self, cycle : u64) -> bool {
+            cycle.is_multiple_of(self.checkpoint_interval) && self.checkpoints.last().is_none_or(|(last, _)| *last < cycle)
+        }
+
+        pub fn add_checkpoint(&mut self, cycle : u64, text : String){
+            self.checkpoints.push((cycle, text));
+            if self.checkpoints.len() > MAX_CHECKPOINTS {
+                self.checkpoint_interval *= 2;
+                let interval = self.checkpoint_interval;
+                self.checkpoints.retain(|(cycle, _)| cycle.is_multiple_of(interval));
+            }
+        }
+
+        pub fn earliest_cycle(&self) -> Option<u64> {
+            // The earliest cycle the machine can be taken back to
+            let from_log = self.records.front().map(|record| record.cycle);
+            let from_checkpoint = self.checkpoints.first().map(|(cycle, _)| *cycle);
+            match (from_log, from_checkpoint) {
+                (Some(log), Some(checkpoint)) => Some(log.min(checkpoint)),
+                (log, checkpoint) => log.or(checkpoint)
+            }
+        }
+
+        pub fn summary(&self) -> String {
+            format!("{0} cycles in the undo log ({1} KB of {2} KB), {3} checkpoints every {4} cycles",
+                self.records.len(), self.records_size / 1024, self.budget / 1024, self.checkpoints.len(), self.checkpoint_interval)
+        }
+    }
+
+    pub fn step_back(clock : &mut Clock) -> bool {
+        // Undoes a single cycle - returns false if there is no history to go back to
+        let history = match clock.history.as_mut() {
+            Some(history) => history,
+            None => return false
+        };
+        let record = match history.records.pop_back() {
+            Some(record) => record,
+            None => {
+                let target = clock.cycle_count.saturating_sub(1);
+                return clock.cycle_count > 0 && goto_cycle(clock, target);
+            }
+        };
+        history.records_size -= record.size();
+        history.caches_before = None;
+        history.checkpoints.retain(|(cycle, _)| *cycle <= record.cycle);
+        record.core.apply(&mut clock.ctrl);
+        if let Some(caches) = record.caches { apply_caches(&mut clock.ctrl, caches); }
+        for (addr, old_value, old_check) in record.memory.into_iter().rev() {
+            let memory = &mut clock.ctrl.data_access_manager.main_memory;
+            match old_value {
+                Some(value) => memory.write(to_addr(addr), to_word(value)),
+                None => { memory.ram_map.remove(&to_addr(addr)); }
+            }
+            match old_check {
+                Some(check) => { memory.check_bits.insert(to_addr(addr), check); }
+                None => { memory.check_bits.remove(&to_addr(addr)); }
+            }
+        }
+        clock.cycle_count = record.cycle;
+        clock.running = !clock.ctrl.halt;
+        true
+    }
+
+    pub fn step_back_instruction(clock : &mut Clock) -> bool {
+        // Undoes cycles until the start of the previous instruction - the CPU ready to fetch it
+        if !step_back(clock) { return false; }
+        while !matches!(clock.ctrl.state, CpuState::Fetch) {
+            if !step_back(clock) { return false; }
+        }
+        true
+    }
+
+    pub fn goto_cycle(clock : &mut Clock, target : u64) -> bool {
+        // Takes the machine to any cycle between the earliest in the history and the end of the program
+        // Returns false if the cycle is out of reach - the machine is left as close to it as possible
+        while clock.cycle_count > target {
+            let log_reaches = clock.history.as_ref().and_then(|history| history.records.front()).is_some_and(|record| record.cycle <= target);
+            if log_reaches {
+                step_back(clock);
+                continue;
+            }
+            // Restart from the last checkpoint before the target, and run forward to it
+            let history = match clock.history.as_mut() {
+                Some(history) => history,
+                None => return false
+            };
+            let checkpoint_text = match history.checkpoints.iter().rev().find(|(cycle, _)| *cycle <= target) {
+                Some((_, text)) => text.clone(),
+                None => return false
+            };
+            let checkpoints = std::mem::take(&mut history.checkpoints);
+            history.clear();
+            history.checkpoints = checkpoints;
+            checkpoint::apply(clock, &checkpoint_text).expect("Checkpoint Error");
+            if let Some(history) = clock.history.as_mut() { history.checkpoints.retain(|(cycle, _)| *cycle <= target); }
+            run_quietly(clock, target);
+            return clock.cycle_count == target;
+        }
+        while clock.cycle_count < target && !clock.ctrl.halt {
+            clock.refresh();
+        }
+        clock.cycle_count == target
+    }
+
+    fn run_quietly(clock : &mut Clock, target : u64){
+        // Re-runs cycles which have already been run once, without repeating their output or trace
+        let output = std::mem::replace(&mut clock.ctrl.output, Box::new(BufferSink::default()));
+        let tracer = clock.tracer.take();
+        while clock.cycle_count < target && !clock.ctrl.halt {
+            clock.refresh();
+        }
+        clock.ctrl.output = output;
+        clock.tracer = tracer;
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::RunOptions;
+        use crate::tests::{load_program, program_file};
+
+        // Sums 300 down to 1, storing each partial sum - a few thousand cycles, with a store in every loop
+        const PROGRAM : [&str; 6] = ["ADD R2, #0, #300", "loop: ADD R1, R1, R2", "STR R1, &000000000800", "SUB R2, R2, #1", "BNE &loop", "HLT"];
+
+        fn machine(name : &str, history : Option<History>) -> Clock {
+            let path = program_file(name, &PROGRAM);
+            let (mut clk, _) = load_program(&path, RunOptions::default());
+            std::fs::remove_file(&path).ok();
+            clk.history = history;
+            clk
+        }
+
+        fn state_at(name : &str, cycle : u64) -> String {
+            // The machine's state after running forwards to the cycle, with no history
+            let mut clk = machine(name, None);
+            while clk.cycle_count < cycle { clk.refresh(); }
+            checkpoint::capture(&mut clk)
+        }
+
+        #[test]
+        fn step_back_restores_state() {
+            let mut clk = machine("history_step", Some(History::new(64)));
+            let mut states = vec![];
+            for _ in 0..200 {
+                states.push(checkpoint::capture(&mut clk));
+                clk.refresh();
+            }
+            for state in states.iter().rev() {
+                assert!(step_back(&mut clk));
+                assert_eq!(&checkpoint::capture(&mut clk), state);
+            }
+            assert_eq!(clk.cycle_count, 0);
+            assert!(!step_back(&mut clk));
+        }
+
+        #[test]
+        fn step_back_instruction_restores_state() {
+            let mut clk = machine("history_instruction", Some(History::new(64)));
+            let mut states = vec![];
+            while !clk.ctrl.halt {
+                states.push((clk.cycle_count, checkpoint::capture(&mut clk)));
+                clk.step_instruction();
+            }
+            let end = clk.cycle_count;
+            assert!(end > 3000, "{}", end);
+            for (cycle, state) in states.iter().rev() {
+                assert!(step_back_instruction(&mut clk));
+                assert_eq!(clk.cycle_count, *cycle);
+                assert_eq!(&checkpoint::capture(&mut clk), state);
+            }
+            assert!(!step_back_instruction(&mut clk));
+            // And forwards again, to the same end
+            while !clk.ctrl.halt { clk.step_instruction(); }
+            assert_eq!(clk.cycle_count, end);
+            assert_eq!(checkpoint::capture(&mut clk), state_at("history_instruction_end", end));
+        }
+
+        #[test]
+        fn goto_cycle_before_undo_log() {
+            let mut clk = machine("history_goto", Some(History::new(64)));
+            clk.history.as_mut().unwrap().budget = 64 * 1024; // Only the last few hundred cycles
+            clk.start();
+            let history = clk.history.as_ref().unwrap();
+            let log_start = history.records.front().unwrap().cycle;
+            assert!(log_start > 2000, "{}", history.summary());
+            assert!(history.records_size <= history.budget);
+            assert_eq!(history.records_size, history.records.iter().map(|record| record.size()).sum::<usize>());
+
+            // Before the log - restarts from the checkpoint at cycle 1000
+            assert!(goto_cycle(&mut clk, 1500));
+            assert_eq!(checkpoint::capture(&mut clk), state_at("history_goto_1500", 1500));
+            // Back again, through the undo log rebuilt on the way forwards
+            assert!(goto_cycle(&mut clk, 1200));
+            assert_eq!(checkpoint::capture(&mut clk), state_at("history_goto_1200", 1200));
+            assert!(goto_cycle(&mut clk, 2500));
+            assert_eq!(checkpoint::capture(&mut clk), state_at("history_goto_2500", 2500));
+        }
+
+        #[test]
+        fn eviction_keeps_earliest_cycle() {
+            let mut clk = machine("history_evict", Some(History::new(64)));
+            clk.history.as_mut().unwrap().budget = 64 * 1024;
+            for _ in 0..2500 { clk.refresh(); }
+            let history = clk.history.as_mut().unwrap();
+            assert_eq!(history.earliest_cycle(), Some(0)); // The checkpoint at cycle 0
+            history.checkpoints.clear();
+            let earliest = history.earliest_cycle().unwrap();
+            assert_eq!(earliest, history.records.front().unwrap().cycle);
+            assert!(earliest > 0 && earliest < 2500);
+
+            let state = state_at("history_evict_earliest", earliest);
+            assert!(!goto_cycle(&mut clk, earliest - 1)); // Out of reach
+            assert_eq!(clk.cycle_count, 2500);
+            assert!(goto_cycle(&mut clk, earliest));
+            assert_eq!(checkpoint::capture(&mut clk), state);
+            assert_eq!(clk.history.as_ref().unwrap().earliest_cycle(), None); // Nothing left to undo
+        }
+    }
+}
@@ -0,0 +1,221 @@
+pub(crate) mod image{
+    /*
+     * Executable images - assembled programs, written by 'cpu_emu assemble' and loaded with --image
+     *
+     * All values are little-endian
+     *
+     * HEADER
+     *   4   Magic, "CEMU"
+     *   2   Format version
+     *   1   Addressing - 0 word (bit addresses), 1 byte
+     *   1   Reserved, 0
+     *   8   Entry point - the address execution starts at
+     *   4   Number of sections
+     *   4   Number of symbols
+     *
+     * SECTION (repeated)
+     *   1   Kind - 0 .text, 1 .data, 2 .bss
+     *   8   Load address
+     *   8   Size, in 64-bit words
+     *   8n  The words themselves - none for .bss, which is loaded as zeros (up to 2^20 words)
+     *
+     * SYMBOL (repeated)
+     *   2   Name length
+     *   n   Name (UTF-8)
+     *   8   Address
+     *
+     * Sections are loaded in order, so a later section overwrites an earlier one where they overlap
+     */
+    use std::collections::HashMap;
+    use crate::converter::converter::Converter;
+    use crate::main_memory::main_memory::{Addressing, MainMemory};
+
+    const MAGIC : &[u8; 4] = b"CEMU";
+    const VERSION : u16 = 1;
+    const MAX_BSS_WORDS : u64 = 1 << 20; // .bss is loaded a word at a time, and costs nothing in the file
+
+    #[repr(u8)]
+    #[derive(Clone, Copy, PartialEq)]
+    pub(crate) enum SectionKind {
+        Text = 0,
+        Data = 1,
+        Bss = 2
+    }
+
+    impl SectionKind {
+        pub fn from_code(code : u8) -> Result<SectionKind, String> {
+            match code {
+                0 => Ok(SectionKind::Text),
+                1 => Ok(SectionKind::Data),
+                2 => Ok(SectionKind::Bss),
+                other => Err(format!("Unknown section kind {0}", other))
+            }
+        }
+
+        pub fn name(&self) -> &'static str {
+            match self {
+                SectionKind::Text => ".text",
+                SectionKind::Data => ".data",
+                SectionKind::Bss => ".bss"
+            }
+        }
+    }
+
+    pub(crate) struct Section {
+        pub(crate) kind : SectionKind,
+        pub(crate) addr : u64,
+        pub(crate) size : u64, // Words
+        pub(crate) words : Vec<u64> // Empty for .bss
+    }
+
+    pub(crate) struct Image {
+        pub(crate) addressing : Addressing,
+        pub(crate) entry : u64,
+        pub(crate) sections : Vec<Section>,
+        pub(crate) symbols : Vec<(String, u64)>
+    }
+
+    pub(crate) struct Reader<'a> {
+        pub(crate) bytes : &'a [u8],
+        pub(crate) position : usize
+    }
+
+    impl Reader<'_> {
+        pub fn take(&mut self, count : usize) -> Result<&[u8], String> {
+            if self.position + count > self.bytes.len() { return Err("Image is truncated".to_string()); }
+            self.position += count;
+            Ok(&self.bytes[self.position - count..self.position])
+        }
+
+        pub fn u8(&mut self) -> Result<u8, String> { Ok(self.take(1)?[0]) }
+        pub fn u16(&mut self) -> Result<u16, String> { Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap())) }
+        pub fn u32(&mut self) -> Result<u32, String> { Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap())) }
+        pub fn u64(&mut self) -> Result<u64, String> { Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap())) }
+    }
+
+    impl Image {
+
+        pub fn add_words(&mut self, kind : SectionKind, mut words : Vec<(u64, u64)>){
+            // Adds (address, word) pairs as sections - one for each run of consecutive words
+            // Only the size of a .bss section is kept, as its words are all zero
+            words.sort_by_key(|(addr, _)| *addr);
+            let word_size = self.addressing.word_size();
+            for (addr, word) in words {
+                let word = if kind == SectionKind::Bss { vec![] } else { vec![word] };
+                match self.sections.last_mut() {
+                    Some(section) if section.kind == kind && section.addr + section.size * word_size == addr => {
+                        section.size += 1;
+                        section.words.extend(word);
+                    }
+                    _ => self.sections.push(Section { kind, addr, size: 1, words: word })
+                }
+            }
+        }
+
+        pub fn to_bytes(&self) -> Vec<u8> {
+            let mut bytes = MAGIC.to_vec();
+            bytes.extend(VERSION.to_le_bytes());
+            bytes.extend([self.addressing as u8, 0]);
+            bytes.extend(self.entry.to_le_bytes());
+            bytes.extend((self.sections.len() as u32).to_le_bytes());
+            bytes.extend((self.symbols.len() as u32).to_le_bytes());
+            for section in &self.sections {
+                bytes.push(section.kind as u8);
+                bytes.extend(section.addr.to_le_bytes());
+                bytes.extend(section.size.to_le_bytes());
+                for word in &section.words { bytes.extend(word.to_le_bytes()); }
+            }
+            for (name, addr) in &self.symbols {
+                bytes.extend((name.len() as u16).to_le_bytes());
+                bytes.extend(name.as_bytes());
+                bytes.extend(addr.to_le_bytes());
+            }
+            bytes
+        }
+
+        pub fn from_bytes(bytes : &[u8]) -> Result<Image, String> {
+            let mut reader = Reader { bytes, position: 0 };
+            if reader.take(4)? != MAGIC { return Err("Not an executable image".to_string()); }
+            let version = reader.u16()?;
+            if version != VERSION { return Err(format!("Unsupported image version {0}", version)); }
+            let addressing = Addressing::from_code(reader.u8()?)?;
+            reader.u8()?;
+            let entry = reader.u64()?;
+            let section_count = reader.u32()?;
+            let symbol_count = reader.u32()?;
+
+            let mut sections = vec![];
+            for _ in 0..section_count {
+                let kind = SectionKind::from_code(reader.u8()?)?;
+                let addr = reader.u64()?;
+                let size = reader.u64()?;
+                let mut words = vec![];
+                if kind != SectionKind::Bss {
+                    for _ in 0..size { words.push(reader.u64()?); }
+                }
+                else if size > MAX_BSS_WORDS {
+                    return Err(format!(".bss section of {0} words is over the {1} word limit", size, MAX_BSS_WORDS));
+                }
+                sections.push(Section { kind, addr, size, words });
+            }
+            let mut symbols = vec![];
+            for _ in 0..symbol_count {
+                let length = reader.u16()? as usize;
+                let name = String::from_utf8(reader.take(length)?.to_vec()).map_err(|_| "Invalid symbol name".to_string())?;
+                symbols.push((name, reader.u64()?));
+            }
+            if reader.position != bytes.len() { return Err("Unexpected data after the symbol table".to_string()); }
+            Ok(Image { addressing, entry, sections, symbols })
+        }
+
+        pub fn write(&self, path : &str){
+            std::fs::write(path, self.to_bytes()).expect("Image File Error");
+        }
+
+        pub fn read(path : &str) -> Result<Image, String> {
+            let bytes = std::fs::read(path).map_err(|error| format!("{0}: {1}", path, error))?;
+            Image::from_bytes(&bytes)
+        }
+
+        pub fn load(&self, memory : &mut MainMemory){
+            // Sets the addressing mode, then writes every section into memory
+            memory.set_addressing(self.addressing);
+            let word_size = self.addressing.word_size();
+            for section in &self.sections {
+                for index in 0..section.size {
+                    let addr : [bool; 48] = Converter::dec_to_bin_pos_only(section.addr + index * word_size, 48).try_into().unwrap();
+                    let word = section.words.get(index as usize).cloned().unwrap_or(0);
+                    memory.write(addr, Converter::dec_to_bin_pos_only(word, 64).try_into().unwrap());
+                }
+            }
+        }
+
+        pub fn symbol_map(&self) -> HashMap<String, [bool; 48]> {
+            self.symbols.iter().map(|(name, addr)| (name.clone(), Converter::dec_to_bin_pos_only(*addr, 48).try_into().unwrap())).collect()
+        }
+
+        pub fn describe(&self) -> Vec<String> {
+            // Summary printed by the assembler
+            let mut lines = vec![format!("Entry point {0:012X}", self.entry)];
+            for section in &self.sections {
+                lines.push(format!("{0:<6} {1:012X}  {2} words", section.kind.name(), section.addr, section.size));
+            }
+            lines.push(format!("{0} symbols", self.symbols.len()));
+            lines
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn bss_size_capped() {
+            let mut image = Image { addressing: Addressing::Word, entry: 0, sections: vec![], symbols: vec![] };
+            image.sections.push(Section { kind: SectionKind::Bss, addr: 0, size: MAX_BSS_WORDS, words: vec![] });
+            assert!(Image::from_bytes(&image.to_bytes()).is_ok());
+            image.sections[0].size = u64::MAX;
+            assert!(Image::from_bytes(&image.to_bytes()).is_err());
+        }
+    }
+}
@@ -0,0 +1,107 @@
+pub(crate) mod io_bus{
+    use crate::converter::converter::Converter;
+    use crate::main_memory::main_memory::Addressing;
+
+    pub(crate) trait Device {
+        // Anything which can sit on the address/data buses alongside main memory
+        // Offsets are relative to the base address the device is mapped at, and always in bits - one register every 0x40,
+        // whatever the addressing mode
+        fn read(&mut self, offset : [bool; 48]) -> [bool; 64];
+        fn write(&mut self, offset : [bool; 48], val : [bool; 64]);
+        fn tick(&mut self); // Called once per clock cycle
+        fn irq(&self) -> bool; // Interrupt request line
+        fn save_state(&self) -> Vec<u64> { vec![] } // Internal registers, for checkpoints
+        fn restore_state(&mut self, _state : Vec<u64>) {}
+    }
+
+    pub(crate) struct MappedDevice {
+        pub(crate) base : u64,
+        pub(crate) size : u64,
+        pub(crate) cacheable : bool,
+        pub(crate) device : Box<dyn Device>
+    }
+
+    pub(crate) struct AddressDecoder {
+        // Sits in front of the DRAM, routing bus transactions in mapped ranges to devices
+        pub(crate) devices : Vec<MappedDevice>,
+        pub(crate) addressing : Addressing
+    }
+    impl AddressDecoder {
+
+        pub fn map(&mut self, base : [bool; 48], size : u64, cacheable : bool, device : Box<dyn Device>){
+            // Map a device at [base, base + size) - later mappings take priority over earlier ones
+            let base_dec = Converter::bin_to_dec_pos_only(base.to_vec());
+            self.devices.insert(0, MappedDevice{ base: base_dec, size, cacheable, device });
+        }
+
+        fn find(&self, addr : [bool; 48]) -> Option<usize> {
+            let addr_dec = Converter::bin_to_dec_pos_only(addr.to_vec());
+            self.devices.iter().position(|mapped| mapped.base <= addr_dec && addr_dec < mapped.base + mapped.size)
+        }
+
+        fn offset(&self, index : usize, addr : [bool; 48]) -> [bool; 48] {
+            let offset = (Converter::bin_to_dec_pos_only(addr.to_vec()) - self.devices[index].base) * (64 / self.addressing.word_size());
+            Converter::dec_to_bin_pos_only(offset, 48).try_into().unwrap()
+        }
+
+        pub fn claims(&self, addr : [bool; 48]) -> bool {
+            // True if the address belongs to a device rather than main memory
+            self.find(addr).is_some()
+        }
+
+        pub fn is_cacheable(&self, addr : [bool; 48]) -> bool {
+            // Addresses not claimed by a device are plain DRAM, and always cacheable
+            match self.find(addr) {
+                Some(index) => self.devices[index].cacheable,
+                None => true
+            }
+        }
+
+        pub fn read(&mut self, addr : [bool; 48]) -> Option<[bool; 64]> {
+            // Returns None if the address belongs to main memory
+            let index = self.find(addr)?;
+            let offset = self.offset(index, addr);
+            Some(self.devices[index].device.read(offset))
+        }
+
+        pub fn write(&mut self, addr : [bool; 48], val : [bool; 64]) -> bool {
+            // Returns false if the address belongs to main memory
+            match self.find(addr) {
+                Some(index) => {
+                    let offset = self.offset(index, addr);
+                    self.devices[index].device.write(offset, val);
+                    true
+                }
+                None => false
+            }
+        }
+
+        pub fn tick(&mut self){
+            for mapped in self.devices.iter_mut(){
+                mapped.device.tick();
+            }
+        }
+
+        pub fn save_state(&self) -> Vec<Vec<u64>> {
+            // One entry per device, in mapping order
+            self.devices.iter().map(|mapped| mapped.device.save_state()).collect()
+        }
+
+        pub fn restore_state(&mut self, states : Vec<Vec<u64>>) {
+            for (mapped, state) in self.devices.iter_mut().zip(states) {
+                mapped.device.restore_state(state);
+            }
+        }
+
+        pub fn irq_pending(&self) -> Option<[bool; 48]> {
+            // Returns the base address of the first device requesting an interrupt
+            self.devices.iter()
+                .find(|mapped| mapped.device.irq())
+                .map(|mapped| Converter::dec_to_bin_pos_only(mapped.base, 48).try_into().unwrap())
+        }
+    }
+
+    impl Default for AddressDecoder {
+        fn default() -> Self { AddressDecoder { devices: vec![], addressing: Addressing::Word } }
+    }
+}
@@ -0,0 +1,424 @@
+pub(crate) mod linker{
+    /*
+     * Relocatable objects and the linker - 'cpu_emu assemble --object' writes an object, 'cpu_emu link' combines
+     * objects into an executable image (see image.rs) and a map file
+     *
+     * An object is assembled as if it started at address 0. Every label used as a B or LDR/STR address has a
+     * relocation record, as does each 16 bits of the address built by 'LI <register>, &label', and the linker rewrites
+     * that field once it knows where the label ended up
+     * Each .text, .data or .bss directive in the source starts a new section, which runs up to the next one
+     *
+     * All values are little-endian
+     *
+     * HEADER
+     *   4   Magic, "CEMO"
+     *   2   Format version
+     *   1   Addressing - 0 word (bit addresses), 1 byte
+     *   1   Reserved, 0
+     *   4   Number of sections
+     *   4   Number of symbols
+     *   4   Number of relocations
+     *
+     * SECTION (repeated)
+     *   1   Kind - 0 .text, 1 .data, 2 .bss
+     *   8   Size, in 64-bit words
+     *   8n  The words themselves - none for .bss
+     *
+     * SYMBOL (repeated)
+     *   2   Name length
+     *   n   Name (UTF-8)
+     *   1   Binding - 0 local, 1 global (.global), 2 imported (.extern)
+     *   4   Section index - unused for imported symbols
+     *   8   Offset from the start of the section
+     *
+     * RELOCATION (repeated)
+     *   4   Section index
+     *   8   Offset of the word from the start of the section
+     *   1   First bit of the 48-bit address field - 8 for LDR/STR, 9 for B, 16 for VLDR/VSTR - or 64 to 67 for the
+     *       16-bit immediate of a MOVZ/MOVK from LI, which takes bits 0-15, 16-31, 32-47 or 48-63 of the address
+     *   4   Symbol index - the field is set to the symbol's final address
+     *
+     * Offsets are in address units, so they depend on the addressing mode - every object linked together must use the same one
+     *
+     * The linker places every .text section, in the order the objects are given, starting at the base address, then every
+     * .data section, then every .bss section
+     */
+    use std::collections::HashMap;
+    use crate::assembler::assembler::{Assembler, HALFWORD_RELOCATION};
+    use crate::converter::converter::Converter;
+    use crate::image::image::{Image, Reader, Section, SectionKind};
+    use crate::main_memory::main_memory::Addressing;
+
+    const MAGIC : &[u8; 4] = b"CEMO";
+    const VERSION : u16 = 1;
+    const FIELD_MASK : u64 = (1 << 48) - 1;
+
+    #[repr(u8)]
+    #[derive(Clone, Copy, PartialEq)]
+    pub(crate) enum Binding {
+        Local = 0,
+        Global = 1,
+        Imported = 2
+    }
+
+    pub(crate) struct ObjectSection {
+        pub(crate) kind : SectionKind,
+        pub(crate) size : u64, // Words
+        pub(crate) words : Vec<u64> // Empty for .bss
+    }
+
+    pub(crate) struct ObjectSymbol {
+        pub(crate) name : String,
+        pub(crate) binding : Binding,
+        pub(crate) section : u32,
+        pub(crate) offset : u64
+    }
+
+    pub(crate) struct Relocation {
+        pub(crate) section : u32,
+        pub(crate) offset : u64,
+        pub(crate) field : u8,
+        pub(crate) symbol : u32
+    }
+
+    pub(crate) struct ObjectFile {
+        pub(crate) addressing : Addressing,
+        pub(crate) sections : Vec<ObjectSection>,
+        pub(crate) symbols : Vec<ObjectSymbol>,
+        pub(crate) relocations : Vec<Relocation>
+    }
+
+    impl ObjectFile {
+
+        pub fn from_program(addressing : Addressing, program : Vec<(u64, u64)>, assembler : &Assembler) -> Result<ObjectFile, String> {
+            // A section for each .text, .data or .bss directive (and one for any lines before the first), running up to the
+            // next one - the last ends at the last assembled word or label. Gaps are left as zeros
+            let word_size = addressing.word_size();
+            let labels = assembler.symbols.values().map(|addr| Converter::bin_to_dec_pos_only(addr.to_vec()));
+            let end = program.iter().map(|(addr, _)| *addr).chain(labels).map(|addr| (addr / word_size + 1) * word_size).max().unwrap_or(0);
+            let mut starts = vec![(0, SectionKind::Text)];
+            starts.extend(assembler.sections.iter().cloned());
+            let mut bounds = vec![]; // (start, end, kind) of each section with something in it
+            for (index, (start, kind)) in starts.iter().enumerate() {
+                let next = starts.get(index + 1).map(|(next, _)| *next).unwrap_or(end).max(*start);
+                if next > *start { bounds.push((*start, next, *kind)); }
+            }
+            let locate = |addr : u64| bounds.iter().position(|(start, end, _)| *start <= addr && addr < *end).map(|index| (index as u32, addr - bounds[index].0));
+
+            let mut sections : Vec<ObjectSection> = bounds.iter().map(|(start, end, kind)| {
+                let size = (end - start) / word_size;
+                ObjectSection { kind: *kind, size, words: if *kind == SectionKind::Bss { vec![] } else { vec![0; size as usize] } }
+            }).collect();
+            for (addr, word) in program {
+                let (section, offset) = locate(addr).unwrap();
+                if let Some(slot) = sections[section as usize].words.get_mut((offset / word_size) as usize) { *slot = word; }
+            }
+
+            let mut labels : Vec<(u64, &String)> = assembler.symbols.iter().map(|(name, addr)| (Converter::bin_to_dec_pos_only(addr.to_vec()), name)).collect();
+            labels.sort();
+            let mut symbols = vec![];
+            for (addr, name) in labels {
+                if assembler.externs.contains(name) { return Err(format!("'{0}' is defined here, but also declared .extern", name)); }
+                let binding = if assembler.exports.contains(name) { Binding::Global } else { Binding::Local };
+                let (section, offset) = locate(addr).unwrap();
+                symbols.push(ObjectSymbol { name: name.clone(), binding, section, offset });
+            }
+            if let Some(name) = assembler.exports.iter().find(|name| !assembler.symbols.contains_key(*name)) {
+                return Err(format!("'{0}' is declared .global, but never defined", name));
+            }
+            for name in &assembler.externs {
+                symbols.push(ObjectSymbol { name: name.clone(), binding: Binding::Imported, section: 0, offset: 0 });
+            }
+
+            let relocations = assembler.relocations.iter().map(|(location, field, label)| {
+                let (section, offset) = locate(*location).unwrap();
+                Relocation { section, offset, field: *field as u8, symbol: symbols.iter().position(|symbol| &symbol.name == label).unwrap() as u32 }
+            }).collect();
+            Ok(ObjectFile { addressing, sections, symbols, relocations })
+        }
+
+        pub fn to_bytes(&self) -> Vec<u8> {
+            let mut bytes = MAGIC.to_vec();
+            bytes.extend(VERSION.to_le_bytes());
+            bytes.extend([self.addressing as u8, 0]);
+            bytes.extend((self.sections.len() as u32).to_le_bytes());
+            bytes.extend((self.symbols.len() as u32).to_le_bytes());
+            bytes.extend((self.relocations.len() as u32).to_le_bytes());
+            for section in &self.sections {
+                bytes.push(section.kind as u8);
+                bytes.extend(section.size.to_le_bytes());
+                for word in &section.words { bytes.extend(word.to_le_bytes()); }
+            }
+            for symbol in &self.symbols {
+                bytes.extend((symbol.name.len() as u16).to_le_bytes());
+                bytes.extend(symbol.name.as_bytes());
+                bytes.push(symbol.binding as u8);
+                bytes.extend(symbol.section.to_le_bytes());
+                bytes.extend(symbol.offset.to_le_bytes());
+            }
+            for relocation in &self.relocations {
+                bytes.extend(relocation.section.to_le_bytes());
+                bytes.extend(relocation.offset.to_le_bytes());
+                bytes.push(relocation.field);
+                bytes.extend(relocation.symbol.to_le_bytes());
+            }
+            bytes
+        }
+
+        pub fn from_bytes(bytes : &[u8]) -> Result<ObjectFile, String> {
+            let mut reader = Reader { bytes, position: 0 };
+            if reader.take(4)? != MAGIC { return Err("Not an object file".to_string()); }
+            let version = reader.u16()?;
+            if version != VERSION { return Err(format!("Unsupported object version {0}", version)); }
+            let addressing = Addressing::from_code(reader.u8()?)?;
+            reader.u8()?;
+            let section_count = reader.u32()?;
+            let symbol_count = reader.u32()?;
+            let relocation_count = reader.u32()?;
+
+            let mut sections = vec![];
+            for _ in 0..section_count {
+                let kind = SectionKind::from_code(reader.u8()?)?;
+                let size = reader.u64()?;
+                let mut words = vec![];
+                if kind != SectionKind::Bss {
+                    for _ in 0..size { words.push(reader.u64()?); }
+                }
+                sections.push(ObjectSection { kind, size, words });
+            }
+            let mut symbols = vec![];
+            for _ in 0..symbol_count {
+                let length = reader.u16()? as usize;
+                let name = String::from_utf8(reader.take(length)?.to_vec()).map_err(|_| "Invalid symbol name".to_string())?;
+                let binding = match reader.u8()? {
+                    0 => Binding::Local,
+                    1 => Binding::Global,
+                    2 => Binding::Imported,
+                    other => return Err(format!("Unknown symbol binding {0}", other))
+                };
+                let section = reader.u32()?;
+                if binding != Binding::Imported && section >= section_count { return Err(format!("Symbol '{0}' is in a missing section", name)); }
+                symbols.push(ObjectSymbol { name, binding, section, offset: reader.u64()? });
+            }
+            let mut relocations = vec![];
+            for _ in 0..relocation_count {
+                let relocation = Relocation { section: reader.u32()?, offset: reader.u64()?, field: reader.u8()?, symbol: reader.u32()? };
+                let field = relocation.field as u32;
+                if relocation.section >= section_count || relocation.symbol >= symbol_count || (field > 16 && !(HALFWORD_RELOCATION..HALFWORD_RELOCATION + 4).contains(&field)) {
+                    return Err("Invalid relocation".to_string());
+                }
+                relocations.push(relocation);
+            }
+            if reader.position != bytes.len() { return Err("Unexpected data after the relocations".to_string()); }
+            Ok(ObjectFile { addressing, sections, symbols, relocations })
+        }
+
+        pub fn write(&self, path : &str){
+            std::fs::write(path, self.to_bytes()).expect("Object File Error");
+        }
+
+        pub fn read(path : &str) -> Result<ObjectFile, String> {
+            let bytes = std::fs::read(path).map_err(|error| format!("{0}: {1}", path, error))?;
+            ObjectFile::from_bytes(&bytes).map_err(|error| format!("{0}: {1}", path, error))
+        }
+    }
+
+    pub fn link(objects : &[(String, ObjectFile)], base : u64, entry : Option<&str>) -> Result<(Image, Vec<String>), Vec<String>> {
+        // Objects are (name, object) pairs - returns the image and the lines of the map file, or every error found
+        let mut errors = vec![];
+        let addressing = objects.first().map(|(_, object)| object.addressing).unwrap_or(Addressing::Word);
+        let word_size = addressing.word_size();
+        for (name, object) in objects {
+            if object.addressing != addressing { errors.push(format!("{0} uses a different addressing mode to {1}", name, objects[0].0)); }
+        }
+        if !errors.is_empty() { return Err(errors); }
+
+        // Place the sections - .text, then .data, then .bss
+        let mut placements : Vec<Vec<u64>> = objects.iter().map(|(_, object)| vec![0; object.sections.len()]).collect();
+        let mut order = vec![];
+        let mut next = base;
+        for kind in [SectionKind::Text, SectionKind::Data, SectionKind::Bss] {
+            for (object_index, (_, object)) in objects.iter().enumerate() {
+                for (section_index, section) in object.sections.iter().enumerate() {
+                    if section.kind != kind { continue; }
+                    placements[object_index][section_index] = next;
+                    order.push((object_index, section_index));
+                    next += section.size * word_size;
+                }
+            }
+        }
+
+        // Global symbols, which every object can see
+        let mut globals : HashMap<&str, (u64, usize)> = HashMap::new();
+        for (object_index, (name, object)) in objects.iter().enumerate() {
+            for symbol in object.symbols.iter().filter(|symbol| symbol.binding == Binding::Global) {
+                let addr = placements[object_index][symbol.section as usize] + symbol.offset;
+                if let Some((_, other)) = globals.insert(&symbol.name, (addr, object_index)) {
+                    errors.push(format!("Duplicate symbol '{0}' - defined in {1} and {2}", symbol.name, objects[other].0, name));
+                }
+            }
+        }
+
+        // Apply the relocations
+        let mut contents : Vec<Vec<Vec<u64>>> = objects.iter().map(|(_, object)| object.sections.iter().map(|section| section.words.clone()).collect()).collect();
+        let mut patched = 0;
+        for (object_index, (name, object)) in objects.iter().enumerate() {
+            let mut undefined = vec![];
+            for relocation in &object.relocations {
+                let symbol = &object.symbols[relocation.symbol as usize];
+                let addr = match symbol.binding {
+                    Binding::Imported => match globals.get(symbol.name.as_str()) {
+                        Some((addr, _)) => *addr,
+                        None => {
+                            if !undefined.contains(&symbol.name) { undefined.push(symbol.name.clone()); }
+                            continue;
+                        }
+                    },
+                    _ => placements[object_index][symbol.section as usize] + symbol.offset
+                };
+                let words = &mut contents[object_index][relocation.section as usize];
+                match words.get_mut((relocation.offset / word_size) as usize) {
+                    Some(word) => {
+                        *word = match relocation.field as u32 {
+                            field if field >= HALFWORD_RELOCATION => *word & !(0xFFFF << 18) | (addr >> ((field - HALFWORD_RELOCATION) * 16) & 0xFFFF) << 18, // MOVZ/MOVK immediate
+                            _ => *word & !(FIELD_MASK << relocation.field) | (addr & FIELD_MASK) << relocation.field
+                        };
+                        patched += 1;
+                    }
+                    None => errors.push(format!("{0}: relocation at {1:012X} is outside its section", name, relocation.offset))
+                }
+            }
+            for symbol in undefined { errors.push(format!("Undefined symbol '{0}' - referenced in {1}", symbol, name)); }
+        }
+        if !errors.is_empty() { return Err(errors); }
+
+        let mut image = Image { addressing, entry: base, sections: vec![], symbols: vec![] };
+        for (object_index, section_index) in &order {
+            let section = &objects[*object_index].1.sections[*section_index];
+            if section.size == 0 { continue; }
+            let words = std::mem::take(&mut contents[*object_index][*section_index]);
+            image.sections.push(Section { kind: section.kind, addr: placements[*object_index][*section_index], size: section.size, words });
+        }
+        let mut symbols = vec![]; // (address, name, object, binding)
+        for (object_index, (name, object)) in objects.iter().enumerate() {
+            for symbol in object.symbols.iter().filter(|symbol| symbol.binding != Binding::Imported) {
+                symbols.push((placements[object_index][symbol.section as usize] + symbol.offset, &symbol.name, name, symbol.binding));
+            }
+        }
+        symbols.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+        image.symbols = symbols.iter().map(|(addr, name, _, _)| ((*name).clone(), *addr)).collect();
+
+        // The entry point is a global symbol or hex address - '_start' if there is one, otherwise the base address
+        let entry = entry.or(globals.contains_key("_start").then_some("_start"));
+        if let Some(entry) = entry {
+            image.entry = match globals.get(entry) {
+                Some((addr, _)) => *addr,
+                None => u64::from_str_radix(entry.trim_start_matches("0x"), 16).map_err(|_| vec![format!("Unknown entry point '{0}'", entry)])?
+            };
+        }
+
+        let mut map = vec![format!("ENTRY {0:012X}", image.entry), String::new(), "SECTIONS".to_string()];
+        for (object_index, section_index) in &order {
+            let section = &objects[*object_index].1.sections[*section_index];
+            let start = placements[*object_index][*section_index];
+            let end = start + section.size * word_size;
+            map.push(format!("  {0:<6} {1:012X}-{2:012X}  {3:>6} words  {4}", section.kind.name(), start, end, section.size, objects[*object_index].0));
+        }
+        map.push(String::new());
+        map.push("SYMBOLS".to_string());
+        for (addr, symbol, object, binding) in symbols {
+            map.push(format!("  {0:012X}  {1:<24} {2:<6} {3}", addr, symbol, if binding == Binding::Global { "global" } else { "local" }, object));
+        }
+        map.push(String::new());
+        map.push(format!("{0} relocations applied", patched));
+        Ok((image, map))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::assemble_program;
+        use crate::tests::program_file;
+
+        fn object(addressing : Addressing, sections : Vec<(SectionKind, Vec<u64>)>, symbols : Vec<(&str, Binding, u32, u64)>, relocations : Vec<(u32, u64, u8, u32)>) -> ObjectFile {
+            ObjectFile {
+                addressing,
+                sections: sections.into_iter().map(|(kind, words)| ObjectSection { kind, size: words.len() as u64, words }).collect(),
+                symbols: symbols.into_iter().map(|(name, binding, section, offset)| ObjectSymbol { name: name.to_string(), binding, section, offset }).collect(),
+                relocations: relocations.into_iter().map(|(section, offset, field, symbol)| Relocation { section, offset, field, symbol }).collect()
+            }
+        }
+
+        #[test]
+        fn relocation_fields() {
+            // LDR/STR (8), B (9) and VLDR/VSTR (16) address fields, with the bits either side of each left alone
+            let text = vec![0xFF00_0000_0000_00FF, 0xFE00_0000_0000_01FF, 0x0000_0000_0000_FFFF];
+            let first = object(Addressing::Word, vec![(SectionKind::Text, text), (SectionKind::Data, vec![7])],
+                vec![("target", Binding::Local, 1, 0)], vec![(0, 0, 8, 0), (0, 64, 9, 0), (0, 128, 16, 0)]);
+            let (image, _) = link(&[("first.o".to_string(), first)], 0x1000, None).ok().unwrap();
+            let target : u64 = 0x1000 + 3 * 64; // After the three .text words
+            assert_eq!(image.sections[0].words, vec![0xFF00_0000_0000_00FF | target << 8, 0xFE00_0000_0000_01FF | target << 9, 0xFFFF | target << 16]);
+            assert_eq!((image.sections[1].addr, image.sections[1].words.clone()), (target, vec![7]));
+        }
+
+        #[test]
+        fn duplicate_globals() {
+            let first = object(Addressing::Word, vec![(SectionKind::Text, vec![0])], vec![("f", Binding::Global, 0, 0)], vec![]);
+            let second = object(Addressing::Word, vec![(SectionKind::Text, vec![0])], vec![("f", Binding::Global, 0, 0)], vec![]);
+            let errors = link(&[("a.o".to_string(), first), ("b.o".to_string(), second)], 0, None).err().unwrap();
+            assert_eq!(errors, vec!["Duplicate symbol 'f' - defined in a.o and b.o".to_string()]);
+        }
+
+        #[test]
+        fn undefined_extern() {
+            let first = object(Addressing::Word, vec![(SectionKind::Text, vec![0, 0])], vec![("g", Binding::Imported, 0, 0)], vec![(0, 0, 9, 0), (0, 64, 9, 0)]);
+            let errors = link(&[("a.o".to_string(), first)], 0, None).err().unwrap();
+            assert_eq!(errors, vec!["Undefined symbol 'g' - referenced in a.o".to_string()]); // Reported once
+        }
+
+        #[test]
+        fn mixed_addressing() {
+            let first = object(Addressing::Word, vec![(SectionKind::Text, vec![0])], vec![], vec![]);
+            let second = object(Addressing::Byte, vec![(SectionKind::Text, vec![0])], vec![], vec![]);
+            let errors = link(&[("a.o".to_string(), first), ("b.o".to_string(), second)], 0, None).err().unwrap();
+            assert_eq!(errors, vec!["b.o uses a different addressing mode to a.o".to_string()]);
+        }
+
+        #[test]
+        fn load_label_address() {
+            // LI's MOVZ and two MOVKs each get the matching 16 bits of the label's final address
+            let path = program_file("object_li", &["LI R1, &value", "HLT", ".data", "value: .dword 9"]);
+            let (program, assembler, _) = assemble_program(&path, Addressing::Word.word_size(), true);
+            std::fs::remove_file(&path).ok();
+            let object = ObjectFile::from_program(Addressing::Word, crate::to_words(program), &assembler).unwrap();
+            let fields : Vec<u8> = object.relocations.iter().map(|relocation| relocation.field).collect();
+            assert_eq!(fields, vec![64, 65, 66]);
+
+            let object = ObjectFile::from_bytes(&object.to_bytes()).unwrap();
+            let base = 0x1234_5678_0000;
+            let (image, _) = link(&[("a.o".to_string(), object)], base, None).ok().unwrap();
+            let value = base + 5 * 64; // After the three moves, HLT and .data
+            let halves : Vec<u64> = image.sections[0].words[0..3].iter().map(|word| word >> 18 & 0xFFFF).collect();
+            assert_eq!(halves, vec![value & 0xFFFF, value >> 16 & 0xFFFF, value >> 32 & 0xFFFF]);
+        }
+
+        #[test]
+        fn sections_from_directives() {
+            let path = program_file("object_sections", &[".global _start", "_start: LDR R1, &value", "HLT", ".data", "value: .dword 9", ".bss", "buffer: .zero 8"]);
+            let (program, assembler, _) = assemble_program(&path, Addressing::Word.word_size(), true);
+            std::fs::remove_file(&path).ok();
+            let object = ObjectFile::from_program(Addressing::Word, crate::to_words(program), &assembler).unwrap();
+            let kinds : Vec<SectionKind> = object.sections.iter().map(|section| section.kind).collect();
+            assert!(kinds == vec![SectionKind::Text, SectionKind::Data, SectionKind::Bss]);
+            let symbols : Vec<(&str, u32, u64)> = object.symbols.iter().map(|symbol| (symbol.name.as_str(), symbol.section, symbol.offset)).collect();
+            assert_eq!(symbols, vec![("_start", 0, 0x40), ("value", 1, 0x40), ("buffer", 2, 0x40)]);
+
+            let object = ObjectFile::from_bytes(&object.to_bytes()).unwrap();
+            let (image, _) = link(&[("a.o".to_string(), object)], 0x1000, None).ok().unwrap();
+            assert_eq!(image.entry, 0x1040);
+            assert_eq!(image.sections[0].words[1] >> 8 & FIELD_MASK, 0x1000 + 0xC0 + 0x40); // .data follows the three .text words
+            assert_eq!(image.sections[1].words, vec![0, 9]);
+            assert!(image.sections[2].kind == SectionKind::Bss && image.sections[2].size == 2);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+pub(crate) mod macros{
+    /*
+     * Macro expansion - runs over the program's lines before they are assembled
+     *
+     *   .macro <name> [<param>, <param>...]
+     *   ...body, using \<param> for each argument...
+     *   .endm
+     *
+     * '<name> <arg>, <arg>...' anywhere after the definition is replaced by the body, with every \<param> replaced by its
+     * argument, and \@ by a number unique to that expansion - so 'loop\@:' gives each expansion its own label
+     * A label on the calling line labels the first line of the expansion
+     *
+     * Macros may call other macros, up to MAX_DEPTH deep - past that the macro is assumed to call itself forever
+     * The LI pseudo-instruction is expanded here too, into MOVZ/MOVN and MOVK
+     * Definitions and calls take up no space - only the expanded lines do
+     */
+    use std::collections::HashMap;
+    use crate::assembler::assembler::Assembler;
+
+    const MAX_DEPTH : usize = 32;
+
+    pub(crate) struct SourceLine {
+        pub(crate) text : String,
+        pub(crate) line : usize, // Line number in the file, from 1 - for expanded lines, the line of the outermost call
+        pub(crate) depth : usize, // 0 for lines written in the file, 1 for their expansions, and so on
+        pub(crate) placed : bool // Takes up a word - false for macro definitions and calls, which are only listed
+    }
+
+    struct Macro {
+        params : Vec<String>,
+        body : Vec<String>
+    }
+
+    #[derive(Default)]
+    pub(crate) struct MacroExpander {
+        macros : HashMap<String, Macro>,
+        expansions : u64
+    }
+
+    fn list(text : &str) -> Vec<String> {
+        // Comma separated, e.g. macro parameters or call arguments
+        text.split(',').map(|item| item.trim().to_string()).filter(|item| !item.is_empty()).collect()
+    }
+
+    impl MacroExpander {
+
+        pub fn expand(&mut self, lines : Vec<String>) -> Result<Vec<SourceLine>, String> {
+            let mut expanded = vec![];
+            let mut definition : Option<(String, Macro)> = None;
+            for (index, text) in lines.into_iter().enumerate() {
+                let line = index + 1;
+                let directive = text.split_whitespace().next().unwrap_or("");
+                if let Some((name, mut body)) = definition.take() {
+                    match directive {
+                        ".endm" => { self.macros.insert(name, body); }
+                        ".macro" => return Err(format!("Line {0}: .macro inside the definition of '{1}'", line, name)),
+                        _ => {
+                            body.body.push(text.clone());
+                            definition = Some((name, body));
+                        }
+                    }
+                    expanded.push(SourceLine { text, line, depth: 0, placed: false });
+                    continue;
+                }
+                match directive {
+                    ".macro" => {
+                        let header = text.trim_start()[".macro".len()..].trim();
+                        let (name, params) = header.split_once(char::is_whitespace).unwrap_or((header, ""));
+                        if name.is_empty() { return Err(format!("Line {0}: .macro needs a name", line)); }
+                        definition = Some((name.to_string(), Macro { params: list(params), body: vec![] }));
+                        expanded.push(SourceLine { text, line, depth: 0, placed: false });
+                    }
+                    ".endm" => return Err(format!("Line {0}: .endm without .macro", line)),
+                    _ => self.expand_line(text, line, 0, &mut expanded)?
+                }
+            }
+            if let Some((name, _)) = definition { return Err(format!("Macro '{0}' has no .endm", name)); }
+            Ok(expanded)
+        }
+
+        fn expand_line(&mut self, text : String, line : usize, depth : usize, expanded : &mut Vec<SourceLine>) -> Result<(), String> {
+            let (label, rest) = Assembler::split_label(text.trim_start());
+            let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((&rest, ""));
+            if name == "LI" {
+                // Pseudo-instruction - expanded like a macro, as it may take up to four words
+                let mut body = Assembler::expand_li(args).map_err(|error| format!("Line {0}: {1}", line, error))?;
+                if let Some(label) = label { body[0] = format!("{0}: {1}", label, body[0]); }
+                expanded.push(SourceLine { text, line, depth, placed: false });
+                expanded.extend(body.into_iter().map(|body_line| SourceLine { text: body_line, line, depth: depth + 1, placed: true }));
+                return Ok(());
+            }
+            let Some(definition) = self.macros.get(name) else {
+                expanded.push(SourceLine { text, line, depth, placed: true });
+                return Ok(());
+            };
+            if depth >= MAX_DEPTH {
+                return Err(format!("Line {0}: macros nested more than {1} deep - does '{2}' call itself?", line, MAX_DEPTH, name));
+            }
+            let args = list(args);
+            if args.len() != definition.params.len() {
+                return Err(format!("Line {0}: '{1}' takes {2} arguments, not {3}", line, name, definition.params.len(), args.len()));
+            }
+
+            // Longest parameter names first, so \count isn't replaced as \c followed by 'ount'
+            let mut substitutions : Vec<(String, &String)> = definition.params.iter().map(|param| format!("\\{0}", param)).zip(args.iter()).collect();
+            substitutions.sort_by_key(|(param, _)| std::cmp::Reverse(param.len()));
+            self.expansions += 1;
+            let unique = self.expansions.to_string();
+            let mut body : Vec<String> = definition.body.iter().map(|body_line| {
+                let mut body_line = body_line.replace("\\@", &unique);
+                for (param, arg) in &substitutions { body_line = body_line.replace(param, arg); }
+                body_line
+            }).collect();
+            if let Some(label) = label {
+                match body.first_mut() {
+                    Some(first) => *first = format!("{0}: {1}", label, first.trim_start()),
+                    None => body.push(format!("{0}:", label))
+                }
+            }
+
+            expanded.push(SourceLine { text, line, depth, placed: false });
+            for body_line in body { self.expand_line(body_line, line, depth + 1, expanded)?; }
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn expand(lines : &[&str]) -> Result<Vec<SourceLine>, String> {
+            MacroExpander::default().expand(lines.iter().map(|line| line.to_string()).collect())
+        }
+
+        fn error(lines : &[&str]) -> String {
+            match expand(lines) {
+                Ok(_) => panic!("Expanded without an error"),
+                Err(error) => error
+            }
+        }
+
+        fn placed(lines : &[&str]) -> Vec<String> {
+            // The lines which take up space once expanded
+            expand(lines).unwrap().into_iter().filter(|line| line.placed).map(|line| line.text.trim().to_string()).collect()
+        }
+
+        #[test]
+        fn parameters_substituted() {
+            let lines = [".macro SET reg, c, count", "ADD \\reg, #0, #\\count", "OUT A \\c", ".endm", "start: SET R1, R2, 5", "HLT"];
+            assert_eq!(placed(&lines), ["start: ADD R1, #0, #5", "OUT A R2", "HLT"]);
+            let expanded = expand(&lines).unwrap();
+            assert!(expanded[..5].iter().all(|line| !line.placed)); // The definition and the call
+            assert_eq!((expanded[5].line, expanded[5].depth), (5, 1));
+            assert!(error(&[".macro SET reg", ".endm", "SET R1, R2"]).contains("'SET' takes 1 arguments, not 2"));
+        }
+
+        #[test]
+        fn unique_labels() {
+            let lines = [".macro WAIT reg", "wait\\@: SUB \\reg, \\reg, #1", "BNE &wait\\@", ".endm", "WAIT R1", "WAIT R2"];
+            assert_eq!(placed(&lines), ["wait1: SUB R1, R1, #1", "BNE &wait1", "wait2: SUB R2, R2, #1", "BNE &wait2"]);
+        }
+
+        #[test]
+        fn nested_calls() {
+            let lines = [".macro INNER reg", "ADD \\reg, \\reg, #1", ".endm", ".macro OUTER reg", "INNER \\reg", "INNER \\reg", ".endm", "OUTER R3"];
+            assert_eq!(placed(&lines), ["ADD R3, R3, #1", "ADD R3, R3, #1"]);
+            assert_eq!(expand(&lines).unwrap().iter().map(|line| line.depth).max(), Some(2));
+        }
+
+        #[test]
+        fn recursion_limit() {
+            let message = error(&[".macro FOREVER", "FOREVER", ".endm", "FOREVER"]);
+            assert!(message.contains(&format!("nested more than {0} deep", MAX_DEPTH)) && message.contains("'FOREVER'"), "{}", message);
+            // Exactly MAX_DEPTH deep is fine
+            let mut lines = vec![".macro M0".to_string(), "HLT".to_string(), ".endm".to_string()];
+            for depth in 1..MAX_DEPTH {
+                lines.extend([format!(".macro M{0}", depth), format!("M{0}", depth - 1), ".endm".to_string()]);
+            }
+            lines.push(format!("M{0}", MAX_DEPTH - 1));
+            let lines : Vec<&str> = lines.iter().map(|line| line.as_str()).collect();
+            assert_eq!(placed(&lines), ["HLT"]);
+        }
+
+        #[test]
+        fn definition_errors() {
+            assert!(error(&[".macro A", ".macro B", ".endm", ".endm"]).contains("Line 2: .macro inside the definition of 'A'"));
+            assert!(error(&[".macro A", "HLT"]).contains("Macro 'A' has no .endm"));
+            assert!(error(&["HLT", ".endm"]).contains("Line 2: .endm without .macro"));
+            assert!(error(&[".macro", ".endm"]).contains(".macro needs a name"));
+        }
+    }
+}
@@ -1,5 +1,3 @@
-#![feature(anonymous_pipe)]
-
 mod assembler;
 mod converter;
 mod control_unit;
@@ -16,22 +14,64 @@ mod buses;
 mod multiplier;
 mod caches;
 mod bitwise_operator;
+mod system_regs;
+mod io_bus;
+mod devices;
+mod output_sink;
+mod gui_pipe;
+mod gdb_stub;
+mod disassembler;
+mod debugger;
+mod trace;
+mod checkpoint;
+mod history;
+mod clock_domains;
+mod dram_controller;
+mod ecc;
+mod fault_injector;
+mod image;
+mod linker;
+mod macros;
+mod fpu;
+mod simd;
 
 use std::io::*;
 use std::fs::File;
-use std::pipe::pipe;
+use std::collections::{BTreeMap, HashMap};
 use regex::Regex;
-use crate::assembler::assembler::{Assembler as assembler_struct, ParsedInstruction};
+use crate::assembler::assembler::{Assembler as assembler_struct, DataDirective, ParsedInstruction};
 use crate::control_unit::control_unit::ControlUnit as control_unit_struct;
 use crate::control_unit::control_unit::CpuState;
 use crate::reg64::reg64::Reg64 as reg64_struct;
 use crate::reg_bank::reg_bank::RegBank as reg_bank_struct;
-use crate::main_memory::main_memory::MainMemory;
+use crate::main_memory::main_memory::{Addressing, MainMemory};
 use crate::alu::alu::Alu as alu_struct;
+use crate::fpu::fpu::Fpu;
+use crate::simd::simd::Simd;
 use crate::clock::clock::Clock as clock_struct;
+use crate::clock::clock::MIN_CLOCK_SPEED;
 use crate::converter::converter::{Converter as converter_struct, Converter};
 use crate::buses::buses::{AddressBus, ControlBus, DataBus};
 use crate::caches::caches::{DataAccessManager, L1Cache, L2Cache };
+use crate::system_regs::system_regs::{PrivilegeLevel, SystemRegs};
+use crate::io_bus::io_bus::AddressDecoder;
+use crate::devices::devices::{ConsoleInput, Timer};
+use crate::output_sink::output_sink::{BufferSink, FileSink, OutputSink, StdoutSink};
+use crate::gui_pipe::gui_pipe as gui_link;
+use crate::gdb_stub::gdb_stub::serve as serve_gdb;
+use crate::debugger::debugger::run as run_debugger;
+use crate::trace::trace::{TraceFilter, TraceFormat, Tracer};
+use crate::checkpoint::checkpoint::{restore as restore_checkpoint, save as save_checkpoint};
+use crate::history::history::History;
+use crate::clock_domains::clock_domains::{ClockDomain, DomainScheduler};
+use crate::dram_controller::dram_controller::{DramController, DramTiming};
+use crate::ecc::ecc::{EccMode, EccStats};
+use crate::fault_injector::fault_injector::{Fault, FaultInjector, RandomFaults};
+use crate::image::image::{Image, SectionKind};
+use crate::linker::linker::{link, ObjectFile};
+use crate::macros::macros::{MacroExpander, SourceLine};
+
+type MemoryWords = Vec<([bool; 48], [bool; 64])>; // Addresses and the words to store at them
 
 fn check_comment(str : String) -> bool {
     // Check if line is a comment - comment lines start, like in C++, with '//'
@@ -56,27 +96,451 @@ fn vec_to_str(mut val : Vec<bool>) -> String {
     return_str
 }
 
-fn main() {
-    println!(" ----- START -----");
-    load_memory();
+pub(crate) struct RunOptions {
+    // Command line options
+    pub(crate) input_file : Option<String>, // Scripted input for IN - stdin if not given
+    pub(crate) output : String, // Destination for OUT - 'stdout', 'buffer' (printed after the program halts) or a file path
+    pub(crate) gui_socket : Option<String>, // Unix socket to wait for a GUI front end on
+    pub(crate) gdb_port : Option<u16>, // TCP port to wait for gdb on
+    pub(crate) debug : bool, // Start in the interactive debugger
+    pub(crate) trace : Option<String>, // Destination for the execution trace - 'stdout' or a file path
+    pub(crate) trace_format : TraceFormat,
+    pub(crate) trace_range : Option<(u64, u64)>, // Only trace instructions in [start, end)
+    pub(crate) trace_only : Vec<String>, // Only trace these instructions
+    pub(crate) restore : Option<String>, // Checkpoint to continue from
+    pub(crate) checkpoint : Option<String>, // File to save a checkpoint to
+    pub(crate) checkpoint_at : Option<u64>, // Cycle to save the checkpoint at - when the program halts if not given
+    pub(crate) core_dump : Option<String>, // File to save a checkpoint to if the emulator crashes
+    pub(crate) history_mb : Option<usize>, // Memory budget for reverse execution - on by default under a debugger
+    pub(crate) clock_speed : Option<f64>, // Run in real time at this frequency (Hz) - as fast as possible if not given
+    pub(crate) domains : DomainScheduler, // Simulated frequency and phase of the CPU, memory and devices
+    pub(crate) dram : DramTiming, // DRAM controller timings - see dram_controller.rs
+    pub(crate) ecc : EccMode,
+    pub(crate) faults : Vec<Fault>, // Bit flips to inject - see fault_injector.rs
+    pub(crate) random_faults : Option<RandomFaults>,
+    pub(crate) addressing : Addressing, // Byte addresses, or bit addresses (words 0x40 apart) for older programs
+    pub(crate) image : Option<String> // Executable image to run, in place of input_data.txt and the program file
+}
+
+impl RunOptions {
+    pub fn parse(args : Vec<String>) -> RunOptions {
+        let mut options = RunOptions::default();
+        let mut index = 1;
+        while index < args.len() {
+            match args[index].as_str() {
+                "--input" => {
+                    index += 1;
+                    options.input_file = Some(args.get(index).expect("Missing input file").clone());
+                }
+                "--output" => {
+                    index += 1;
+                    options.output = args.get(index).expect("Missing output destination").clone();
+                }
+                "--gui" => {
+                    index += 1;
+                    options.gui_socket = Some(args.get(index).expect("Missing GUI socket path").clone());
+                }
+                "--gdb" => {
+                    index += 1;
+                    options.gdb_port = Some(args.get(index).expect("Missing gdb port").parse().expect("Invalid gdb port"));
+                }
+                "--debug" => { options.debug = true; }
+                "--trace" => {
+                    index += 1;
+                    options.trace = Some(args.get(index).expect("Missing trace destination").clone());
+                }
+                "--trace-format" => {
+                    index += 1;
+                    options.trace_format = match args.get(index).map(|format| format.as_str()) {
+                        Some("text") => TraceFormat::Text,
+                        Some("json") => TraceFormat::Json,
+                        _ => panic!("Trace format must be 'text' or 'json'")
+                    };
+                }
+                "--trace-range" => { // <start>:<end>, in hex
+                    index += 1;
+                    let (start, end) = args.get(index).and_then(|range| range.split_once(':')).expect("Trace range must be <start>:<end>");
+                    let parse_hex = |hex : &str| u64::from_str_radix(hex.trim_start_matches("0x"), 16).expect("Invalid trace range");
+                    options.trace_range = Some((parse_hex(start), parse_hex(end)));
+                }
+                "--restore" => {
+                    index += 1;
+                    options.restore = Some(args.get(index).expect("Missing checkpoint file").clone());
+                }
+                "--checkpoint" => {
+                    index += 1;
+                    options.checkpoint = Some(args.get(index).expect("Missing checkpoint file").clone());
+                }
+                "--checkpoint-at" => {
+                    index += 1;
+                    options.checkpoint_at = Some(args.get(index).expect("Missing checkpoint cycle").parse().expect("Invalid checkpoint cycle"));
+                }
+                "--core-dump" => {
+                    index += 1;
+                    options.core_dump = Some(args.get(index).expect("Missing core dump file").clone());
+                }
+                "--history" => {
+                    index += 1;
+                    options.history_mb = Some(args.get(index).expect("Missing history budget").parse().expect("Invalid history budget"));
+                }
+                "--clock" => { // <hz> or 'max'
+                    index += 1;
+                    options.clock_speed = match args.get(index).expect("Missing clock speed").as_str() {
+                        "max" => None,
+                        speed => Some(speed.parse().ok().filter(|speed : &f64| speed.is_finite() && *speed >= MIN_CLOCK_SPEED).expect("Invalid clock speed"))
+                    };
+                }
+                "--domain" => { // <cpu|memory|io>=<hz>[:<phase>], e.g. memory=25M:90
+                    index += 1;
+                    let (name, spec) = args.get(index).and_then(|domain| domain.split_once('=')).expect("Clock domain must be <name>=<hz>[:<phase>]");
+                    let domain = ClockDomain::parse(spec).expect("Invalid clock domain frequency or phase");
+                    if !options.domains.set(name, domain) { panic!("Clock domain must be cpu, memory or io"); }
+                }
+                "--dram" => { // 'off', or e.g. cas=3,rcd=3,rp=3,refi=780,rfc=26,retention=5000
+                    index += 1;
+                    options.dram = DramTiming::parse(args.get(index).expect("Missing DRAM timings")).unwrap_or_else(|error| panic!("{}", error));
+                }
+                "--ecc" => {
+                    index += 1;
+                    options.ecc = EccMode::parse(args.get(index).expect("Missing ECC setting")).expect("ECC must be 'off', 'memory' or 'all'");
+                }
+                "--fault" => { // <cycle>:<target>[:<bits>]
+                    index += 1;
+                    options.faults.push(Fault::parse(args.get(index).expect("Missing fault")).unwrap_or_else(|error| panic!("{}", error)));
+                }
+                "--fault-random" => { // <seed>:<rate>[:<kinds>]
+                    index += 1;
+                    options.random_faults = Some(RandomFaults::parse(args.get(index).expect("Missing random fault settings")).unwrap_or_else(|error| panic!("{}", error)));
+                }
+                "--addressing" => { // 'byte', or 'word' for programs written before byte addressing
+                    index += 1;
+                    options.addressing = Addressing::parse(args.get(index).expect("Missing addressing mode")).expect("Addressing must be 'byte' or 'word'");
+                }
+                "--image" => { // Written by 'cpu_emu assemble' - sets the addressing mode too
+                    index += 1;
+                    options.image = Some(args.get(index).expect("Missing image file").clone());
+                }
+                "--trace-only" => { // Comma separated, e.g. LDR,STR,BEQ
+                    index += 1;
+                    options.trace_only = args.get(index).expect("Missing instruction list").split(',').map(|mnemonic| mnemonic.to_uppercase()).collect();
+                }
+                other => { println!("Unknown option: {0}", other); }
+            }
+            index += 1;
+        }
+        options
+    }
 }
 
-fn load_memory() {
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            input_file: None, output: "stdout".to_string(), gui_socket: None, gdb_port: None, debug: false,
+            trace: None, trace_format: TraceFormat::Text, trace_range: None, trace_only: vec![],
+            restore: None, checkpoint: None, checkpoint_at: None, core_dump: None, history_mb: None,
+            clock_speed: None, domains: DomainScheduler::default(),
+            dram: DramTiming::default(), ecc: EccMode::Off, faults: vec![], random_faults: None,
+            addressing: Addressing::Word, image: None
+        }
+    }
+}
 
-    println!(" ----- DATA LOAD START -----");
+fn main() {
+    let args : Vec<String> = std::env::args().collect();
+    match args.get(1).map(|command| command.as_str()) {
+        Some("assemble") => return assemble_image(args),
+        Some("link") => return link_image(args),
+        _ => {}
+    }
+    println!(" ----- START -----");
+    let options = RunOptions::parse(args);
+    load_memory(options);
+}
 
-    // Load locations and values from 'input_data.txt', and store in the appropriate memory location
+fn build_machine(options : &mut RunOptions, output : Box<dyn OutputSink>) -> (clock_struct, HashMap<String, [bool; 48]>) {
+    // Memory, devices, the loaded program and the CPU, ready to start - faults and clock domains are taken from the options
 
     let mut memory: MainMemory = MainMemory{
         // Initialise main memory
         ram_map: Default::default(),
         data_bus: DataBus::default(),
         address_bus: AddressBus::default(),
-        control_bus: ControlBus::default()
+        control_bus: ControlBus::default(),
+        address_decoder: AddressDecoder::default(),
+        addressing: Addressing::Word,
+        dram: DramController::new(options.dram),
+        ecc: options.ecc != EccMode::Off,
+        check_bits: HashMap::new(),
+        ecc_stats: EccStats::default(),
+        changed_words: vec![]
     };
     memory.clear();
+    memory.set_addressing(options.addressing);
+    memory.control_bus.sync_stages = options.domains.sync_stages(); // Handshakes between clock domains are synchronized
+
+    let console_input = match &options.input_file {
+        Some(path) => ConsoleInput::from_file(path),
+        None => ConsoleInput::from_stdin()
+    };
+    map_devices(&mut memory, &console_input);
+
+    let (symbols, entry) = match &options.image {
+        Some(path) => {
+            // A prebuilt program - its sections go straight into memory
+            let image = Image::read(path).unwrap_or_else(|error| panic!("{}", error));
+            image.load(&mut memory);
+            println!(" ----- IMAGE LOADED FROM {0} -----", path);
+            (image.symbol_map(), image.entry)
+        }
+        None => {
+            // Load locations and values from 'input_data.txt', then assemble the program over them - starting at address 0
+            println!(" ----- DATA LOAD START -----");
+            for (addr, data) in read_data("./input_data.txt") { memory.write(addr, data); }
+            println!(" ----- DATA LOAD COMPLETE -----");
+            let (program, assembler, _) = assemble_program("./recursive_fib.txt", options.addressing.word_size(), false);
+            for (addr, word) in program { memory.write(addr, word); }
+            println!(" ----- INSTRUCTION LOAD COMPLETE -----");
+            (assembler.symbols, 0)
+        }
+    };
+
+    let mut cpu_cu: control_unit_struct = control_unit_struct {
+        alu: alu_struct::default(), fpu: Fpu::default(), simd: Simd::default(), memory_instr_reg: reg64_struct::default(),
+        memory_instr_stall: false, memory_data_reg: reg64_struct::default(), memory_data_stall: false,
+        pc: reg64_struct::default(), register_bank: reg_bank_struct::default(), halt: false, faulted: false,
+        state: CpuState::Fetch, decoded_instruction: ParsedInstruction::default(),
+        privilege: PrivilegeLevel::Supervisor, system_regs: SystemRegs::default(), // Start in Supervisor mode
+        console_input, output,
+        data_access_manager: DataAccessManager{
+            l1_cache: L1Cache::default(), l2_cache: L2Cache::default(), main_memory: memory, last_access_level: -1
+        } // Set up a default CPU
+    };
+    cpu_cu.pc.set_data(Converter::dec_to_bin_pos_only(entry, 64).try_into().unwrap()); // Where the image says to start
+    cpu_cu.data_access_manager.l1_cache.set_ecc(options.ecc == EccMode::All);
+    cpu_cu.data_access_manager.l2_cache.set_ecc(options.ecc == EccMode::All);
+
+    let clk: clock_struct = clock_struct{
+        clock_speed : options.clock_speed.unwrap_or(100.0),
+        throttled : options.clock_speed.is_some(),
+        running : false,
+        paused : false,
+        ctrl : cpu_cu,
+        tracer : None,
+        checkpoint : options.checkpoint.clone().map(|path| (options.checkpoint_at, path)),
+        history : None,
+        domains : std::mem::take(&mut options.domains),
+        faults : (!options.faults.is_empty() || options.random_faults.is_some())
+            .then_some(FaultInjector { faults: std::mem::take(&mut options.faults), random: options.random_faults.take(), injected: 0 }),
+        cycle_count : 0
+    }; // Create a default clock, with the new CPU
+    (clk, symbols)
+}
+
+fn load_memory(mut options : RunOptions) {
+
+    let output_buffer = BufferSink::default();
+    let output : Box<dyn OutputSink> = match options.output.as_str() {
+        "stdout" => Box::new(StdoutSink {}),
+        "buffer" => Box::new(output_buffer.clone()),
+        path => Box::new(FileSink::create(path))
+    };
+    let (mut clk, symbols) = build_machine(&mut options, output);
+
+    if let Some(destination) = &options.trace {
+        let trace_output : Box<dyn OutputSink> = match destination.as_str() {
+            "stdout" => Box::new(StdoutSink {}),
+            path => Box::new(FileSink::create(path))
+        };
+        let filter = TraceFilter { addr_range: options.trace_range, mnemonics: options.trace_only.clone() };
+        clk.tracer = Some(Tracer::new(trace_output, options.trace_format, filter));
+    }
+
+    if let Some(path) = &options.gui_socket {
+        gui_link::connect(path);
+    }
+    if let Some(path) = &options.restore {
+        restore_checkpoint(&mut clk, path).expect("Checkpoint File Error");
+    }
+    let debugging = options.debug || options.gdb_port.is_some();
+    match options.history_mb {
+        Some(0) => {} // Turned off
+        Some(budget) => { clk.history = Some(History::new(budget)); }
+        None if debugging => { clk.history = Some(History::new(64)); }
+        None => {}
+    }
+
+    // A panic part way through the program leaves the machine state to be saved as a core dump
+    let run_result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        match options.gdb_port {
+            Some(port) => serve_gdb(&mut clk, port), // The debugger controls the clock
+            None if options.debug => run_debugger(&mut clk, symbols),
+            None => clk.start() // Start the clock
+        }
+    }));
+    if let Err(panic) = run_result {
+        if let Some(path) = &options.core_dump {
+            save_checkpoint(&mut clk, path);
+        }
+        std::panic::resume_unwind(panic);
+    }
 
-    for line in BufReader::new(File::open("./input_data.txt").expect("Input File Error")).lines() {
+    if options.output == "buffer" {
+        println!("----- PROGRAM OUTPUT -----");
+        println!("{}", output_buffer.contents());
+    }
+    println!("----- END -----");
+}
+
+fn assemble_image(args : Vec<String>) {
+    // cpu_emu assemble <program> <image> [--data <file>] [--addressing byte|word] [--entry <label|address>] [--listing <file>]
+    // cpu_emu assemble --object <program> <object> [--addressing byte|word] [--listing <file>]
+    // Data words go in before the program, so the program overwrites them where they clash, as when running from text
+    let mut paths = vec![];
+    let mut data_path = None;
+    let mut addressing = Addressing::Word;
+    let mut entry = None;
+    let mut object = false;
+    let mut listing_path = None;
+    let mut index = 2;
+    while index < args.len() {
+        match args[index].as_str() {
+            "--object" => object = true,
+            "--listing" => {
+                index += 1;
+                listing_path = Some(args.get(index).expect("Missing listing file").clone());
+            }
+            "--data" => {
+                index += 1;
+                data_path = Some(args.get(index).expect("Missing data file").clone());
+            }
+            "--addressing" => {
+                index += 1;
+                addressing = Addressing::parse(args.get(index).expect("Missing addressing mode")).expect("Addressing must be 'byte' or 'word'");
+            }
+            "--entry" => {
+                index += 1;
+                entry = Some(args.get(index).expect("Missing entry point").clone());
+            }
+            path => paths.push(path.to_string())
+        }
+        index += 1;
+    }
+    if paths.len() != 2 { panic!("Usage: cpu_emu assemble [--object] <program> <image> [--data <file>] [--addressing byte|word] [--entry <label|address>] [--listing <file>]"); }
+
+    let (program, assembler, listing) = assemble_program(&paths[0], addressing.word_size(), object);
+    if let Some(path) = &listing_path {
+        std::fs::write(path, listing.join("\n") + "\n").expect("Listing File Error");
+    }
+    if object {
+        // Relocatable - placed and given its data by the linker
+        if data_path.is_some() || entry.is_some() { panic!("--data and --entry are given to 'cpu_emu link' for objects"); }
+        let object_file = ObjectFile::from_program(addressing, to_words(program), &assembler).unwrap_or_else(|error| panic!("{}", error));
+        object_file.write(&paths[1]);
+        println!("{0} sections, {1} symbols, {2} relocations", object_file.sections.len(), object_file.symbols.len(), object_file.relocations.len());
+        println!(" ----- OBJECT WRITTEN TO {0} -----", paths[1]);
+        return;
+    }
+    let mut image = Image { addressing, entry: 0, sections: vec![], symbols: vec![] };
+    if let Some(path) = &data_path {
+        image.add_words(SectionKind::Data, to_words(read_data(path)));
+    }
+    add_program(&mut image, program, &assembler);
+    let symbols = assembler.symbols;
+
+    // The entry point is a label or hex address - '_start' if there is one, otherwise address 0
+    let entry = entry.or(symbols.contains_key("_start").then(|| "_start".to_string()));
+    image.entry = match entry {
+        Some(entry) => match symbols.get(&entry) {
+            Some(addr) => Converter::bin_to_dec_pos_only(addr.to_vec()),
+            None => u64::from_str_radix(entry.trim_start_matches("0x"), 16).unwrap_or_else(|_| panic!("Unknown entry point '{0}'", entry))
+        },
+        None => 0
+    };
+    image.symbols = symbols.iter().map(|(name, addr)| (name.clone(), Converter::bin_to_dec_pos_only(addr.to_vec()))).collect();
+    image.symbols.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+
+    image.write(&paths[1]);
+    for line in image.describe() { println!("{}", line); }
+    println!(" ----- IMAGE WRITTEN TO {0} -----", paths[1]);
+}
+
+fn link_image(args : Vec<String>) {
+    // cpu_emu link <objects...> -o <image> [--map <file>] [--base <address>] [--entry <label|address>] [--data <file>]
+    let mut objects = vec![];
+    let mut output = None;
+    let mut map_path = None;
+    let mut base = 0;
+    let mut entry = None;
+    let mut data_path = None;
+    let mut index = 2;
+    while index < args.len() {
+        match args[index].as_str() {
+            "-o" => {
+                index += 1;
+                output = Some(args.get(index).expect("Missing image file").clone());
+            }
+            "--map" => {
+                index += 1;
+                map_path = Some(args.get(index).expect("Missing map file").clone());
+            }
+            "--base" => {
+                index += 1;
+                let value = args.get(index).expect("Missing base address");
+                base = u64::from_str_radix(value.trim_start_matches("0x"), 16).unwrap_or_else(|_| panic!("Invalid base address '{0}'", value));
+            }
+            "--entry" => {
+                index += 1;
+                entry = Some(args.get(index).expect("Missing entry point").clone());
+            }
+            "--data" => {
+                index += 1;
+                data_path = Some(args.get(index).expect("Missing data file").clone());
+            }
+            path => objects.push((path.to_string(), ObjectFile::read(path).unwrap_or_else(|error| panic!("{}", error))))
+        }
+        index += 1;
+    }
+    let output = output.unwrap_or_else(|| panic!("Usage: cpu_emu link <objects...> -o <image> [--map <file>] [--base <address>] [--entry <label|address>] [--data <file>]"));
+    if objects.is_empty() { panic!("No objects to link"); }
+
+    let (linked, map) = match link(&objects, base, entry.as_deref()) {
+        Ok(result) => result,
+        Err(errors) => {
+            for error in errors { eprintln!("LINK ERROR: {}", error); }
+            std::process::exit(1);
+        }
+    };
+    // Data words are absolute - they go in first, so the linked sections overwrite them where they clash
+    let mut image = Image { addressing: linked.addressing, entry: linked.entry, sections: vec![], symbols: linked.symbols };
+    if let Some(path) = &data_path {
+        image.add_words(SectionKind::Data, to_words(read_data(path)));
+    }
+    image.sections.extend(linked.sections);
+
+    image.write(&output);
+    if let Some(path) = &map_path {
+        std::fs::write(path, map.join("\n") + "\n").expect("Map File Error");
+    }
+    for line in image.describe() { println!("{}", line); }
+    println!(" ----- IMAGE WRITTEN TO {0} -----", output);
+}
+
+fn to_words(words : MemoryWords) -> Vec<(u64, u64)> {
+    words.into_iter().map(|(addr, word)| (Converter::bin_to_dec_pos_only(addr.to_vec()), Converter::bin_to_dec_pos_only(word.to_vec()))).collect()
+}
+
+fn add_program(image : &mut Image, program : MemoryWords, assembler : &assembler_struct) {
+    // Each word goes in the section it was assembled in - .text, unless a .data or .bss directive came before it
+    let words = to_words(program);
+    for kind in [SectionKind::Text, SectionKind::Data, SectionKind::Bss] {
+        image.add_words(kind, words.iter().filter(|(addr, _)| assembler.section_at(*addr) == kind).cloned().collect());
+    }
+}
+
+fn read_data(path : &str) -> MemoryWords {
+    // Locations and values from a data file (e.g. 'input_data.txt') - invalid lines are skipped
+    let mut data = vec![];
+    for line in BufReader::new(File::open(path).expect("Input File Error")).lines() {
         let str: String = line.unwrap();
 
         if check_comment(str.clone()) { continue; } // Skip over comment lines
@@ -85,7 +549,7 @@ fn load_memory() {
         let mut addr_hex = split_str.clone().nth(0).unwrap().to_string();
 
         if !Regex::new(r"^0x[0123456789ABCDEF]{8}$").unwrap().is_match(&*addr_hex.clone()) { continue; } // Bypass if address is invalid
-        let addr_bits : [bool; 48] = Converter::hex_val_to_bin(addr_hex[2..addr_hex.len()].parse().unwrap()).try_into().unwrap();
+        let addr_bits : [bool; 48] = Converter::set_size(Converter::hex_val_to_bin(addr_hex[2..addr_hex.len()].parse().unwrap()), 48).try_into().unwrap();
 
         let data_str = String::from(&split_str.clone().nth(1).unwrap()[2..split_str.clone().nth(1).unwrap().len()]);
         let datatype = split_str.nth(1).unwrap().chars().nth(1).unwrap();
@@ -131,78 +595,135 @@ fn load_memory() {
             _ => { continue; }
         }
 
-        // STORE AT ADDR IN RAM
-        memory.write(addr_bits, new_data);
+        data.push((addr_bits, new_data));
     };
 
-    println!(" ----- DATA LOAD COMPLETE -----");
+    data
+}
 
-    let mut assembler: assembler_struct = assembler_struct {}; // Create new Assembler Object
-    let mut line_counter: u32 = 0;
-    for mut line in BufReader::new(File::open("./recursive_fib.txt").expect("Instr File Error")).lines() {
-        let line_string= line.unwrap();
-        if line_string.clone().len() == 15 {
-            line_counter += 64; // Make sure blank lines don't affect storage in memory
-            continue;
-        } // Lines are 15 chars long by default (0x000000000000|) - Bypass in this case
-        let mut offset = 15;
-        while 1==1 {
-            if line_string.clone().chars().nth(offset).unwrap() != ' ' {
-                break;
-            }
-            else{
-                offset += 1;
+fn assemble_program(path : &str, word_size : u64, relocatable : bool) -> (MemoryWords, assembler_struct, Vec<String>) {
+    // Assembles every instruction and data directive in a program file - returns the words, the assembler with its labels and relocations, and a listing
+    // Labels imported with .extern are only allowed if the program is relocatable, to be resolved by the linker
+    let mut words : BTreeMap<u64, u64> = BTreeMap::new(); // Word address -> value, as data directives fill words a few bytes at a time
+    let program = read_program(path, word_size);
+    let mut assembler: assembler_struct = assembler_struct::default(); // Create new Assembler Object
+    for (addr, source) in program.iter().filter(|(_, source)| source.placed) {
+        // First pass - record label addresses and directives, so labels can be used before they are defined
+        let (label, rest) = assembler_struct::split_label(&source.text);
+        if let Some(label) = label {
+            assembler.symbols.insert(label, Converter::dec_to_bin_pos_only(*addr, 48).try_into().unwrap());
+        }
+        assembler.location = *addr;
+        if rest.starts_with('.') && !matches!(assembler_struct::parse_data(&rest), Ok(Some(_))) && !assembler.directive(&rest) { panic!("Unknown directive '{0}'", rest); }
+    }
+    if !relocatable && let Some(label) = assembler.externs.first() {
+        panic!("'{0}' is declared .extern - assemble with --object and link the program", label);
+    }
+    let mut listing = vec![format!("{0:<12}  {1:<16}  {2:>5}  SOURCE", "ADDRESS", "WORD", "LINE")];
+    for (addr, source) in program {
+        let marker = "+".repeat(source.depth); // One for each level of macro expansion
+        let line_unwrap = assembler_struct::split_label(&source.text).1;
+        let in_bss = source.placed && assembler.section_at(addr) == SectionKind::Bss;
+        if source.placed && let Ok(Some(DataDirective::Bytes(_, bytes))) = assembler_struct::parse_data(&line_unwrap) {
+            if in_bss && bytes.iter().any(|byte| *byte != 0) { panic!("Line {0}: .bss can only hold zeros - use .space or .zero", source.line); }
+            let position = addr * 8 / word_size; // In bytes
+            for (index, byte) in bytes.iter().enumerate() {
+                let byte_position = position + index as u64;
+                *words.entry(byte_position / 8 * word_size).or_insert(0) |= (*byte as u64) << (byte_position % 8 * 8);
             }
+            let shown : String = bytes.iter().take(8).map(|byte| format!("{:02X}", byte)).collect(); // Bytes in address order
+            listing.push(format!("{0:012X}  {1:<16}  {2:>5}{3:<2} {4}", addr, shown, source.line, marker, source.text));
+            continue;
         }
-        let line_unwrap= line_string.clone()[offset..line_string.len()].to_string();
-        if check_comment(line_unwrap.clone()) {
-            line_counter += 64; // Make sure comments don't affect storage in memory
+        if !source.placed || line_unwrap.is_empty() || line_unwrap.starts_with('.') || check_comment(line_unwrap.clone()) { // Bypass comments, directives, and lines holding only a label
+            let location = if source.placed { format!("{:012X}", addr) } else { String::new() };
+            listing.push(format!("{0:<12}  {1:<16}  {2:>5}{3:<2} {4}", location, "", source.line, marker, source.text));
             continue;
-        } // Bypass comments
-        let assembled: [bool; 64] = assembler.assemble(line_unwrap); // Assemble line
-        let new : [bool; 48] = Converter::dec_to_bin_pos_only(line_counter as u64, 48).try_into().unwrap();
-        memory.write(new, assembled);
-        line_counter += 64; // RAM words are referenced in 64-bit words
+        }
+        if in_bss { panic!("Line {0}: .bss can only hold zeros - use .space or .zero", source.line); }
+        assembler.location = addr;
+        let assembled = Converter::bin_to_dec_pos_only(assembler.assemble(line_unwrap).to_vec()); // Assemble line
+        listing.push(format!("{0:012X}  {1:016X}  {2:>5}{3:<2} {4}", addr, assembled, source.line, marker, source.text));
+        words.insert(addr, assembled);
     };
 
-    println!(" ----- INSTRUCTION LOAD COMPLETE -----");
-
-    let mut cpu_cu: control_unit_struct = control_unit_struct {
-        alu: alu_struct::default(), memory_instr_reg: reg64_struct::default(),
-        memory_instr_stall: false, memory_data_reg: reg64_struct::default(), memory_data_stall: false,
-        pc: reg64_struct::default(), register_bank: reg_bank_struct::default(), halt: false,
-        state: CpuState::Fetch, decoded_instruction: ParsedInstruction::default(),
-        data_access_manager: DataAccessManager{
-            l1_cache: L1Cache::default(), l2_cache: L2Cache::default(), main_memory: memory
-        } // Set up a default CPU
-    };
+    let words = words.into_iter().map(|(addr, word)| {
+        (Converter::dec_to_bin_pos_only(addr, 48).try_into().unwrap(), Converter::dec_to_bin_pos_only(word, 64).try_into().unwrap())
+    }).collect();
+    (words, assembler, listing)
+}
 
-    let mut clk: clock_struct = clock_struct{
-        clock_speed : 100,
-        running : false,
-        ctrl : cpu_cu,
-        cycle_count : 0
-    }; // Create a default clock, with the new CPU
-    clk.start(); // Start the clock
+fn read_program(path : &str, word_size : u64) -> Vec<(u64, SourceLine)> {
+    // Returns each line of the instruction file after macro expansion, with the address it is stored at
+    // Every line takes up a word, starting on a word boundary, apart from data directives, which take up as many bytes as they hold
+    let mut lines = vec![];
+    for line in BufReader::new(File::open(path).expect("Instr File Error")).lines() {
+        let line_string = line.unwrap();
+        // Lines are 15 chars long by default (0x000000000000|) - Blank lines and comments still take up a word
+        lines.push(if line_string.len() > 15 { line_string[15..line_string.len()].trim_start().to_string() } else { String::new() });
+    }
+    let expanded = MacroExpander::default().expand(lines).unwrap_or_else(|error| panic!("{}", error));
+
+    let mut program = vec![];
+    let mut position: u64 = 0; // In bytes
+    let address = |position : u64| position * word_size / 8;
+    for source in expanded {
+        if !source.placed {
+            program.push((address(position), source));
+            continue;
+        }
+        let directive = assembler_struct::parse_data(&assembler_struct::split_label(&source.text).1)
+            .unwrap_or_else(|error| panic!("Line {0}: {1}", source.line, error));
+        match directive {
+            Some(DataDirective::Bytes(alignment, bytes)) => {
+                position = position.next_multiple_of(alignment);
+                program.push((address(position), source));
+                position += bytes.len() as u64;
+            }
+            Some(DataDirective::Align(alignment)) => {
+                position = position.next_multiple_of(alignment);
+                program.push((address(position), source));
+            }
+            Some(DataDirective::Org(addr)) => {
+                if addr % (word_size / 8) != 0 { panic!("Line {0}: .org {1:X} is not the address of a byte", source.line, addr); }
+                if addr * 8 / word_size < position { panic!("Line {0}: .org {1:X} is behind the current address {2:X}", source.line, addr, address(position)); }
+                position = addr * 8 / word_size;
+                program.push((addr, source));
+            }
+            None => {
+                position = position.next_multiple_of(8);
+                program.push((address(position), source));
+                position += 8;
+            }
+        }
+    }
+    program
+}
 
-    println!("----- END -----");
+fn map_devices(memory : &mut MainMemory, console_input : &ConsoleInput) {
+    // Memory-mapped devices - these addresses bypass the caches
+    let timer_base : [bool; 48] = Converter::hex_val_to_bin("FFFFFFFF0000".to_string()).try_into().unwrap();
+    memory.address_decoder.map(timer_base, 0x100, false, Box::new(Timer::default()));
+    let input_base : [bool; 48] = Converter::hex_val_to_bin("FFFFFFFF0100".to_string()).try_into().unwrap();
+    memory.address_decoder.map(input_base, 0x100, false, Box::new(console_input.clone()));
 }
 
 /*
- * THE FOLLOWING SECTION IS IN DEVELOPMENT -- Building C++ GUI in Unreal Engine, with Unix pipe inter-process communication
+ * GUI LINK -- Messages to and from an external front end (C++ GUI in Unreal Engine), over a Unix domain socket
+ * The message grammar is described in gui_pipe.rs
  */
 
 
 pub fn send_reg(reg : u8, val : String){
-    send_pipe_data("REG//".to_string() + &reg.to_string() + "//" + &val);
+    update_pipe_data("REG//".to_string() + &reg.to_string() + "//" + &val);
 }
 
 pub fn send_state(state : CpuState){
-    send_pipe_data("STATE//".to_string() + &(state as u8).to_string());
+    update_pipe_data("STATE//".to_string() + &(state as u8).to_string());
 }
 
 pub fn send_instr(current_instr: String, incr_instr: String){
-    send_pipe_data("INSTR//".to_string() + &current_instr + "//" + &incr_instr);
+    update_pipe_data("INSTR//".to_string() + &current_instr + "//" + &incr_instr);
 }
 
 pub fn send_memory(memory_addr : String, memory_data : String){
@@ -210,17 +731,163 @@ pub fn send_memory(memory_addr : String, memory_data : String){
 }
 
 pub fn send_l1_util(util : String){
-    send_pipe_data("L1_UTIL//".to_string() + &util);
+    update_pipe_data("L1_UTIL//".to_string() + &util);
 }
 
 pub fn send_l2_util(util : String){
-    send_pipe_data("L2_UTIL//".to_string() + &util);
+    update_pipe_data("L2_UTIL//".to_string() + &util);
+}
+
+pub fn send_cycle(cycle : u64){
+    update_pipe_data("CYCLE//".to_string() + &cycle.to_string());
+}
+
+pub fn send_paused(paused : bool){
+    update_pipe_data("PAUSED//".to_string() + if paused { "1" } else { "0" });
+}
+
+pub fn send_speed(hz : f64){
+    update_pipe_data(format!("SPEED//{0:.3}", hz));
+}
+
+pub fn send_halt(){
+    send_pipe_data("HALT".to_string());
 }
 
 pub fn send_pipe_data(val : String){
+    gui_link::send(val);
+}
+
+pub fn update_pipe_data(val : String){
+    // Only sent if different to the last message of the same type
+    gui_link::send_on_change(val);
 }
 
 pub fn read_pipe() -> String{
-    let data : String = "".to_string();
-    data
-}
\ No newline at end of file
+    gui_link::receive()
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(crate) fn load_program(path : &str, mut options : RunOptions) -> (clock_struct, BufferSink) {
+        // Assembles a program file into an image and builds the machine for it - returns the machine and the sink OUT writes to
+        let (program, assembler, _) = assemble_program(path, options.addressing.word_size(), false);
+        let mut image = Image { addressing: options.addressing, entry: 0, sections: vec![], symbols: vec![] };
+        add_program(&mut image, program, &assembler);
+        let image_path = std::env::temp_dir().join(format!("cpu_emu_{0}_{1}.img", std::process::id(), path.replace(['/', '.'], "_")));
+        image.write(image_path.to_str().unwrap());
+        options.image = Some(image_path.to_str().unwrap().to_string());
+
+        let output = BufferSink::default();
+        let (clk, _) = build_machine(&mut options, Box::new(output.clone()));
+        std::fs::remove_file(image_path).ok();
+        (clk, output)
+    }
+
+    pub(crate) fn run_program(path : &str, options : RunOptions) -> (clock_struct, String) {
+        // Runs a program file to the end - returns the machine and everything written by OUT
+        let (mut clk, output) = load_program(path, options);
+        clk.start();
+        (clk, output.contents())
+    }
+
+    pub(crate) fn program_file(name : &str, lines : &[&str]) -> String {
+        // Writes the lines to a temporary program file, one word apart - returns its path
+        let path = std::env::temp_dir().join(format!("cpu_emu_{0}_{1}.txt", std::process::id(), name));
+        let text : String = lines.iter().enumerate().map(|(index, line)| format!("0x{0:012X}|{1}\n", index * 64, line)).collect();
+        std::fs::write(&path, text).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn program_image(name : &str, lines : &[&str]) -> Image {
+        let path = program_file(name, lines);
+        let (program, assembler, _) = assemble_program(&path, Addressing::Word.word_size(), false);
+        std::fs::remove_file(&path).ok();
+        let mut image = Image { addressing: Addressing::Word, entry: 0, sections: vec![], symbols: vec![] };
+        add_program(&mut image, program, &assembler);
+        image
+    }
+
+    #[test]
+    fn section_directives() {
+        let image = program_image("sections", &["ADD R1, #0, #1", "HLT", ".data", "value: .dword 5", ".bss", "buffer: .zero 16", ".text", "HLT"]);
+        let sections : Vec<(SectionKind, u64, u64, Vec<u64>)> = image.sections.iter().map(|section| (section.kind, section.addr, section.size, section.words.clone())).collect();
+        assert!(sections[0].0 == SectionKind::Text && sections[0].1 == 0x0 && sections[0].2 == 2);
+        assert!(sections[1].0 == SectionKind::Text && sections[1].1 == 0x200 && sections[1].2 == 1);
+        assert!(sections[2].0 == SectionKind::Data && sections[2].1 == 0xC0 && sections[2].3 == vec![5]);
+        assert!(sections[3].0 == SectionKind::Bss && sections[3].1 == 0x140 && sections[3].2 == 2 && sections[3].3.is_empty());
+        assert_eq!(sections.len(), 4);
+        assert_eq!(Image::from_bytes(&image.to_bytes()).unwrap().sections.len(), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = ".bss can only hold zeros")]
+    fn bss_rejects_values() {
+        program_image("bss_values", &[".bss", ".word 1"]);
+    }
+
+    #[test]
+    fn load_label_address() {
+        let path = program_file("li_label", &["LI R1, &value", "OUT R R1", "HLT", ".org 000123400000", "value: .dword 9"]);
+        let (_, output) = run_program(&path, RunOptions::default());
+        std::fs::remove_file(&path).ok();
+        assert_eq!(output.trim(), format!("R1 OUTPUT: {0}", 0x123400000u64));
+    }
+
+    fn program_words(name : &str, lines : &[&str], addressing : Addressing) -> (Vec<u64>, HashMap<u64, u64>) {
+        // The address of each line, and the words assembled from them
+        let path = program_file(name, lines);
+        let addrs = read_program(&path, addressing.word_size()).into_iter().filter(|(_, source)| source.placed).map(|(addr, _)| addr).collect();
+        let (program, _, _) = assemble_program(&path, addressing.word_size(), false);
+        std::fs::remove_file(&path).ok();
+        (addrs, to_words(program).into_iter().collect())
+    }
+
+    #[test]
+    fn string_directives() {
+        let (addrs, words) = program_words("ascii", &["HLT", "msg: .ascii \"Hi\\n\", \"A\"", ".asciz \"ok\", \"\"", "HLT"], Addressing::Byte);
+        assert_eq!(addrs, [0x0, 0x8, 0xC, 0x10]); // Strings are byte aligned, and the next instruction starts on the next word
+        assert_eq!(words[&0x8].to_le_bytes(), *b"Hi\nAok\0\0");
+        assert!(words.contains_key(&0x10) && !words.contains_key(&0x18));
+    }
+
+    #[test]
+    fn align_directive() {
+        let (addrs, words) = program_words("align", &["HLT", ".ascii \"x\"", ".align 4", ".word 7", ".align 32", ".dword 1", ".align 1", ".space 3, 0xEE"], Addressing::Byte);
+        assert_eq!(addrs, [0x0, 0x8, 0xC, 0xC, 0x20, 0x20, 0x28, 0x28]); // .align is listed at the address it moves to
+        assert_eq!(words[&0x8], 0x0000_0007_0000_0078); // Padded with zeros
+        assert_eq!((words.get(&0x10), words.get(&0x18), words[&0x20], words[&0x28]), (None, None, 1, 0xEEEEEE));
+        // Word addressing - addresses are 8 times larger, but alignment is still in bytes
+        let (addrs, _) = program_words("align_word", &["HLT", ".ascii \"x\"", ".align 4", ".word 7"], Addressing::Word);
+        assert_eq!(addrs, [0x0, 0x40, 0x60, 0x60]);
+    }
+
+    #[test]
+    fn word_directive() {
+        let (addrs, words) = program_words("word", &[".word 1, -2, 0x7F", "HLT", ".word 0xFFFFFFFF", ".word -2147483648"], Addressing::Byte);
+        assert_eq!(addrs, [0x0, 0x10, 0x18, 0x1C]); // 12 bytes, then the instruction on the next word
+        assert_eq!((words[&0x0], words[&0x8]), (0xFFFF_FFFE_0000_0001, 0x7F));
+        assert_eq!(words[&0x18], 0x8000_0000_FFFF_FFFF);
+        assert_eq!(assembler_struct::parse_data(".word 0x100000000").err().unwrap(), "0x100000000 doesn't fit in 4 bytes");
+        assert_eq!(assembler_struct::parse_data(".word -2147483649").err().unwrap(), "-2147483649 doesn't fit in 4 bytes");
+    }
+
+    #[test]
+    #[should_panic(expected = "Line 2: .org 801 is not the address of a byte")]
+    fn org_between_bytes() {
+        program_words("org_between_bytes", &["HLT", ".org 000000000801"], Addressing::Word);
+    }
+
+    #[test]
+    #[should_panic(expected = "Line 3: .org 40 is behind the current address 80")]
+    fn org_backwards() {
+        program_words("org_backwards", &["HLT", "HLT", ".org 000000000040"], Addressing::Word);
+    }
+
+    #[test]
+    fn hello_world_output() {
+        let (_, output) = run_program("hello_world.txt", RunOptions::default());
+        assert_eq!(output, "Hello World!\nd");
+    }
+}
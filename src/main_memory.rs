@@ -3,26 +3,112 @@ pub(crate) mod main_memory {
     use std::collections::HashMap;
     use crate::buses::buses::{AddressBus, ControlBus, DataBus};
     use crate::converter::converter::Converter;
+    use crate::io_bus::io_bus::AddressDecoder;
+    use crate::dram_controller::dram_controller::{DramController, ROW_WORDS};
+    use crate::ecc::ecc::{self, EccResult, EccStats};
+    use crate::send_memory;
+
+    #[repr(u8)]
+    #[derive(Clone, Copy, PartialEq)]
+    pub(crate) enum Addressing {
+        Word = 0, // Compatibility mode - an address for every bit, so 64-bit words are 0x40 apart
+        Byte = 1  // An address for every byte, so 64-bit words are 8 apart
+    }
+
+    impl Addressing {
+        pub fn parse(name : &str) -> Option<Addressing> {
+            match name {
+                "word" => Some(Addressing::Word),
+                "byte" => Some(Addressing::Byte),
+                _ => None
+            }
+        }
+
+        pub fn from_code(code : u8) -> Result<Addressing, String> {
+            // As stored in images and object files
+            match code {
+                0 => Ok(Addressing::Word),
+                1 => Ok(Addressing::Byte),
+                other => Err(format!("Unknown addressing mode {0}", other))
+            }
+        }
+
+        pub fn word_size(&self) -> u64 {
+            // Address units per 64-bit word
+            match self {
+                Addressing::Word => 64,
+                Addressing::Byte => 8
+            }
+        }
+
+        pub fn word_start(&self, addr : [bool; 48]) -> [bool; 48] {
+            // Address of the word holding addr
+            let addr_dec = Converter::bin_to_dec_pos_only(addr.to_vec());
+            Converter::dec_to_bin_pos_only(addr_dec - addr_dec % self.word_size(), 48).try_into().unwrap()
+        }
+
+        fn bit_offset(&self, addr : [bool; 48]) -> usize {
+            // Position of addr within its word - words are little-endian, so the lowest address holds the least significant byte
+            let addr_dec = Converter::bin_to_dec_pos_only(addr.to_vec());
+            ((addr_dec % self.word_size()) * (64 / self.word_size())) as usize
+        }
+
+        pub fn aligned(&self, addr : [bool; 48], size : u8) -> bool {
+            // An access of 'size' bytes must start at a multiple of its size
+            self.bit_offset(addr).is_multiple_of(size as usize * 8)
+        }
+
+        pub fn extract(&self, word : [bool; 64], addr : [bool; 48], size : u8, signed : bool) -> [bool; 64] {
+            // The 'size' byte value at addr, zero or sign extended to 64 bits
+            let offset = self.bit_offset(addr);
+            let bits = size as usize * 8;
+            let mut value = [false; 64];
+            value[0..bits].copy_from_slice(&word[offset..offset + bits]);
+            if signed && value[bits - 1] { value[bits..64].fill(true); }
+            value
+        }
+
+        pub fn insert(&self, word : [bool; 64], addr : [bool; 48], size : u8, value : [bool; 64]) -> [bool; 64] {
+            // The word with the 'size' bytes at addr replaced by the low bytes of value
+            let offset = self.bit_offset(addr);
+            let bits = size as usize * 8;
+            let mut merged = word;
+            merged[offset..offset + bits].copy_from_slice(&value[0..bits]);
+            merged
+        }
+    }
 
     // A HashMap is simply used to reduce to RAM requirements of having a full memory array stored
     // Stores values in individual 'DRAM' chips, rather than as just booleans
+    // Bus transactions pass through the address decoder first, so memory-mapped devices can claim them
+    // Transactions for DRAM are timed by the DRAM controller
+    // With ECC on, every word has 8 check bits alongside it, checked on every read from the bus
+    // Storage is always whole 64-bit words, keyed by their start address - narrower loads and stores are made by the CPU
     pub(crate) struct MainMemory {
         pub(crate) ram_map: HashMap<[bool; 48], [DRAM; 64]>,
         pub(crate) data_bus: DataBus,
         pub(crate) address_bus: AddressBus,
-        pub(crate) control_bus: ControlBus
+        pub(crate) control_bus: ControlBus,
+        pub(crate) address_decoder: AddressDecoder,
+        pub(crate) addressing: Addressing,
+        pub(crate) dram: DramController,
+        pub(crate) ecc: bool,
+        pub(crate) check_bits: HashMap<[bool; 48], u8>, // Only kept with ECC on
+        pub(crate) ecc_stats: EccStats,
+        pub(crate) changed_words: Vec<(u64, Option<u64>, Option<u8>)> // Words changed this cycle other than by a store - address, old value and check bits - for the execution history
     }
 
     impl MainMemory {
 
-        pub fn get_valid_start(loc : [bool; 48]) -> [bool; 48] {
-            // Moves addresses to the valid 64-bit word start
-            if Converter::bin_to_dec_pos_only(loc.to_vec()) % 64 == 0 {
-                loc
-            } else {
-                let round_up = (Converter::bin_to_dec_pos_only(loc.to_vec()) as f64 / 64.0).ceil() as u64;
-                Converter::dec_to_bin_pos_only(round_up, 48).try_into().unwrap()
-            }
+        pub fn set_addressing(&mut self, addressing : Addressing) {
+            // Devices see the same register offsets in either mode
+            self.addressing = addressing;
+            self.address_decoder.addressing = addressing;
+        }
+
+        pub fn get_valid_start(&self, loc : [bool; 48]) -> [bool; 48] {
+            // Moves addresses to the start of the 64-bit word holding them
+            self.addressing.word_start(loc)
         }
 
         pub fn write(&mut self, loc : [bool; 48], val : [bool; 64]) {
@@ -32,12 +118,53 @@ pub(crate) mod main_memory {
                     dram_write[i].charge();
                 }
             }
-            self.ram_map.insert(MainMemory::get_valid_start(loc), dram_write);
+            self.ram_map.insert(self.get_valid_start(loc), dram_write);
+            if self.ecc{
+                self.check_bits.insert(self.get_valid_start(loc), ecc::encode(Converter::bin_to_dec_pos_only(val.to_vec())));
+            }
+        }
+
+        pub fn flip_bits(&mut self, loc : [bool; 48], mask : u64) {
+            // Fault injection - the check bits are left as they were
+            let key = self.get_valid_start(loc);
+            let check = self.check_bits.get(&key).cloned();
+            let value = Converter::bin_to_dec_pos_only(self.read(key).to_vec()) ^ mask;
+            self.write(key, Converter::dec_to_bin_pos_only(value, 64).try_into().unwrap());
+            match check {
+                Some(check) => { self.check_bits.insert(key, check); }
+                None => { self.check_bits.remove(&key); }
+            }
+        }
+
+        fn read_checked(&mut self, loc : [bool; 48]) -> Option<[bool; 64]> {
+            // Bus read through ECC - single-bit errors are corrected in memory, None for an uncorrectable error
+            let data = self.read(loc);
+            if !self.ecc{ return Some(data); }
+            let key = self.get_valid_start(loc);
+            let value = Converter::bin_to_dec_pos_only(data.to_vec());
+            let stored = self.check_bits.get(&key).cloned();
+            match ecc::check(value, stored.unwrap_or(0)) { // Words never written are all zero, which has zero check bits
+                EccResult::Clean => Some(data),
+                EccResult::Corrected(corrected, check) => {
+                    self.ecc_stats.corrected += 1;
+                    eprintln!("ECC: corrected a single-bit error at {:012X}", Converter::bin_to_dec_pos_only(key.to_vec()));
+                    self.changed_words.push((Converter::bin_to_dec_pos_only(key.to_vec()), self.ram_map.contains_key(&key).then_some(value), stored));
+                    let corrected_bits : [bool; 64] = Converter::dec_to_bin_pos_only(corrected, 64).try_into().unwrap();
+                    self.write(key, corrected_bits); // Scrub
+                    self.check_bits.insert(key, check);
+                    Some(corrected_bits)
+                }
+                EccResult::Uncorrectable => {
+                    self.ecc_stats.uncorrectable += 1;
+                    eprintln!("ECC: uncorrectable error at {:012X} - machine check", Converter::bin_to_dec_pos_only(key.to_vec()));
+                    None
+                }
+            }
         }
 
         pub fn read(&mut self, loc : [bool; 48]) -> [bool; 64] {
             let mut return_bits = [false; 64];
-            let dram_read = self.ram_map.get(&MainMemory::get_valid_start(loc)).cloned().unwrap_or([DRAM::default(); 64]).clone();
+            let dram_read = self.ram_map.get(&self.get_valid_start(loc)).cloned().unwrap_or([DRAM::default(); 64]).clone();
             for i in 0..dram_read.len() {
                 if dram_read[i].charge{
                     return_bits[i] = true;
@@ -49,24 +176,136 @@ pub(crate) mod main_memory {
 
         pub fn clear(&mut self) {
             self.ram_map.clear();
+            self.check_bits.clear();
         }
 
-        pub fn tick(&mut self){ // Controls interaction with buses
+        pub fn tick(&mut self, cycle : u64){ // Controls interaction with buses - cycle is the memory clock cycle number
             while self.control_bus.lock {}
             self.control_bus.lock = true;
-            if self.control_bus.ready_memory{
-                self.control_bus.ready_memory = false;
-                if self.control_bus.str{
-                    self.write(self.address_bus.bits, self.data_bus.bits);
-                    self.control_bus.str = false;
+            if self.dram.state.busy > 0{
+                // Access in progress
+                self.dram.state.busy -= 1;
+                if self.dram.state.busy == 0{ self.complete(); }
+            }
+            else if self.control_bus.memory_sees_request(){
+                if !self.dram.timing.enabled || self.address_decoder.claims(self.address_bus.bits){
+                    self.complete();
+                }
+                else if self.dram.refreshing(cycle){
+                    self.dram.state.stats.refresh_stalls += 1; // Wait for the refresh to finish
                 }
                 else{
-                    self.data_bus.bits = self.read(self.address_bus.bits);
-                    self.control_bus.ready_cpu = true;
+                    let word = Converter::bin_to_dec_pos_only(self.address_bus.bits.to_vec()) / self.addressing.word_size();
+                    let (latency, decayed) = self.dram.activate(word, cycle);
+                    if decayed{ self.lose_row(word); }
+                    if latency == 0{ self.complete(); }
+                    else{ self.dram.state.busy = latency; }
                 }
             }
             self.control_bus.lock = false;
         }
+
+        fn complete(&mut self){
+            // Carry out the transaction on the buses
+            self.control_bus.ready_memory = false;
+            if self.control_bus.str{
+                if !self.address_decoder.write(self.address_bus.bits, self.data_bus.bits){
+                    self.write(self.address_bus.bits, self.data_bus.bits);
+                }
+                self.control_bus.str = false;
+                send_memory(format!("{:012X}", Converter::bin_to_dec_pos_only(self.address_bus.bits.to_vec())), format!("{:016X}", Converter::bin_to_dec_pos_only(self.data_bus.bits.to_vec())));
+            }
+            else{
+                self.data_bus.bits = match self.address_decoder.read(self.address_bus.bits) {
+                    Some(device_data) => device_data,
+                    None => match self.read_checked(self.address_bus.bits) {
+                        Some(data) => data,
+                        None => {
+                            self.control_bus.error = true;
+                            self.read(self.address_bus.bits)
+                        }
+                    }
+                };
+                self.control_bus.respond();
+            }
+        }
+
+        fn lose_row(&mut self, word : u64){
+            // A decayed row - every word in it reads as 0 from now on
+            let first_word = word - word % ROW_WORDS;
+            for row_word in first_word..first_word + ROW_WORDS {
+                let addr = row_word * self.addressing.word_size();
+                let key : [bool; 48] = Converter::dec_to_bin_pos_only(addr, 48).try_into().unwrap();
+                if self.ram_map.contains_key(&key){
+                    let old_value = Converter::bin_to_dec_pos_only(self.read(key).to_vec());
+                    self.ram_map.remove(&key);
+                    let old_check = self.check_bits.remove(&key);
+                    self.changed_words.push((addr, Some(old_value), old_check));
+                }
+            }
+        }
     }
-}
 
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const WORD : u64 = 0xF1E2_D3C4_B5A6_9788;
+
+        fn bits(value : u64) -> [bool; 64] { Converter::dec_to_bin_pos_only(value, 64).try_into().unwrap() }
+        fn value(bits : [bool; 64]) -> u64 { Converter::bin_to_dec_pos_only(bits.to_vec()) }
+
+        fn addr(addressing : Addressing, byte : u64) -> [bool; 48] {
+            // Byte 'byte' of the word at 0x800
+            Converter::dec_to_bin_pos_only(0x800 + byte * addressing.word_size() / 8, 48).try_into().unwrap()
+        }
+
+        #[test]
+        fn extract_little_endian() {
+            let bytes = WORD.to_le_bytes();
+            for addressing in [Addressing::Word, Addressing::Byte] {
+                for size in [1u8, 2, 4, 8] {
+                    for start in (0..8).step_by(size as usize) {
+                        let mut le = [0u8; 8];
+                        le[..size as usize].copy_from_slice(&bytes[start..start + size as usize]);
+                        let unsigned = u64::from_le_bytes(le);
+                        let signed = match size { 1 => unsigned as i8 as u64, 2 => unsigned as i16 as u64, 4 => unsigned as i32 as u64, _ => unsigned };
+                        let at = addr(addressing, start as u64);
+                        assert!(addressing.aligned(at, size));
+                        assert_eq!(value(addressing.extract(bits(WORD), at, size, false)), unsigned, "{0} bytes at {1}", size, start);
+                        assert_eq!(value(addressing.extract(bits(WORD), at, size, true)), signed, "{0} bytes at {1}, signed", size, start);
+                    }
+                }
+            }
+            // A positive value isn't sign extended
+            assert_eq!(value(Addressing::Byte.extract(bits(0x7F), addr(Addressing::Byte, 0), 1, true)), 0x7F);
+        }
+
+        #[test]
+        fn insert_little_endian() {
+            for addressing in [Addressing::Word, Addressing::Byte] {
+                for size in [1u8, 2, 4, 8] {
+                    for start in (0..8).step_by(size as usize) {
+                        let mut expected = WORD.to_le_bytes();
+                        expected[start..start + size as usize].copy_from_slice(&0x0123_4567_89AB_CDEFu64.to_le_bytes()[..size as usize]);
+                        let merged = addressing.insert(bits(WORD), addr(addressing, start as u64), size, bits(0x0123_4567_89AB_CDEF));
+                        assert_eq!(value(merged), u64::from_le_bytes(expected), "{0} bytes at {1}", size, start);
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn alignment() {
+            for addressing in [Addressing::Word, Addressing::Byte] {
+                for size in [1u8, 2, 4, 8] {
+                    for start in 0..8 {
+                        assert_eq!(addressing.aligned(addr(addressing, start), size), start % size as u64 == 0, "{0} bytes at {1}", size, start);
+                    }
+                }
+            }
+            // Word addressing - an address between bytes isn't aligned for any size
+            assert!(!Addressing::Word.aligned(Converter::dec_to_bin_pos_only(0x801, 48).try_into().unwrap(), 1));
+        }
+    }
+}
@@ -23,6 +23,7 @@ pub(crate) mod memory_chips {
         pub fn read(&mut self) -> bool {
             /*
             Having to refresh the charge on the DRAM chips, like in reality, was simply too slow to run efficiently
+            Refresh, and decay of rows which miss it, are modelled per row by the DRAM controller instead - see dram_controller.rs
             if (self.refresh_timer < Self::current_time()) {
                 self.charge = false;
                 return false;
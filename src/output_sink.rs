@@ -0,0 +1,59 @@
+pub(crate) mod output_sink{
+    use std::fs::File;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    pub(crate) trait OutputSink {
+        // Destination for everything written by the OUT instruction
+        fn write_str(&mut self, text : &str);
+        fn flush(&mut self){}
+    }
+
+    pub(crate) struct StdoutSink {}
+    impl OutputSink for StdoutSink {
+        fn write_str(&mut self, text : &str){
+            print!("{}", text);
+        }
+
+        fn flush(&mut self){
+            std::io::stdout().flush().expect("Error Writing Output");
+        }
+    }
+
+    pub(crate) struct FileSink {
+        file : File
+    }
+    impl FileSink {
+        pub fn create(path : &str) -> FileSink {
+            FileSink { file: File::create(path).expect("Output File Error") }
+        }
+    }
+    impl OutputSink for FileSink {
+        fn write_str(&mut self, text : &str){
+            self.file.write_all(text.as_bytes()).expect("Error Writing Output");
+        }
+
+        fn flush(&mut self){
+            self.file.flush().expect("Error Writing Output");
+        }
+    }
+
+    #[derive(Clone)]
+    pub(crate) struct BufferSink {
+        // Keeps output in memory - clones share the same buffer, so a copy can be kept to read it back
+        buffer : Arc<Mutex<String>>
+    }
+    impl BufferSink {
+        pub fn contents(&self) -> String {
+            self.buffer.lock().unwrap().clone()
+        }
+    }
+    impl OutputSink for BufferSink {
+        fn write_str(&mut self, text : &str){
+            self.buffer.lock().unwrap().push_str(text);
+        }
+    }
+    impl Default for BufferSink {
+        fn default() -> Self { BufferSink { buffer: Arc::new(Mutex::new(String::new())) } }
+    }
+}
@@ -0,0 +1,213 @@
+pub(crate) mod simd{
+    /*
+     * SIMD unit - 16 vector registers, V0-V15, each a 64-bit word packed with 8 8-bit, 4 16-bit, 2 32-bit or 1 64-bit lane
+     *
+     * Lane-wise addition and subtraction go through the same gate-level adder/subtractor as the ALU, with its carry chain
+     * cut at every lane boundary - so a single pass works on all the lanes at once. Multiplication is shift-and-add over
+     * the same split adder, keeping the low half of each lane's product
+     * Saturating variants clamp each lane instead of wrapping - signed ones to the lane's most positive or negative value
+     * on signed overflow, unsigned ones to all ones on a carry out or to zero on a borrow
+     * AND, OR and XOR don't cross bits, so they are the same for every lane size
+     */
+    use crate::adders::adders::AddSub64bit;
+    use crate::assembler::assembler::InstrType;
+    use crate::bitwise_operator::bitwise_operator::BitwiseOperator;
+    use crate::converter::converter::Converter;
+    use crate::reg64::reg64::Reg64;
+
+    #[derive(Clone, Copy, PartialEq)]
+    pub(crate) enum Saturation {
+        Wrap,
+        Signed,
+        Unsigned
+    }
+
+    #[derive(Default)]
+    pub(crate) struct Simd {
+        adder_subtractor_64bit : AddSub64bit,
+        bitwise_operator : BitwiseOperator,
+        pub(crate) registers : [Reg64; 16]
+    }
+
+    impl Simd {
+
+        pub fn get_data(&self, index : [bool; 4]) -> [bool; 64] {
+            self.registers[Converter::bin_to_dec_pos_only(index.to_vec()) as usize].get_data()
+        }
+
+        pub fn set_data(&mut self, index : [bool; 4], data : [bool; 64]){
+            self.registers[Converter::bin_to_dec_pos_only(index.to_vec()) as usize].set_data(data);
+        }
+
+        pub fn add_sub(&self, val0 : [bool; 64], val1 : [bool; 64], lane_bytes : u8, add : bool, saturation : Saturation) -> [bool; 64] {
+            let lane_bits = lane_bytes as usize * 8;
+            let (mut result, carries) = self.adder_subtractor_64bit.value_lanes(val0, val1, lane_bits, add);
+            for low in (0..64).step_by(lane_bits) {
+                let top = low + lane_bits - 1;
+                match saturation {
+                    Saturation::Wrap => {}
+                    Saturation::Signed => {
+                        // Overflowed if the operands' signs match (addition) or differ (subtraction), and the result's doesn't match the first's
+                        if (val0[top] == val1[top]) == add && result[top] != val0[top] {
+                            result[low..top].fill(!val0[top]);
+                            result[top] = val0[top];
+                        }
+                    }
+                    Saturation::Unsigned => {
+                        if carries[top / 8] {
+                            result[low..=top].fill(add);
+                        }
+                    }
+                }
+            }
+            result
+        }
+
+        pub fn mul(&self, val0 : [bool; 64], val1 : [bool; 64], lane_bytes : u8) -> [bool; 64] {
+            // One pass of the split adder per multiplier bit, adding in each lane's multiplicand shifted up where that bit is set
+            let lane_bits = lane_bytes as usize * 8;
+            let mut product = [false; 64];
+            for shift in 0..lane_bits {
+                let mut partial = [false; 64];
+                for low in (0..64).step_by(lane_bits) {
+                    if val1[low + shift] {
+                        partial[low + shift..low + lane_bits].copy_from_slice(&val0[low..low + lane_bits - shift]);
+                    }
+                }
+                product = self.adder_subtractor_64bit.value_lanes(product, partial, lane_bits, true).0;
+            }
+            product
+        }
+
+        pub fn bitwise(&self, val0 : [bool; 64], val1 : [bool; 64], operation : InstrType) -> [bool; 64] {
+            // VAND, VOR and VXOR - operation is the scalar AND, OR or XOR they match
+            self.bitwise_operator.value(val0, val1, Converter::dec_to_bin_pos_only(operation as u64, 4).try_into().unwrap())
+        }
+
+        pub fn duplicate(&self, val0 : [bool; 64], lane_bytes : u8) -> [bool; 64] {
+            // Broadcast the low lane_bytes of val0 to every lane
+            let lane_bits = lane_bytes as usize * 8;
+            let mut result = [false; 64];
+            for low in (0..64).step_by(lane_bits) {
+                result[low..low + lane_bits].copy_from_slice(&val0[0..lane_bits]);
+            }
+            result
+        }
+
+        pub fn reduce(&self, val0 : [bool; 64], lane_bytes : u8) -> [bool; 64] {
+            // Horizontal sum of the lanes, each sign extended to 64 bits first
+            let lane_bits = lane_bytes as usize * 8;
+            let mut sum = [false; 64];
+            for low in (0..64).step_by(lane_bits) {
+                let lane = Converter::sign_extend(val0[low..low + lane_bits].to_vec(), 64).try_into().unwrap();
+                sum = self.adder_subtractor_64bit.value(sum, lane, true).0;
+            }
+            sum
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const LANE_BYTES : [u8; 4] = [1, 2, 4, 8];
+
+        fn bits(value : u64) -> [bool; 64] { Converter::dec_to_bin_pos_only(value, 64).try_into().unwrap() }
+        fn value(bits : [bool; 64]) -> u64 { Converter::bin_to_dec_pos_only(bits.to_vec()) }
+
+        fn lanes(value : u64, lane_bytes : u8) -> Vec<u64> {
+            let lane_bits = lane_bytes as usize * 8;
+            (0..64).step_by(lane_bits).map(|low| value >> low & u64::MAX >> (64 - lane_bits)).collect()
+        }
+
+        fn pack(lanes : &[u64], lane_bytes : u8) -> u64 {
+            lanes.iter().enumerate().fold(0, |value, (index, lane)| value | lane << (index * lane_bytes as usize * 8))
+        }
+
+        fn signed(lane : u64, lane_bytes : u8) -> i128 {
+            let lane_bits = lane_bytes as u32 * 8;
+            (lane as i128) << (128 - lane_bits) >> (128 - lane_bits)
+        }
+
+        fn reference(a : u64, b : u64, lane_bytes : u8, add : bool, saturation : Saturation) -> u64 {
+            // Each lane's exact sum or difference, then wrapped or clamped to the lane
+            let lane_bits = lane_bytes as u32 * 8;
+            let mask = u64::MAX >> (64 - lane_bits);
+            let results : Vec<u64> = lanes(a, lane_bytes).iter().zip(lanes(b, lane_bytes)).map(|(x, y)| {
+                let (x, y) = match saturation {
+                    Saturation::Signed => (signed(*x, lane_bytes), signed(y, lane_bytes)),
+                    _ => (*x as i128, y as i128)
+                };
+                let exact = if add { x + y } else { x - y };
+                let (min, max) = match saturation {
+                    Saturation::Wrap => return exact as u64 & mask,
+                    Saturation::Signed => (-(1i128 << (lane_bits - 1)), (1i128 << (lane_bits - 1)) - 1),
+                    Saturation::Unsigned => (0, mask as i128)
+                };
+                exact.clamp(min, max) as u64 & mask
+            }).collect();
+            pack(&results, lane_bytes)
+        }
+
+        fn samples() -> Vec<u64> {
+            let mut values = vec![0, u64::MAX, 0x7F7F_7F7F_7F7F_7F7F, 0x8080_8080_8080_8080, 0x7FFF_8000_7FFF_8000, 0x0001_FFFF_0001_FFFF];
+            let mut value : u64 = 99;
+            for _ in 0..20 {
+                value = value.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                values.push(value);
+            }
+            values
+        }
+
+        #[test]
+        fn add_sub_matches_reference() {
+            let simd = Simd::default();
+            let samples = samples();
+            for (a, b) in samples.iter().zip(samples.iter().rev()) {
+                for lane_bytes in LANE_BYTES {
+                    for saturation in [Saturation::Wrap, Saturation::Signed, Saturation::Unsigned] {
+                        for add in [true, false] {
+                            let result = value(simd.add_sub(bits(*a), bits(*b), lane_bytes, add, saturation));
+                            assert_eq!(result, reference(*a, *b, lane_bytes, add, saturation), "{0:016X} {1:016X} lanes {2} add {3}", a, b, lane_bytes, add);
+                        }
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn saturates_to_limits() {
+            // Every lane at its most positive, most negative, all ones or zero, pushed past the limit by 1
+            let simd = Simd::default();
+            for lane_bytes in LANE_BYTES {
+                let count = 8 / lane_bytes as usize;
+                let lane_bits = lane_bytes as u32 * 8;
+                let (max, min, ones) = (pack(&vec![(1u64 << (lane_bits - 1)) - 1; count], lane_bytes), pack(&vec![1u64 << (lane_bits - 1); count], lane_bytes), u64::MAX);
+                let one = pack(&vec![1; count], lane_bytes);
+                let add_sub = |a : u64, add : bool, saturation : Saturation| value(simd.add_sub(bits(a), bits(one), lane_bytes, add, saturation));
+                assert_eq!(add_sub(max, true, Saturation::Signed), max);
+                assert_eq!(add_sub(min, false, Saturation::Signed), min);
+                assert_eq!(add_sub(ones, true, Saturation::Unsigned), ones);
+                assert_eq!(add_sub(0, false, Saturation::Unsigned), 0);
+                assert_eq!(add_sub(max, true, Saturation::Wrap), min);
+                assert_eq!(add_sub(0, false, Saturation::Wrap), ones);
+            }
+        }
+
+        #[test]
+        fn mul_duplicate_reduce() {
+            let simd = Simd::default();
+            let samples = samples();
+            for (a, b) in samples.iter().zip(samples.iter().skip(1)) {
+                for lane_bytes in LANE_BYTES {
+                    let mask = u64::MAX >> (64 - lane_bytes as u32 * 8);
+                    let products : Vec<u64> = lanes(*a, lane_bytes).iter().zip(lanes(*b, lane_bytes)).map(|(x, y)| x.wrapping_mul(y) & mask).collect();
+                    assert_eq!(value(simd.mul(bits(*a), bits(*b), lane_bytes)), pack(&products, lane_bytes));
+                    assert_eq!(value(simd.duplicate(bits(*a), lane_bytes)), pack(&vec![*a & mask; 8 / lane_bytes as usize], lane_bytes));
+                    let sum : i128 = lanes(*a, lane_bytes).iter().map(|lane| signed(*lane, lane_bytes)).sum();
+                    assert_eq!(value(simd.reduce(bits(*a), lane_bytes)), sum as u64);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,123 @@
+pub(crate) mod system_regs{
+    use num_derive::FromPrimitive;
+    use crate::converter::converter::Converter;
+    use crate::reg64::reg64::Reg64;
+
+    #[repr(u8)]
+    #[derive(Clone, Copy, PartialEq)]
+    pub(crate) enum PrivilegeLevel {
+        // The CPU starts in Supervisor mode, and drops to User mode through SRET
+        Supervisor = 0,
+        User = 1
+    }
+
+    #[repr(u8)]
+    #[derive(Clone, Copy, PartialEq, FromPrimitive)]
+    pub(crate) enum SystemRegister {
+        // System register enumeration - read with MFS, written with MTS (both Supervisor only)
+        ELR = 0,    // Exception Link Register - address SRET returns to
+        VEC = 1,    // Trap vector - address jumped to on any trap
+        CAUSE = 2,  // Cause of the most recent trap (see TrapCause)
+        TVAL = 3,   // Trap value - syscall number, faulting address or faulting instruction
+        STATUS = 4, // Bit 0: interrupt enable, Bit 1: previous interrupt enable, Bit 2: previous mode was User
+        UBASE = 5,  // Lowest address accessible in User mode
+        ULIMIT = 6, // First address above the User mode region
+        OTH = 15
+    }
+
+    #[repr(u8)]
+    #[derive(Clone, Copy, PartialEq)]
+    pub(crate) enum TrapCause {
+        Syscall = 0,
+        PrivilegedInstruction = 1,
+        AccessFault = 2,
+        Interrupt = 3,
+        MachineCheck = 4,
+        MisalignedAccess = 5
+    }
+
+    pub(crate) struct SystemRegs {
+        // Registers used to handle traps between User and Supervisor mode
+        pub(crate) exception_link : Reg64,
+        pub(crate) trap_vector : Reg64,
+        pub(crate) trap_cause : Reg64,
+        pub(crate) trap_value : Reg64,
+        pub(crate) user_base : Reg64,
+        pub(crate) user_limit : Reg64,
+        pub(crate) interrupt_enable : bool,
+        pub(crate) saved_interrupt_enable : bool,
+        pub(crate) saved_privilege : PrivilegeLevel
+    }
+
+    impl SystemRegs {
+
+        pub fn get_register(name : &str) -> Option<SystemRegister> {
+            match name {
+                "ELR" => Some(SystemRegister::ELR),
+                "VEC" => Some(SystemRegister::VEC),
+                "CAUSE" => Some(SystemRegister::CAUSE),
+                "TVAL" => Some(SystemRegister::TVAL),
+                "STATUS" => Some(SystemRegister::STATUS),
+                "UBASE" => Some(SystemRegister::UBASE),
+                "ULIMIT" => Some(SystemRegister::ULIMIT),
+                _ => None
+            }
+        }
+
+        pub fn get_data(&self, reg : SystemRegister) -> [bool; 64] {
+            match reg {
+                SystemRegister::ELR => self.exception_link.get_data(),
+                SystemRegister::VEC => self.trap_vector.get_data(),
+                SystemRegister::CAUSE => self.trap_cause.get_data(),
+                SystemRegister::TVAL => self.trap_value.get_data(),
+                SystemRegister::STATUS => {
+                    let mut status = [false; 64];
+                    status[0] = self.interrupt_enable;
+                    status[1] = self.saved_interrupt_enable;
+                    status[2] = self.saved_privilege == PrivilegeLevel::User;
+                    status
+                },
+                SystemRegister::UBASE => self.user_base.get_data(),
+                SystemRegister::ULIMIT => self.user_limit.get_data(),
+                SystemRegister::OTH => [false; 64]
+            }
+        }
+
+        pub fn set_data(&mut self, reg : SystemRegister, data : [bool; 64]) {
+            match reg {
+                SystemRegister::ELR => self.exception_link.set_data(data),
+                SystemRegister::VEC => self.trap_vector.set_data(data),
+                SystemRegister::CAUSE => self.trap_cause.set_data(data),
+                SystemRegister::TVAL => self.trap_value.set_data(data),
+                SystemRegister::STATUS => {
+                    self.interrupt_enable = data[0];
+                    self.saved_interrupt_enable = data[1];
+                    self.saved_privilege = if data[2] { PrivilegeLevel::User } else { PrivilegeLevel::Supervisor };
+                },
+                SystemRegister::UBASE => self.user_base.set_data(data),
+                SystemRegister::ULIMIT => self.user_limit.set_data(data),
+                SystemRegister::OTH => {}
+            }
+        }
+
+        pub fn user_access_allowed(&self, addr : [bool; 48]) -> bool {
+            // User mode may only touch addresses in [UBASE, ULIMIT)
+            let addr_dec = Converter::bin_to_dec_pos_only(addr.to_vec());
+            let base = Converter::bin_to_dec_pos_only(self.user_base.get_data().to_vec());
+            let limit = Converter::bin_to_dec_pos_only(self.user_limit.get_data().to_vec());
+            base <= addr_dec && addr_dec < limit
+        }
+    }
+
+    impl Default for SystemRegs {
+        fn default() -> Self {
+            SystemRegs {
+                exception_link: Reg64::default(), trap_vector: Reg64::default(),
+                trap_cause: Reg64::default(), trap_value: Reg64::default(),
+                user_base: Reg64::default(), user_limit: Reg64::default(),
+                interrupt_enable: false, saved_interrupt_enable: false,
+                saved_privilege: PrivilegeLevel::Supervisor
+            }
+        }
+    }
+}
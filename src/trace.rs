@@ -0,0 +1,253 @@
+pub(crate) mod trace{
+    /*
+     * Execution trace - one record per retired instruction
+     *
+     * TEXT
+     *   <cycle> <pc> <raw word> <disassembly> fetch=<L1|L2|MEM> [regs=R1=5,R14=64,F2=1.5] [mem=<R|W>:<addr>:<L1|L2|MEM>] flags=Z0,N0,V0,C0
+     * JSON (one object per line)
+     *   {"cycle":27,"pc":"0x000000000200","raw":"0x0000000004000312","instr":"SUB R1, R1, #1","fetch":"L1",
+     *    "regs":{"R1":9},"mem":[{"access":"R","addr":"0x000000000C00","level":"MEM"}],"flags":{"z":false,"n":false,"v":false,"c":false}}
+     *
+     * Registers are the instruction's destination, plus any other register whose value changed (e.g. R14 on a branch)
+     * Float registers are shown as doubles - in JSON, infinities and NaNs are the strings "inf", "-inf" and "NaN"
+     * Vector registers are shown as a hex word, e.g. V1=0x0001000200030004 - a string in JSON
+     * Cycle is the cycle the instruction retired on
+     * An instruction that faults (e.g. an access fault or a privileged instruction in User mode) never retires, so isn't recorded
+     */
+    use crate::assembler::assembler::InstrType;
+    use crate::control_unit::control_unit::ControlUnit;
+    use crate::converter::converter::Converter;
+    use crate::disassembler::disassembler::Disassembler;
+    use crate::output_sink::output_sink::OutputSink;
+
+    #[derive(Clone, Copy, PartialEq)]
+    pub(crate) enum TraceFormat {
+        Text,
+        Json
+    }
+
+    pub(crate) struct TraceFilter {
+        // Only instructions matching every filter are recorded
+        pub(crate) addr_range : Option<(u64, u64)>, // [start, end)
+        pub(crate) mnemonics : Vec<String> // e.g. 'LDR', 'BEQ' - empty to record everything
+    }
+
+    struct PendingInstr {
+        // State captured when the instruction was fetched
+        pc : u64,
+        registers : [i64; 15],
+        float_registers : [u64; 16],
+        vector_registers : [u64; 16],
+        fetch_level : i32
+    }
+
+    pub(crate) struct Tracer {
+        output : Box<dyn OutputSink>,
+        format : TraceFormat,
+        filter : TraceFilter,
+        pending : Option<PendingInstr>
+    }
+
+    impl Tracer {
+
+        pub fn new(output : Box<dyn OutputSink>, format : TraceFormat, filter : TraceFilter) -> Tracer {
+            Tracer { output, format, filter, pending: None }
+        }
+
+        fn registers(ctrl : &mut ControlUnit) -> [i64; 15] {
+            let mut values = [0; 15];
+            for (reg, value) in values.iter_mut().enumerate() {
+                let reg_index : [bool; 4] = Converter::dec_to_bin_pos_only(reg as u64, 4).try_into().unwrap();
+                *value = Converter::bin_to_dec_2s_comp(ctrl.register_bank.get_data(reg_index).to_vec());
+            }
+            values
+        }
+
+        fn float_registers(ctrl : &ControlUnit) -> [u64; 16] {
+            ctrl.fpu.registers.map(|reg| Converter::bin_to_dec_pos_only(reg.get_data().to_vec()))
+        }
+
+        fn vector_registers(ctrl : &ControlUnit) -> [u64; 16] {
+            ctrl.simd.registers.map(|reg| Converter::bin_to_dec_pos_only(reg.get_data().to_vec()))
+        }
+
+        fn float_json(value : f64) -> String {
+            if value.is_finite() { format!("{:?}", value) } else { format!("\"{0}\"", value) }
+        }
+
+        pub fn begin(&mut self, ctrl : &mut ControlUnit){
+            // Called before a Fetch cycle - replaces anything pending, e.g. if the fetch was interrupted
+            self.pending = Some(PendingInstr {
+                pc: Converter::bin_to_dec_pos_only(ctrl.pc.get_data().to_vec()),
+                registers: Self::registers(ctrl),
+                float_registers: Self::float_registers(ctrl),
+                vector_registers: Self::vector_registers(ctrl),
+                fetch_level: -1
+            });
+        }
+
+        pub fn fetched(&mut self, ctrl : &ControlUnit){
+            // Called after the Fetch cycle
+            if let Some(pending) = self.pending.as_mut() {
+                pending.fetch_level = ctrl.data_access_manager.last_access_level;
+            }
+        }
+
+        pub fn retire(&mut self, ctrl : &mut ControlUnit, cycle : u64){
+            let pending = match self.pending.take() {
+                Some(pending) => pending,
+                None => return
+            };
+            if ctrl.faulted { return; }
+            let raw = ctrl.memory_instr_reg.get_data();
+            if !raw.contains(&true) { return; } // Empty words are skipped, not executed
+            let instr = Disassembler::disassemble(raw);
+            if let Some((start, end)) = self.filter.addr_range && !(start <= pending.pc && pending.pc < end) { return; }
+            let mnemonic = instr.split(' ').next().unwrap_or("").to_string();
+            if !self.filter.mnemonics.is_empty() && !self.filter.mnemonics.contains(&mnemonic) { return; }
+
+            let after = Self::registers(ctrl);
+            let destination = match ctrl.decoded_instruction.instr_type {
+                InstrType::ADD | InstrType::SUB | InstrType::MULT | InstrType::AND | InstrType::OR | InstrType::XOR | InstrType::NOT | InstrType::FLIP |
+                InstrType::LDR | InstrType::MFS | InstrType::IN | InstrType::MOVZ | InstrType::MOVK | InstrType::MOVN | InstrType::ADC | InstrType::SBC |
+                InstrType::FTOI | InstrType::FRSR | InstrType::VADDV => Converter::bin_to_dec_pos_only(ctrl.decoded_instruction.return_register.to_vec()) as usize,
+                InstrType::FMOV | InstrType::VMOV if ctrl.decoded_instruction.reg_1 => Converter::bin_to_dec_pos_only(ctrl.decoded_instruction.return_register.to_vec()) as usize,
+                _ => 0 // R0 is never written
+            };
+            let float_destination = match ctrl.decoded_instruction.instr_type {
+                InstrType::FADD | InstrType::FSUB | InstrType::FMUL | InstrType::FDIV | InstrType::FSQRT | InstrType::FMOVI | InstrType::ITOF =>
+                    Some(Converter::bin_to_dec_pos_only(ctrl.decoded_instruction.return_register.to_vec()) as usize),
+                InstrType::FMOV if !ctrl.decoded_instruction.reg_1 => Some(Converter::bin_to_dec_pos_only(ctrl.decoded_instruction.return_register.to_vec()) as usize),
+                _ => None
+            };
+            let vector_destination = match ctrl.decoded_instruction.instr_type {
+                InstrType::VADD | InstrType::VSUB | InstrType::VMUL | InstrType::VQADD | InstrType::VQSUB | InstrType::VUQADD | InstrType::VUQSUB |
+                InstrType::VAND | InstrType::VOR | InstrType::VXOR | InstrType::VDUP | InstrType::VLDR =>
+                    Some(Converter::bin_to_dec_pos_only(ctrl.decoded_instruction.return_register.to_vec()) as usize),
+                InstrType::VMOV if !ctrl.decoded_instruction.reg_1 => Some(Converter::bin_to_dec_pos_only(ctrl.decoded_instruction.return_register.to_vec()) as usize),
+                _ => None
+            };
+            let float_after = Self::float_registers(ctrl);
+            let vector_after = Self::vector_registers(ctrl);
+            // (name, text value, JSON value) of each register written
+            let mut regs : Vec<(String, String, String)> = (1..15).filter(|reg| *reg == destination || after[*reg] != pending.registers[*reg])
+                .map(|reg| (format!("R{0}", reg), after[reg].to_string(), after[reg].to_string())).collect();
+            for reg in (0..16).filter(|reg| Some(*reg) == float_destination || float_after[*reg] != pending.float_registers[*reg]) {
+                let value = f64::from_bits(float_after[reg]);
+                regs.push((format!("F{0}", reg), format!("{:?}", value), Self::float_json(value)));
+            }
+            for reg in (0..16).filter(|reg| Some(*reg) == vector_destination || vector_after[*reg] != pending.vector_registers[*reg]) {
+                let value = format!("0x{:016X}", vector_after[reg]);
+                regs.push((format!("V{0}", reg), value.clone(), format!("\"{0}\"", value)));
+            }
+            let mem = match ctrl.decoded_instruction.instr_type {
+                InstrType::LDR | InstrType::VLDR => Some(("R", Converter::bin_to_dec_pos_only(ctrl.decoded_instruction.addr.to_vec()), ctrl.data_access_manager.last_access_level)),
+                InstrType::STR | InstrType::VSTR => Some(("W", Converter::bin_to_dec_pos_only(ctrl.decoded_instruction.addr.to_vec()), ctrl.data_access_manager.last_access_level)),
+                _ => None
+            };
+            let raw_dec = Converter::bin_to_dec_pos_only(raw.to_vec());
+            let (z, n, v, c) = (ctrl.alu.z, ctrl.alu.n, ctrl.alu.v, ctrl.alu.c);
+
+            let line = match self.format {
+                TraceFormat::Text => {
+                    let mut line = format!("{0:>8} {1:012X} {2:016X} {3:<24} fetch={4}", cycle, pending.pc, raw_dec, instr, Self::level_name(pending.fetch_level));
+                    if !regs.is_empty() {
+                        let written : Vec<String> = regs.iter().map(|(reg, value, _)| format!("{0}={1}", reg, value)).collect();
+                        line += &format!(" regs={0}", written.join(","));
+                    }
+                    if let Some((access, addr, level)) = mem {
+                        line += &format!(" mem={0}:{1:012X}:{2}", access, addr, Self::level_name(level));
+                    }
+                    line + &format!(" flags=Z{0},N{1},V{2},C{3}\n", z as u8, n as u8, v as u8, c as u8)
+                }
+                TraceFormat::Json => {
+                    let written : Vec<String> = regs.iter().map(|(reg, _, value)| format!("\"{0}\":{1}", reg, value)).collect();
+                    let accesses = match mem {
+                        Some((access, addr, level)) => format!("{{\"access\":\"{0}\",\"addr\":\"0x{1:012X}\",\"level\":\"{2}\"}}", access, addr, Self::level_name(level)),
+                        None => "".to_string()
+                    };
+                    format!("{{\"cycle\":{0},\"pc\":\"0x{1:012X}\",\"raw\":\"0x{2:016X}\",\"instr\":\"{3}\",\"fetch\":\"{4}\",\"regs\":{{{5}}},\"mem\":[{6}],\"flags\":{{\"z\":{7},\"n\":{8},\"v\":{9},\"c\":{10}}}}}\n",
+                        cycle, pending.pc, raw_dec, Self::json_escape(&instr), Self::level_name(pending.fetch_level), written.join(","), accesses, z, n, v, c)
+                }
+            };
+            self.output.write_str(&line);
+        }
+
+        pub fn flush(&mut self){
+            self.output.flush();
+        }
+
+        fn json_escape(text : &str) -> String {
+            text.replace('\\', "\\\\").replace('"', "\\\"")
+        }
+
+        fn level_name(level : i32) -> &'static str {
+            match level {
+                1 => "L1",
+                2 => "L2",
+                _ => "MEM" // Main memory, or a device
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::RunOptions;
+        use crate::output_sink::output_sink::BufferSink;
+        use crate::tests::{load_program, program_file};
+
+        fn trace(name : &str, lines : &[&str], format : TraceFormat) -> String {
+            let path = program_file(name, lines);
+            let (mut clk, _) = load_program(&path, RunOptions::default());
+            std::fs::remove_file(&path).ok();
+            let output = BufferSink::default();
+            clk.tracer = Some(Tracer::new(Box::new(output.clone()), format, TraceFilter { addr_range: None, mnemonics: vec![] }));
+            clk.start();
+            output.contents()
+        }
+
+        #[test]
+        fn faulting_instruction_not_retired() {
+            // Drops to User mode at 0x240, where MTS is a privileged instruction - the handler at 0x2C0 halts
+            let trace = trace("trace_fault", &[
+                "ADD R1, #0, #704", "MTS VEC, R1", "ADD R1, #0, #2048", "MTS ULIMIT, R1",
+                "ADD R1, #0, #576", "MTS ELR, R1", "ADD R1, #0, #4", "MTS STATUS, R1", "SRET",
+                "MTS VEC, R1", "HLT", "HLT"
+            ], TraceFormat::Text);
+            let pcs : Vec<&str> = trace.lines().map(|line| line.split_whitespace().nth(1).unwrap()).collect();
+            assert!(!pcs.contains(&"000000000240"), "{}", trace);
+            assert_eq!(pcs.last(), Some(&"0000000002C0"));
+        }
+
+        #[test]
+        fn float_registers_written() {
+            let lines = ["FMOV F1, #1.5", "FADD F2, F1, F1", "FDIV F3, F0, F0", "HLT"];
+            let text : Vec<String> = trace("trace_float", &lines, TraceFormat::Text).lines().map(|line| line.to_string()).collect();
+            assert!(text[0].contains(" regs=F1=1.5 "), "{}", text[0]);
+            assert!(text[1].contains(" regs=F2=3.0 "), "{}", text[1]);
+            assert!(text[2].contains(" regs=F3=NaN "), "{}", text[2]); // 0 / 0
+            let json : Vec<String> = trace("trace_float_json", &lines, TraceFormat::Json).lines().map(|line| line.to_string()).collect();
+            assert!(json[1].contains("\"regs\":{\"F2\":3.0}"), "{}", json[1]);
+            assert!(json[2].contains("\"regs\":{\"F3\":\"NaN\"}"), "{}", json[2]);
+        }
+
+        #[test]
+        fn vector_registers_written() {
+            let lines = ["ADD R1, #0, #3", "VDUP.H V1, R1", "VADD.H V2, V1, V1", "VSTR V2, &000000000800", "VLDR V3, &000000000800", "VMOV R2, V3", "HLT"];
+            let text : Vec<String> = trace("trace_vector", &lines, TraceFormat::Text).lines().map(|line| line.to_string()).collect();
+            assert!(text[1].contains(" regs=V1=0x0003000300030003 "), "{}", text[1]);
+            assert!(text[2].contains(" regs=V2=0x0006000600060006 "), "{}", text[2]);
+            assert!(!text[3].contains(" regs="), "{}", text[3]); // VSTR writes no register
+            assert!(text[4].contains(" regs=V3=0x0006000600060006 "), "{}", text[4]);
+            assert!(text[5].contains(" regs=R2=1688875630460934 "), "{}", text[5]);
+            let json : Vec<String> = trace("trace_vector_json", &lines, TraceFormat::Json).lines().map(|line| line.to_string()).collect();
+            assert!(json[2].contains("\"regs\":{\"V2\":\"0x0006000600060006\"}"), "{}", json[2]);
+        }
+
+        #[test]
+        fn json_escapes_instr() {
+            assert_eq!(Tracer::json_escape("OUT A \"\\\""), "OUT A \\\"\\\\\\\"");
+        }
+    }
+}